@@ -0,0 +1,104 @@
+import GObject from 'gi://GObject';
+import Gio from 'gi://Gio';
+import St from 'gi://St';
+import Clutter from 'gi://Clutter';
+
+import {Extension} from 'resource:///org/gnome/shell/extensions/extension.js';
+import * as Main from 'resource:///org/gnome/shell/ui/main.js';
+import * as PanelMenu from 'resource:///org/gnome/shell/ui/panelMenu.js';
+import * as PopupMenu from 'resource:///org/gnome/shell/ui/popupMenu.js';
+
+// Must match BUS_NAME and OBJECT_PATH in src/dbus.rs
+const BUS_NAME = 'io.github.dreygur.BatteryMonitor';
+const OBJECT_PATH = '/io/github/dreygur/BatteryMonitor';
+const INTERFACE_XML = `
+<node>
+  <interface name="io.github.dreygur.BatteryMonitor1">
+    <method name="GetDevices">
+      <arg type="s" direction="out"/>
+    </method>
+    <property name="StatusText" type="s" access="read"/>
+    <signal name="DevicesChanged">
+      <arg type="s" name="devices"/>
+    </signal>
+  </interface>
+</node>`;
+const MonitorProxy = Gio.DBusProxy.makeProxyWrapper(INTERFACE_XML);
+
+const BatteryIndicator = GObject.registerClass(
+class BatteryIndicator extends PanelMenu.Button {
+    _init() {
+        super._init(0.0, 'Bluetooth Battery Monitor');
+
+        this._label = new St.Label({
+            text: '',
+            y_align: Clutter.ActorAlign.CENTER,
+        });
+        this.add_child(this._label);
+
+        this._watchId = Gio.bus_watch_name(
+            Gio.BusType.SESSION, BUS_NAME, Gio.BusNameWatcherFlags.NONE,
+            () => this._connect(),
+            () => this._disconnect());
+    }
+
+    _connect() {
+        this._proxy = new MonitorProxy(Gio.DBus.session, BUS_NAME, OBJECT_PATH);
+        this._signalId = this._proxy.connectSignal('DevicesChanged',
+            (_proxy, _sender, [devices]) => this._showDevices(devices));
+        this._proxy.GetDevicesRemote(([devices]) => this._showDevices(devices));
+    }
+
+    _disconnect() {
+        if (this._proxy && this._signalId)
+            this._proxy.disconnectSignal(this._signalId);
+        this._proxy = null;
+        this._signalId = null;
+        this._showDevices('[]');
+    }
+
+    _showDevices(json) {
+        let devices = [];
+        try {
+            devices = JSON.parse(json);
+        } catch (e) {
+            logError(e, 'Bluetooth Battery Monitor: bad device list');
+        }
+
+        // One short entry per device in the bar, the full lines in the menu
+        this._label.text = devices
+            .filter(d => d.battery_percentage !== null)
+            .map(d => `${d.icon} ${d.battery_percentage}%`)
+            .join('  ');
+        this.visible = devices.length > 0;
+
+        this.menu.removeAll();
+        for (const device of devices) {
+            const level = device.battery_percentage !== null ? `: ${device.battery_percentage}%` : '';
+            const item = new PopupMenu.PopupMenuItem(`${device.icon} ${device.name}${level}`);
+            item.setSensitive(false);
+            this.menu.addMenuItem(item);
+        }
+    }
+
+    destroy() {
+        if (this._watchId) {
+            Gio.bus_unwatch_name(this._watchId);
+            this._watchId = null;
+        }
+        this._disconnect();
+        super.destroy();
+    }
+});
+
+export default class BatteryMonitorExtension extends Extension {
+    enable() {
+        this._indicator = new BatteryIndicator();
+        Main.panel.addToStatusArea(this.uuid, this._indicator);
+    }
+
+    disable() {
+        this._indicator?.destroy();
+        this._indicator = null;
+    }
+}
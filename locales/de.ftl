@@ -0,0 +1,49 @@
+# Battery alerts
+low-battery-title = Akku schwach
+critical-battery-title = Akku kritisch
+battery-recovered-title = Akku wieder geladen
+battery-alert-body = { $icon } { $device } ist bei { $level } %
+digest-title = { $count ->
+    [one] 1 Gerät fast leer
+   *[other] { $count } Geräte fast leer
+}
+
+# Notification buttons
+action-open-details = Details öffnen
+action-snooze = 1 Std. schlummern
+action-dismiss = Für dieses Gerät ausblenden
+
+# Tray icon and menu
+app-name = Akkumonitor
+tray-title-level = Akkumonitor: { $level } %
+bluetooth-unavailable = Bluetooth nicht verfügbar
+scanners-failing = Scans schlagen wiederholt fehl: { $scanners }
+no-devices = Keine Geräte verbunden
+menu-connect = Verbinden
+menu-details = Details
+menu-quit = Beenden
+menu-snooze = 1 Stunde schlummern
+menu-mute = Benachrichtigungen stummschalten
+menu-unmute = Stummschaltung aufheben
+menu-disconnect = Trennen
+menu-forget = Kopplung entfernen
+
+# `test-notification`
+test-device-name = Testgerät
+
+# Details notification
+device-status-title = Akkustand der Geräte
+device-stale = (zuletzt bekannt)
+charge-cycles = { $cycles ->
+    [one] 1 Ladezyklus
+   *[other] { $cycles } Ladezyklen
+}
+
+# Summary shown on unlock
+unlock-summary-title = Während der Bildschirm gesperrt war
+unlock-summary-connected = Verbunden: { $devices }
+unlock-summary-disconnected = Getrennt: { $devices }
+
+# Shown once when HID devices can't be opened
+hid-access-title = Einige Akkustände sind nicht lesbar
+hid-access-body = Keine Berechtigung für { $devices }. `battery_percentage doctor` zeigt die udev-Regel, die das behebt; danach das Gerät neu einstecken.
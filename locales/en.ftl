@@ -0,0 +1,49 @@
+# Battery alerts
+low-battery-title = Low Battery
+critical-battery-title = Critical Battery
+battery-recovered-title = Battery Recovered
+battery-alert-body = { $icon } { $device } is at { $level }%
+digest-title = { $count ->
+    [one] 1 device low
+   *[other] { $count } devices low
+}
+
+# Notification buttons
+action-open-details = Open details
+action-snooze = Snooze 1h
+action-dismiss = Dismiss for this device
+
+# Tray icon and menu
+app-name = Battery Monitor
+tray-title-level = Battery Monitor: { $level }%
+bluetooth-unavailable = Bluetooth unavailable
+scanners-failing = Scans keep failing: { $scanners }
+no-devices = No devices connected
+menu-connect = Connect
+menu-details = Details
+menu-quit = Quit
+menu-snooze = Snooze for 1 hour
+menu-mute = Mute notifications
+menu-unmute = Unmute
+menu-disconnect = Disconnect
+menu-forget = Remove pairing
+
+# `test-notification`
+test-device-name = Test device
+
+# Details notification
+device-status-title = Device Battery Status
+device-stale = (last known)
+charge-cycles = { $cycles ->
+    [one] 1 charge cycle
+   *[other] { $cycles } charge cycles
+}
+
+# Summary shown on unlock
+unlock-summary-title = While the screen was locked
+unlock-summary-connected = Connected: { $devices }
+unlock-summary-disconnected = Disconnected: { $devices }
+
+# Shown once when HID devices can't be opened
+hid-access-title = Can't read some battery levels
+hid-access-body = No permission to open { $devices }. Run `battery_percentage doctor` for the udev rule that fixes this, then replug.
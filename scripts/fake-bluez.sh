@@ -0,0 +1,33 @@
+#!/bin/bash
+
+# Run the monitor against fake BlueZ devices on a private D-Bus bus, so it can
+# be tried out end to end without Bluetooth hardware. Type commands such as
+# "battery F0:11:22:33:44:55 9" or "disconnect AC:80:0A:12:34:56" to change
+# the devices while it runs; see src/bin/fake_bluez.rs for the full list.
+#
+# Usage: scripts/fake-bluez.sh [devices.toml] [monitor arguments...]
+
+set -e
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+PROJECT_ROOT="$(cd "$SCRIPT_DIR/.." && pwd)"
+DEVICES_FILE="${1:-$SCRIPT_DIR/fake-bluez-devices.toml}"
+shift || true
+
+cd "$PROJECT_ROOT"
+cargo build --bin battery_percentage --bin fake_bluez
+
+# A throwaway bus; the monitor and fake BlueZ both treat it as the system bus
+BUS_INFO="$(dbus-daemon --session --fork --print-address=1 --print-pid=1)"
+BUS_ADDRESS="$(echo "$BUS_INFO" | sed -n 1p)"
+BUS_PID="$(echo "$BUS_INFO" | sed -n 2p)"
+trap 'kill $MONITOR_PID $BUS_PID 2>/dev/null' EXIT
+
+export DBUS_SYSTEM_BUS_ADDRESS="$BUS_ADDRESS"
+echo "Private bus at $BUS_ADDRESS"
+
+# Give fake BlueZ a head start so the monitor finds the adapter
+(sleep 1; exec ./target/debug/battery_percentage --status-file /tmp/fake-bluez-status "$@") &
+MONITOR_PID=$!
+
+./target/debug/fake_bluez "$DEVICES_FILE"
@@ -3,7 +3,7 @@
 # GNOME Shell Extension Integration Script for Battery Status
 # This script creates a simple indicator in the GNOME top bar
 
-INDICATOR_FILE="/tmp/bluetooth-battery-status"
+EXTENSION_UUID="battery-monitor@dreygur.github.io"
 DESKTOP_FILE="$HOME/.local/share/applications/bluetooth-battery-monitor.desktop"
 # Get the directory where this script is located
 SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
@@ -16,20 +16,25 @@ else
     echo "Error: Could not find Cargo.toml. Make sure you're in the project directory or scripts directory."
     exit 1
 fi
-BINARY_PATH="$PROJECT_ROOT/target/debug/bluetooth_only"
+BINARY_PATH="$PROJECT_ROOT/target/debug/battery_percentage"
 
 echo "Setting up GNOME integration for Bluetooth Battery Monitor..."
 
-# Build the bluetooth_only binary first
-echo "Building bluetooth_only binary..."
+# Build the monitor first
+echo "Building battery_percentage binary..."
 cd "$PROJECT_ROOT"
-cargo build --bin bluetooth_only
+cargo build --bin battery_percentage
 
 if [ ! -f "$BINARY_PATH" ]; then
-    echo "Error: Failed to build bluetooth_only binary"
+    echo "Error: Failed to build battery_percentage binary"
     exit 1
 fi
 
+# Install the top bar extension, which reads device state from the monitor over D-Bus
+EXTENSION_DIR="$HOME/.local/share/gnome-shell/extensions/$EXTENSION_UUID"
+mkdir -p "$EXTENSION_DIR"
+cp "$PROJECT_ROOT/gnome-extension/$EXTENSION_UUID/"* "$EXTENSION_DIR/"
+
 # Create desktop entry
 mkdir -p "$HOME/.local/share/applications"
 cat > "$DESKTOP_FILE" << EOF
@@ -67,18 +72,13 @@ EOF
 echo "GNOME integration files created!"
 echo ""
 echo "Next steps:"
-echo "1. Install a GNOME Shell extension to display custom text in the status bar:"
-echo "   - 'Executor' extension: https://extensions.gnome.org/extension/2932/executor/"
-echo "   - Or 'Generic Monitor' extension: https://extensions.gnome.org/extension/3968/generic-monitor/"
+echo "1. Enable the top bar extension (log out and back in first on Wayland):"
+echo "   gnome-extensions enable $EXTENSION_UUID"
 echo ""
 echo "2. Enable and start the background service:"
 echo "   systemctl --user daemon-reload"
 echo "   systemctl --user enable bluetooth-battery-monitor.service"
 echo "   systemctl --user start bluetooth-battery-monitor.service"
 echo ""
-echo "3. Configure the GNOME extension to read from: $INDICATOR_FILE"
-echo "   Command: cat $INDICATOR_FILE"
-echo "   Refresh interval: 30 seconds"
-echo ""
-echo "4. Check service status with:"
+echo "3. Check service status with:"
 echo "   systemctl --user status bluetooth-battery-monitor.service"
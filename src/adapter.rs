@@ -0,0 +1,106 @@
+use crate::bluetooth::gatt_battery_notifications;
+use bluer::{Adapter, AdapterEvent, Address, Device, DeviceEvent, DiscoveryFilter, DiscoveryTransport, SessionEvent};
+use futures::stream::SelectAll;
+use futures::{Stream, StreamExt};
+use std::collections::HashSet;
+use std::pin::Pin;
+use tracing::{info, warn};
+
+type DeviceEvents = SelectAll<Pin<Box<dyn Stream<Item = (Address, DeviceEvent)>>>>;
+type GattBatteryEvents = SelectAll<Pin<Box<dyn Stream<Item = (Address, u8)>>>>;
+pub type AdapterWatch = Pin<Box<dyn Stream<Item = SessionEvent>>>;
+
+// Something that happened on the adapter or one of its devices
+pub enum LinkEvent {
+    Adapter(AdapterEvent),
+    Device(Address, DeviceEvent),
+    // A level from a GATT Battery Level notification
+    Battery(Address, u8),
+    // The discovery stream ended, which means the adapter is gone
+    Lost,
+}
+
+// The BlueZ session and adapter the monitor works through, with the event
+// streams of every device being watched. It's thrown away when the adapter
+// is unplugged or bluetoothd goes away, and built again once it's back.
+pub struct BluetoothLink {
+    // Owns the D-Bus connection everything else goes through
+    _session: bluer::Session,
+    pub adapter: Adapter,
+    device_events: Pin<Box<dyn Stream<Item = AdapterEvent>>>,
+    change_events: DeviceEvents,
+    gatt_events: GattBatteryEvents,
+    // Devices with a property change stream in change_events
+    watched: HashSet<Address>,
+}
+
+impl BluetoothLink {
+    // Open a new session on the default adapter, power it on and start discovery
+    pub async fn connect() -> bluer::Result<Self> {
+        let session = bluer::Session::new().await?;
+        let adapter = session.default_adapter().await?;
+        adapter.set_powered(true).await?;
+
+        let filter = DiscoveryFilter {
+            transport: DiscoveryTransport::Auto,
+            ..Default::default()
+        };
+        adapter.set_discovery_filter(filter).await?;
+        let device_events = Box::pin(adapter.discover_devices().await?);
+        info!("Using Bluetooth adapter {}", adapter.name());
+
+        Ok(Self {
+            _session: session,
+            adapter,
+            device_events,
+            change_events: SelectAll::new(),
+            gatt_events: SelectAll::new(),
+            watched: HashSet::new(),
+        })
+    }
+
+    // Follow a connected device's property changes and battery notifications
+    pub async fn watch(&mut self, device: &Device, addr: Address) -> bluer::Result<()> {
+        if self.watched.insert(addr) {
+            let change_events = device.events().await?.map(move |evt| (addr, evt));
+            self.change_events.push(Box::pin(change_events));
+        }
+        self.subscribe_gatt_battery(device, addr).await;
+        Ok(())
+    }
+
+    // GATT services are usually resolved a little after connecting, so this
+    // is tried again when they are
+    pub async fn subscribe_gatt_battery(&mut self, device: &Device, addr: Address) {
+        match gatt_battery_notifications(device).await {
+            Ok(Some(levels)) => self.gatt_events.push(Box::pin(levels.map(move |level| (addr, level)))),
+            Ok(None) => {}
+            Err(e) => warn!("Failed to subscribe to GATT battery level for {}: {}", addr, e),
+        }
+    }
+
+    pub async fn next_event(&mut self) -> LinkEvent {
+        tokio::select! {
+            event = self.device_events.next() => match event {
+                Some(event) => LinkEvent::Adapter(event),
+                None => LinkEvent::Lost,
+            },
+            Some((addr, event)) = self.change_events.next() => LinkEvent::Device(addr, event),
+            Some((addr, level)) = self.gatt_events.next() => LinkEvent::Battery(addr, level),
+        }
+    }
+
+    // Whether BlueZ still answers for the adapter. Device lookups fail
+    // quietly once it's gone, so this is checked on each refresh.
+    pub async fn is_alive(&self) -> bool {
+        self.adapter.is_powered().await.is_ok()
+    }
+}
+
+// Adapters being added and removed, on a session of its own that outlives
+// any one BluetoothLink
+pub async fn watch_adapters() -> bluer::Result<(bluer::Session, AdapterWatch)> {
+    let session = bluer::Session::new().await?;
+    let events = Box::pin(session.events().await?);
+    Ok((session, events))
+}
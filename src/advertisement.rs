@@ -0,0 +1,80 @@
+use crate::scanner::DeviceScanner;
+use crate::status::{DeviceStatus, Transport};
+use bluer::{Adapter, Device, Session, Uuid};
+use futures::future::LocalBoxFuture;
+
+// Battery Service and Battery Level, either of which devices use as the
+// service data UUID when they broadcast their level
+const BATTERY_SERVICE_UUID: Uuid = Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb);
+const BATTERY_LEVEL_UUID: Uuid = Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb);
+
+// Battery levels from BLE advertisements, for beacons, remotes and sensors
+// that broadcast their level in service data but never connect. Reads what
+// BlueZ has cached from the monitor's ongoing discovery.
+#[derive(Default)]
+pub struct AdvertisementScanner {
+    // Opened on the first scan, since creating a session is async
+    adapter: Option<(Session, Adapter)>,
+}
+
+impl DeviceScanner for AdvertisementScanner {
+    fn name(&self) -> &'static str {
+        "ble_adverts"
+    }
+
+    fn scan(&mut self) -> LocalBoxFuture<'_, Result<Vec<DeviceStatus>, Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            if self.adapter.is_none() {
+                let session = Session::new().await?;
+                let adapter = session.default_adapter().await?;
+                self.adapter = Some((session, adapter));
+            }
+            let Some((_, adapter)) = &self.adapter else {
+                return Ok(Vec::new());
+            };
+
+            let mut devices = Vec::new();
+            for addr in adapter.device_addresses().await? {
+                let device = adapter.device(addr)?;
+                if let Some((battery, rssi)) = advertised_battery(&device).await? {
+                    devices.push(DeviceStatus {
+                        id: addr.to_string(),
+                        name: device.name().await?.unwrap_or_else(|| addr.to_string()),
+                        device_type: "Beacon".to_string(),
+                        transport: Transport::Bluetooth,
+                        extra_transports: Vec::new(),
+                        battery_percentage: Some(battery),
+                        batteries: Vec::new(),
+                        rssi: Some(rssi),
+                        icon: "📡".to_string(),
+                        stale: false,
+                    });
+                }
+            }
+
+            devices.sort_by(|a, b| a.id.cmp(&b.id));
+            Ok(devices)
+        })
+    }
+}
+
+// The level a nearby, unconnected device is broadcasting and its signal
+// strength. BlueZ keeps the last service data around after a device goes out
+// of range, but clears its RSSI, so only devices with an RSSI count as seen.
+async fn advertised_battery(device: &Device) -> bluer::Result<Option<(u8, i16)>> {
+    if device.is_connected().await? {
+        return Ok(None);
+    }
+    let Some(rssi) = device.rssi().await? else {
+        return Ok(None);
+    };
+
+    let Some(service_data) = device.service_data().await? else {
+        return Ok(None);
+    };
+    Ok([BATTERY_SERVICE_UUID, BATTERY_LEVEL_UUID]
+        .iter()
+        .find_map(|uuid| service_data.get(uuid)?.first().copied())
+        .filter(|level| *level <= 100)
+        .map(|level| (level, rssi)))
+}
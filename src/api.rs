@@ -0,0 +1,248 @@
+use crate::control::ControlRequest;
+use crate::status::DeviceStatus;
+use crate::thresholds::ThresholdCrossed;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+
+// Events buffered per client before a slow one starts missing some
+#[cfg(feature = "api")]
+const EVENT_BUFFER: usize = 64;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    // Serve the HTTP API (needs the `api` feature)
+    pub enabled: bool,
+    // There's no authentication, so keep this on localhost unless the
+    // network is trusted
+    pub listen: String,
+    // Access-Control-Allow-Origin sent with every response, for dashboards
+    // served from another origin
+    pub cors_origin: Option<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: "127.0.0.1:8733".to_string(),
+            cors_origin: None,
+        }
+    }
+}
+
+// Something pushed to clients on the event stream
+#[derive(Clone, Debug)]
+pub enum ApiEvent {
+    // The whole device list, after every status update
+    Devices(Vec<DeviceStatus>),
+    Threshold(ThresholdCrossed),
+}
+
+pub struct ApiServer {
+    events: broadcast::Sender<ApiEvent>,
+}
+
+impl ApiServer {
+    // Requests that need the monitor's state go through `control`, the same
+    // way control socket commands do
+    #[cfg(feature = "api")]
+    pub async fn start(config: &ApiConfig, control: mpsc::UnboundedSender<ControlRequest>) -> std::io::Result<Self> {
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let cors_origin = match config.cors_origin.as_deref().map(axum::http::HeaderValue::from_str).transpose() {
+            Ok(origin) => origin,
+            Err(e) => return Err(std::io::Error::other(format!("invalid cors_origin: {}", e))),
+        };
+        let state = server::ApiState {
+            control,
+            events: events.clone(),
+        };
+
+        let listener = tokio::net::TcpListener::bind(&config.listen).await?;
+        tracing::info!("Serving the HTTP API on http://{}", listener.local_addr()?);
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, server::router(state, cors_origin)).await {
+                tracing::warn!("HTTP API stopped: {}", e);
+            }
+        });
+
+        Ok(Self { events })
+    }
+
+    #[cfg(not(feature = "api"))]
+    pub async fn start(_config: &ApiConfig, _control: mpsc::UnboundedSender<ControlRequest>) -> std::io::Result<Self> {
+        Err(std::io::Error::other("this build has no HTTP API; rebuild with --features api"))
+    }
+
+    pub fn publish(&self, event: ApiEvent) {
+        // Nobody listening is fine
+        let _ = self.events.send(event);
+    }
+}
+
+#[cfg(feature = "api")]
+mod server {
+    use super::ApiEvent;
+    use crate::cli::parse_duration;
+    use crate::control::{self, ControlCommand, ControlRequest};
+    use crate::history::{self, BatteryHistory};
+    use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+    use axum::extract::{Path, Query, State};
+    use axum::http::{HeaderValue, StatusCode, header};
+    use axum::response::sse::{Event, KeepAlive, Sse};
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use futures::{Stream, StreamExt};
+    use serde::Deserialize;
+    use std::convert::Infallible;
+    use std::time::Duration;
+    use tokio::sync::{broadcast, mpsc};
+
+    // History returned when the request doesn't say how far back to go
+    const DEFAULT_HISTORY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+    const DEVICES_EVENT: &str = "devices";
+
+    #[derive(Clone)]
+    pub struct ApiState {
+        pub control: mpsc::UnboundedSender<ControlRequest>,
+        pub events: broadcast::Sender<ApiEvent>,
+    }
+
+    #[derive(Deserialize)]
+    struct HistoryQuery {
+        // How far back to go, e.g. 7d
+        since: Option<String>,
+    }
+
+    pub fn router(state: ApiState, cors_origin: Option<HeaderValue>) -> Router {
+        Router::new()
+            .route("/devices", get(devices))
+            .route("/devices/{id}/history", get(device_history))
+            .route("/refresh", post(refresh))
+            .route("/events", get(events))
+            .route("/ws", get(websocket))
+            .layer(axum::middleware::map_response_with_state(cors_origin, allow_origin))
+            .with_state(state)
+    }
+
+    async fn allow_origin(State(origin): State<Option<HeaderValue>>, mut response: Response) -> Response {
+        if let Some(origin) = origin {
+            response.headers_mut().insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        }
+        response
+    }
+
+    // Control replies are either the answer or "error: ..."
+    fn control_error(response: &str) -> Option<Response> {
+        response
+            .strip_prefix("error: ")
+            .map(|e| (StatusCode::SERVICE_UNAVAILABLE, e.to_string()).into_response())
+    }
+
+    async fn devices(State(state): State<ApiState>) -> Response {
+        let response = control::request(&state.control, ControlCommand::List).await;
+        if let Some(error) = control_error(&response) {
+            return error;
+        }
+        ([(header::CONTENT_TYPE, "application/json")], response).into_response()
+    }
+
+    async fn device_history(Path(id): Path<String>, Query(query): Query<HistoryQuery>) -> Response {
+        let since = match query.since.as_deref().map(parse_duration).transpose() {
+            Ok(since) => since.unwrap_or(DEFAULT_HISTORY_WINDOW),
+            Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        };
+        let start = history::now().saturating_sub(since.as_secs());
+
+        // The history file can be large, so read it off the event loop
+        let samples =
+            tokio::task::spawn_blocking(move || BatteryHistory::read(start, Some(&id)).map_err(|e| e.to_string())).await;
+        match samples {
+            Ok(Ok(samples)) => Json(samples).into_response(),
+            Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    }
+
+    async fn refresh(State(state): State<ApiState>) -> Response {
+        let response = control::request(&state.control, ControlCommand::Refresh).await;
+        control_error(&response).unwrap_or_else(|| StatusCode::NO_CONTENT.into_response())
+    }
+
+    // Server-Sent Events, starting with the current device list so clients
+    // don't have to fetch it separately
+    async fn events(State(state): State<ApiState>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+        // Subscribe first so nothing slips in between the list and the stream
+        let receiver = state.events.subscribe();
+        let current = control::request(&state.control, ControlCommand::List).await;
+        let first = control_error(&current)
+            .is_none()
+            .then(|| Ok(Event::default().event(DEVICES_EVENT).data(current)));
+
+        let updates = futures::stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((Ok(to_sse(&event)), receiver)),
+                    // A slow client misses some events rather than holding everyone up
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        Sse::new(futures::stream::iter(first).chain(updates)).keep_alive(KeepAlive::default())
+    }
+
+    // The same events as /events, each as a {"event": ..., "data": ...} text message
+    async fn websocket(State(state): State<ApiState>, upgrade: WebSocketUpgrade) -> Response {
+        upgrade.on_upgrade(move |socket| push_events(socket, state))
+    }
+
+    async fn push_events(mut socket: WebSocket, state: ApiState) {
+        let mut receiver = state.events.subscribe();
+        let current = control::request(&state.control, ControlCommand::List).await;
+        if control_error(&current).is_none() && socket.send(ws_message(DEVICES_EVENT, &current)).await.is_err() {
+            return;
+        }
+
+        loop {
+            tokio::select! {
+                event = receiver.recv() => match event {
+                    Ok(event) => {
+                        let (name, data) = event_parts(&event);
+                        if socket.send(ws_message(name, &data)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+                // Clients have nothing to say; stop once they close or go away
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+    }
+
+    // An event's name and its JSON payload
+    fn event_parts(event: &ApiEvent) -> (&'static str, String) {
+        let (name, data) = match event {
+            ApiEvent::Devices(devices) => (DEVICES_EVENT, serde_json::to_string(devices)),
+            ApiEvent::Threshold(crossing) => ("threshold", serde_json::to_string(crossing)),
+        };
+        (name, data.unwrap_or_default())
+    }
+
+    fn to_sse(event: &ApiEvent) -> Event {
+        let (name, data) = event_parts(event);
+        Event::default().event(name).data(data)
+    }
+
+    // `data` is already JSON, so it goes in as is
+    fn ws_message(name: &str, data: &str) -> Message {
+        Message::Text(format!(r#"{{"event":"{}","data":{}}}"#, name, data).into())
+    }
+}
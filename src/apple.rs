@@ -0,0 +1,208 @@
+use crate::bluetooth::BatteryComponent;
+use std::collections::HashMap;
+
+// Bluetooth SIG company identifier for Apple, Inc.
+pub const APPLE_COMPANY_ID: u16 = 0x004C;
+
+// Continuity message type for the proximity pairing advertisement that
+// AirPods and Beats broadcast with their battery levels
+const PROXIMITY_PAIRING: u8 = 0x07;
+const PROXIMITY_PAIRING_MIN_LEN: usize = 9;
+
+// Battery nibbles are in steps of 10%, 15 means the part isn't reporting
+const BATTERY_UNKNOWN: u8 = 0x0F;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AppleBattery {
+    pub level: u8,
+    pub charging: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AppleBatteryStatus {
+    pub model: &'static str,
+    pub left: Option<AppleBattery>,
+    pub right: Option<AppleBattery>,
+    pub case: Option<AppleBattery>,
+}
+
+impl AppleBatteryStatus {
+    pub fn components(&self) -> Vec<BatteryComponent> {
+        let parts = if is_single_battery(self.model) {
+            // Over-ear models only have one battery, reported in either slot
+            vec![("Battery", self.left.as_ref().or(self.right.as_ref()))]
+        } else {
+            vec![("L", self.left.as_ref()), ("R", self.right.as_ref()), ("Case", self.case.as_ref())]
+        };
+
+        parts
+            .into_iter()
+            .filter_map(|(label, part)| {
+                part.map(|b| BatteryComponent {
+                    label: label.to_string(),
+                    level: b.level,
+                    charging: b.charging,
+                })
+            })
+            .collect()
+    }
+}
+
+// Decode the proximity pairing message from Apple's manufacturer data
+//
+// Layout after the company id: type, length, prefix, model (2 bytes),
+// status, pod batteries (one nibble each), charging flags + case battery
+pub fn decode_proximity_pairing(data: &[u8]) -> Option<AppleBatteryStatus> {
+    if data.len() < PROXIMITY_PAIRING_MIN_LEN || data[0] != PROXIMITY_PAIRING {
+        return None;
+    }
+
+    let model = model_name(u16::from_be_bytes([data[3], data[4]]))?;
+
+    // The pods swap nibbles depending on which one is the primary
+    let flipped = data[5] & 0x20 == 0;
+    let (left_level, right_level) = if flipped {
+        (data[6] >> 4, data[6] & 0x0F)
+    } else {
+        (data[6] & 0x0F, data[6] >> 4)
+    };
+
+    let charging = data[7] >> 4;
+    let (left_charging, right_charging) = if flipped {
+        (charging & 0x02 != 0, charging & 0x01 != 0)
+    } else {
+        (charging & 0x01 != 0, charging & 0x02 != 0)
+    };
+
+    Some(AppleBatteryStatus {
+        model,
+        left: battery(left_level, left_charging),
+        right: battery(right_level, right_charging),
+        case: battery(data[7] & 0x0F, charging & 0x04 != 0),
+    })
+}
+
+// Find and decode Apple's entry in a device's manufacturer data
+pub fn decode_manufacturer_data(manufacturer_data: &HashMap<u16, Vec<u8>>) -> Option<AppleBatteryStatus> {
+    manufacturer_data
+        .get(&APPLE_COMPANY_ID)
+        .and_then(|data| decode_proximity_pairing(data))
+}
+
+fn battery(nibble: u8, charging: bool) -> Option<AppleBattery> {
+    if nibble == BATTERY_UNKNOWN || nibble > 10 {
+        return None;
+    }
+
+    Some(AppleBattery {
+        level: nibble * 10,
+        charging,
+    })
+}
+
+fn model_name(model: u16) -> Option<&'static str> {
+    let name = match model {
+        0x0220 => "AirPods",
+        0x0F20 => "AirPods (2nd generation)",
+        0x1320 => "AirPods (3rd generation)",
+        0x0E20 => "AirPods Pro",
+        0x1420 => "AirPods Pro (2nd generation)",
+        0x0A20 => "AirPods Max",
+        0x0320 => "Powerbeats3",
+        0x0B20 => "Powerbeats Pro",
+        0x0520 => "BeatsX",
+        0x1020 => "Beats Flex",
+        0x0620 => "Beats Solo3",
+        0x0C20 => "Beats Solo Pro",
+        0x0920 => "Beats Studio3",
+        0x1120 => "Beats Studio Buds",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn is_single_battery(model: &str) -> bool {
+    matches!(
+        model,
+        "AirPods Max" | "Powerbeats3" | "BeatsX" | "Beats Flex" | "Beats Solo3" | "Beats Solo Pro" | "Beats Studio3"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Manufacturer data from AirPods Pro in their case, both pods at 90%
+    // out of the case, which then reports an unknown level (0xF)
+    const AIRPODS_PRO: [u8; 27] = [
+        0x07, 0x19, 0x01, 0x0e, 0x20, 0x2b, 0x99, 0x8f, 0x01, 0x00, 0x05, 0x6b, 0x3c, 0x2e, 0x1a, 0x4d, 0x9f, 0x10,
+        0x8a, 0x32, 0x5c, 0x71, 0x07, 0xe3, 0x4f, 0xb6, 0x21,
+    ];
+
+    fn battery_at(level: u8, charging: bool) -> Option<AppleBattery> {
+        Some(AppleBattery { level, charging })
+    }
+
+    #[test]
+    fn decodes_a_captured_advertisement() {
+        let status = decode_proximity_pairing(&AIRPODS_PRO).expect("AirPods Pro decode");
+        assert_eq!(status.model, "AirPods Pro");
+        assert_eq!(status.left, battery_at(90, false));
+        assert_eq!(status.right, battery_at(90, false));
+        assert_eq!(status.case, None);
+    }
+
+    #[test]
+    fn swaps_pods_when_the_other_one_is_primary() {
+        let mut data = AIRPODS_PRO;
+        // Primary bit clear: the high nibble is the left pod, and the
+        // charging bits swap with it. The right pod charges in the case at 40%.
+        data[5] = 0x0b;
+        data[6] = 0x57;
+        data[7] = 0x14;
+        let status = decode_proximity_pairing(&data).expect("AirPods Pro decode");
+        assert_eq!(status.left, battery_at(50, false));
+        assert_eq!(status.right, battery_at(70, true));
+        assert_eq!(status.case, battery_at(40, false));
+
+        data[5] = 0x2b;
+        let status = decode_proximity_pairing(&data).expect("AirPods Pro decode");
+        assert_eq!(status.left, battery_at(70, true));
+        assert_eq!(status.right, battery_at(50, false));
+    }
+
+    #[test]
+    fn drops_unknown_and_out_of_range_levels() {
+        let mut data = AIRPODS_PRO;
+        data[6] = 0xfb;
+        data[7] = 0x4a;
+        let status = decode_proximity_pairing(&data).expect("AirPods Pro decode");
+        assert_eq!(status.left, None);
+        assert_eq!(status.right, None);
+        assert_eq!(status.case, battery_at(100, true));
+    }
+
+    #[test]
+    fn rejects_other_messages() {
+        assert_eq!(decode_proximity_pairing(&AIRPODS_PRO[..8]), None);
+        let mut data = AIRPODS_PRO;
+        data[0] = 0x10;
+        assert_eq!(decode_proximity_pairing(&data), None);
+        let mut data = AIRPODS_PRO;
+        data[3] = 0xff;
+        assert_eq!(decode_proximity_pairing(&data), None);
+    }
+
+    #[test]
+    fn single_battery_models_have_one_component() {
+        let mut data = AIRPODS_PRO;
+        // AirPods Max report their one battery in a pod slot
+        data[3] = 0x0a;
+        data[6] = 0xf6;
+        let status = decode_proximity_pairing(&data).expect("AirPods Max decode");
+        let components = status.components();
+        assert_eq!(components.len(), 1);
+        assert_eq!(components[0].label, "Battery");
+        assert_eq!(components[0].level, 60);
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use tracing::{debug, info};
+use zbus::fdo::ObjectManager;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath};
+use zbus::{interface, Connection};
+
+// Where our batteries live on the system bus, under one object manager
+const PROVIDER_ROOT: &str = "/io/github/dreygur/BatteryMonitor/batteries";
+const PROVIDER_MANAGER: &str = "org.bluez.BatteryProviderManager1";
+// What BlueZ reports as the level's origin
+const SOURCE: &str = "battery-monitor";
+
+struct ProvidedBattery {
+    device: OwnedObjectPath,
+    percentage: u8,
+}
+
+#[interface(name = "org.bluez.BatteryProvider1")]
+impl ProvidedBattery {
+    #[zbus(property)]
+    fn device(&self) -> OwnedObjectPath {
+        self.device.clone()
+    }
+
+    #[zbus(property)]
+    fn percentage(&self) -> u8 {
+        self.percentage
+    }
+
+    #[zbus(property)]
+    fn source(&self) -> String {
+        SOURCE.to_string()
+    }
+}
+
+// Hands battery levels BlueZ can't read itself (controllers over HID, AirPods
+// from their advertisements) back to it, so GNOME Settings and everything
+// else reading Battery1 shows them too. BlueZ only offers this with its
+// experimental features on.
+pub struct BatteryProvider {
+    connection: Connection,
+    adapter: String,
+    // Level last published per MAC address
+    published: HashMap<String, u8>,
+}
+
+impl BatteryProvider {
+    pub async fn start(adapter: &str) -> zbus::Result<Self> {
+        let connection = Connection::system().await?;
+        connection.object_server().at(PROVIDER_ROOT, ObjectManager).await?;
+        connection
+            .call_method(
+                Some("org.bluez"),
+                format!("/org/bluez/{}", adapter).as_str(),
+                Some(PROVIDER_MANAGER),
+                "RegisterBatteryProvider",
+                &(ObjectPath::try_from(PROVIDER_ROOT)?,),
+            )
+            .await?;
+        info!("Publishing battery levels to BlueZ on {}", adapter);
+
+        Ok(Self {
+            connection,
+            adapter: adapter.to_string(),
+            published: HashMap::new(),
+        })
+    }
+
+    // Publish `batteries` (MAC address and level), dropping any published
+    // before that aren't in it anymore
+    pub async fn update(&mut self, batteries: &[(String, u8)]) -> zbus::Result<()> {
+        let object_server = self.connection.object_server();
+
+        let gone: Vec<String> = self
+            .published
+            .keys()
+            .filter(|address| !batteries.iter().any(|(a, _)| a == *address))
+            .cloned()
+            .collect();
+        for address in gone {
+            object_server.remove::<ProvidedBattery, _>(battery_path(&address).as_str()).await?;
+            self.published.remove(&address);
+            debug!("Stopped publishing the battery of {} to BlueZ", address);
+        }
+
+        for (address, percentage) in batteries {
+            let path = battery_path(address);
+            match self.published.get(address) {
+                Some(published) if published == percentage => {}
+                Some(_) => {
+                    let battery = object_server.interface::<_, ProvidedBattery>(path.as_str()).await?;
+                    let mut provided = battery.get_mut().await;
+                    provided.percentage = *percentage;
+                    provided.percentage_changed(battery.signal_emitter()).await?;
+                }
+                None => {
+                    let device = OwnedObjectPath::try_from(format!("/org/bluez/{}/{}", self.adapter, device_node(address)))?;
+                    let battery = ProvidedBattery {
+                        device,
+                        percentage: *percentage,
+                    };
+                    object_server.at(path.as_str(), battery).await?;
+                    debug!("Publishing the battery of {} to BlueZ", address);
+                }
+            }
+            self.published.insert(address.clone(), *percentage);
+        }
+        Ok(())
+    }
+}
+
+// BlueZ names device objects dev_AA_BB_CC_DD_EE_FF
+fn device_node(address: &str) -> String {
+    format!("dev_{}", address.to_uppercase().replace(':', "_"))
+}
+
+fn battery_path(address: &str) -> String {
+    format!("{}/{}", PROVIDER_ROOT, device_node(address))
+}
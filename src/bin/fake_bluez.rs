@@ -0,0 +1,368 @@
+// A stand-in for BlueZ that serves org.bluez with made-up devices, for
+// exercising the monitor end to end without Bluetooth hardware. Run it on a
+// private bus and point the monitor at the same bus through
+// DBUS_SYSTEM_BUS_ADDRESS; scripts/fake-bluez.sh does both.
+//
+// Devices come from a TOML file given as the only argument. Lines on stdin
+// change them while the monitor watches:
+//
+//   battery <address> <percent|none>
+//   connect <address>
+//   disconnect <address>
+//   rssi <address> <dBm>
+//   add <address> <name>
+//   remove <address>
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use zbus::fdo::ObjectManager;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue};
+use zbus::{connection, interface, Connection};
+
+const BUS_NAME: &str = "org.bluez";
+const ADAPTER_PATH: &str = "/org/bluez/hci0";
+
+#[derive(Clone, Debug, Deserialize)]
+struct DevicesFile {
+    #[serde(default)]
+    devices: Vec<FakeDevice>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+struct FakeDevice {
+    address: String,
+    name: String,
+    // Bluetooth class of device, e.g. 0x240418 for headphones
+    class: u32,
+    uuids: Vec<String>,
+    icon: String,
+    connected: bool,
+    paired: bool,
+    rssi: i16,
+    // Exposed through org.bluez.Battery1 when set
+    battery: Option<u8>,
+}
+
+impl Default for FakeDevice {
+    fn default() -> Self {
+        Self {
+            address: String::new(),
+            name: "Fake device".to_string(),
+            class: 0,
+            uuids: Vec::new(),
+            icon: String::new(),
+            connected: true,
+            paired: true,
+            rssi: -60,
+            battery: None,
+        }
+    }
+}
+
+fn device_path(address: &str) -> String {
+    format!("{}/dev_{}", ADAPTER_PATH, address.to_uppercase().replace(':', "_"))
+}
+
+struct Adapter {
+    discovering: bool,
+    powered: bool,
+}
+
+#[interface(name = "org.bluez.Adapter1")]
+impl Adapter {
+    fn start_discovery(&mut self) {
+        self.discovering = true;
+    }
+
+    fn stop_discovery(&mut self) {
+        self.discovering = false;
+    }
+
+    fn set_discovery_filter(&self, _filter: HashMap<String, OwnedValue>) {}
+
+    fn get_discovery_filters(&self) -> Vec<String> {
+        vec!["UUIDs", "RSSI", "Pathloss", "Transport", "DuplicateData", "Discoverable", "Pattern"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    async fn remove_device(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        device: ObjectPath<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let server = connection.object_server();
+        // Battery1 may or may not be there
+        let _ = server.remove::<Battery, _>(device.clone()).await;
+        server
+            .remove::<Device, _>(device)
+            .await
+            .map_err(|e| zbus::fdo::Error::Failed(e.to_string()))?;
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn address(&self) -> String {
+        "00:11:22:33:44:55".to_string()
+    }
+
+    #[zbus(property)]
+    fn address_type(&self) -> String {
+        "public".to_string()
+    }
+
+    #[zbus(property)]
+    fn name(&self) -> String {
+        "fake-bluez".to_string()
+    }
+
+    #[zbus(property)]
+    fn alias(&self) -> String {
+        "fake-bluez".to_string()
+    }
+
+    #[zbus(property)]
+    fn class(&self) -> u32 {
+        0
+    }
+
+    #[zbus(property)]
+    fn powered(&self) -> bool {
+        self.powered
+    }
+
+    #[zbus(property)]
+    fn set_powered(&mut self, powered: bool) {
+        self.powered = powered;
+    }
+
+    #[zbus(property)]
+    fn discoverable(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn pairable(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn discovering(&self) -> bool {
+        self.discovering
+    }
+
+    #[zbus(property, name = "UUIDs")]
+    fn uuids(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+struct Device {
+    device: FakeDevice,
+}
+
+#[interface(name = "org.bluez.Device1")]
+impl Device {
+    async fn connect(&mut self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> zbus::Result<()> {
+        self.device.connected = true;
+        self.connected_changed(&emitter).await
+    }
+
+    async fn disconnect(&mut self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> zbus::Result<()> {
+        self.device.connected = false;
+        self.connected_changed(&emitter).await
+    }
+
+    #[zbus(property)]
+    fn address(&self) -> String {
+        self.device.address.to_uppercase()
+    }
+
+    #[zbus(property)]
+    fn address_type(&self) -> String {
+        "public".to_string()
+    }
+
+    #[zbus(property)]
+    fn name(&self) -> String {
+        self.device.name.clone()
+    }
+
+    #[zbus(property)]
+    fn alias(&self) -> String {
+        self.device.name.clone()
+    }
+
+    #[zbus(property)]
+    fn class(&self) -> u32 {
+        self.device.class
+    }
+
+    #[zbus(property)]
+    fn icon(&self) -> String {
+        self.device.icon.clone()
+    }
+
+    #[zbus(property, name = "UUIDs")]
+    fn uuids(&self) -> Vec<String> {
+        self.device.uuids.clone()
+    }
+
+    #[zbus(property)]
+    fn paired(&self) -> bool {
+        self.device.paired
+    }
+
+    #[zbus(property)]
+    fn trusted(&self) -> bool {
+        self.device.paired
+    }
+
+    #[zbus(property)]
+    fn blocked(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn connected(&self) -> bool {
+        self.device.connected
+    }
+
+    #[zbus(property)]
+    fn services_resolved(&self) -> bool {
+        self.device.connected
+    }
+
+    #[zbus(property, name = "RSSI")]
+    fn rssi(&self) -> i16 {
+        self.device.rssi
+    }
+
+    #[zbus(property)]
+    fn adapter(&self) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(ADAPTER_PATH).expect("adapter path is valid")
+    }
+}
+
+struct Battery {
+    percentage: u8,
+}
+
+#[interface(name = "org.bluez.Battery1")]
+impl Battery {
+    #[zbus(property)]
+    fn percentage(&self) -> u8 {
+        self.percentage
+    }
+
+    #[zbus(property)]
+    fn source(&self) -> String {
+        "fake-bluez".to_string()
+    }
+}
+
+async fn add_device(connection: &Connection, device: FakeDevice) -> zbus::Result<()> {
+    let path = device_path(&device.address);
+    let battery = device.battery;
+    let server = connection.object_server();
+    server.at(path.as_str(), Device { device }).await?;
+    if let Some(percentage) = battery {
+        server.at(path.as_str(), Battery { percentage }).await?;
+    }
+    Ok(())
+}
+
+async fn handle_command(connection: &Connection, line: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let server = connection.object_server();
+
+    match parts.as_slice() {
+        [] => {}
+        ["battery", address, "none"] => {
+            server.remove::<Battery, _>(device_path(address).as_str()).await?;
+        }
+        ["battery", address, level] => {
+            let percentage: u8 = level.parse()?;
+            let path = device_path(address);
+            match server.interface::<_, Battery>(path.as_str()).await {
+                Ok(battery) => {
+                    battery.get_mut().await.percentage = percentage;
+                    battery.get().await.percentage_changed(battery.signal_emitter()).await?;
+                }
+                Err(_) => {
+                    server.at(path.as_str(), Battery { percentage }).await?;
+                }
+            }
+        }
+        [command @ ("connect" | "disconnect"), address] => {
+            let device = server.interface::<_, Device>(device_path(address).as_str()).await?;
+            device.get_mut().await.device.connected = *command == "connect";
+            let device_ref = device.get().await;
+            device_ref.connected_changed(device.signal_emitter()).await?;
+            device_ref.services_resolved_changed(device.signal_emitter()).await?;
+        }
+        ["rssi", address, rssi] => {
+            let device = server.interface::<_, Device>(device_path(address).as_str()).await?;
+            device.get_mut().await.device.rssi = rssi.parse()?;
+            device.get().await.rssi_changed(device.signal_emitter()).await?;
+        }
+        ["add", address, name @ ..] if !name.is_empty() => {
+            let device = FakeDevice {
+                address: address.to_string(),
+                name: name.join(" "),
+                ..Default::default()
+            };
+            add_device(connection, device).await?;
+        }
+        ["remove", address] => {
+            let path = device_path(address);
+            let _ = server.remove::<Battery, _>(path.as_str()).await;
+            server.remove::<Device, _>(path.as_str()).await?;
+        }
+        _ => return Err(format!("unknown command '{}'", line.trim()).into()),
+    }
+
+    Ok(())
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let devices = match std::env::args().nth(1) {
+        Some(path) => toml::from_str::<DevicesFile>(&std::fs::read_to_string(&path)?)?.devices,
+        None => Vec::new(),
+    };
+
+    // The system bus address comes from DBUS_SYSTEM_BUS_ADDRESS, which should
+    // point at a private bus rather than the real one where BlueZ lives
+    let connection = connection::Builder::system()?
+        .name(BUS_NAME)?
+        .serve_at("/", ObjectManager)?
+        .serve_at(
+            ADAPTER_PATH,
+            Adapter {
+                discovering: false,
+                powered: true,
+            },
+        )?
+        .build()
+        .await?;
+
+    for device in devices {
+        eprintln!("Adding {} ({})", device.name, device.address);
+        add_device(&connection, device).await?;
+    }
+    eprintln!("Serving {} with adapter {}; reading commands from stdin", BUS_NAME, ADAPTER_PATH);
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        if let Err(e) = handle_command(&connection, &line).await {
+            eprintln!("error: {}", e);
+        }
+    }
+
+    Ok(())
+}
@@ -1,24 +1,87 @@
-use bluer::{Address, Device};
+use crate::apple;
+use crate::quirks::{quirks, BatteryMethod};
+use crate::smoothing::BatteryFilter;
+use bluer::gatt::remote::Characteristic;
+use bluer::{Address, Device, Uuid};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use tracing::{debug, info, warn};
+
+// GATT Battery Service and its Battery Level characteristic
+const BATTERY_SERVICE_UUID: Uuid = Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb);
+const BATTERY_LEVEL_UUID: Uuid = Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb);
+
+// Signal strength below which a device is logged as drifting out of range
+const WEAK_SIGNAL_RSSI: i16 = -85;
+
+// Everything but the 16-bit short id in the Bluetooth Base UUID
+const BASE_UUID: u128 = 0x00000000_0000_1000_8000_00805f9b34fb;
+
+// Audio profiles: Headset, A2DP Audio Sink and Hands-Free
+const HEADSET_UUID: u16 = 0x1108;
+const AUDIO_SINK_UUID: u16 = 0x110b;
+const HANDSFREE_UUID: u16 = 0x111e;
+
+// Class of Device fields for audio devices
+const MAJOR_CLASS_AUDIO_VIDEO: u32 = 0x04;
+const MINOR_CLASS_WEARABLE_HEADSET: u32 = 0x01;
+const MINOR_CLASS_HANDS_FREE: u32 = 0x02;
+const MINOR_CLASS_LOUDSPEAKER: u32 = 0x05;
+const MINOR_CLASS_HEADPHONES: u32 = 0x06;
+const MINOR_CLASS_PORTABLE_AUDIO: u32 = 0x07;
 
 #[derive(Clone, Debug)]
 pub struct BluetoothDevice {
     pub name: String,
+    pub alias: Option<String>,
     pub address: Address,
     pub battery_percentage: Option<u8>,
+    // Individually reported batteries, e.g. left/right earbud and case
+    pub batteries: Vec<BatteryComponent>,
     pub device_type: BluetoothDeviceType,
+    // Signal strength in dBm; BlueZ only has it while it's hearing the device
+    pub rssi: Option<i16>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BatteryComponent {
+    pub label: String,
+    pub level: u8,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub charging: bool,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum BluetoothDeviceType {
     Headphones,
+    Buds,
     Mouse,
     Phone,
     Tablet,
     Speaker,
+    Gamepad,
+    Stylus,
     Unknown,
 }
 
+impl BluetoothDeviceType {
+    // The type named by a quirk's device_type
+    fn from_quirk(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "headphones" => Some(BluetoothDeviceType::Headphones),
+            "buds" => Some(BluetoothDeviceType::Buds),
+            "mouse" => Some(BluetoothDeviceType::Mouse),
+            "phone" => Some(BluetoothDeviceType::Phone),
+            "tablet" => Some(BluetoothDeviceType::Tablet),
+            "speaker" => Some(BluetoothDeviceType::Speaker),
+            "gamepad" => Some(BluetoothDeviceType::Gamepad),
+            "stylus" => Some(BluetoothDeviceType::Stylus),
+            _ => None,
+        }
+    }
+}
+
 impl BluetoothDevice {
     pub async fn from_device(device: Device, addr: Address) -> bluer::Result<Option<Self>> {
         if !device.is_connected().await? {
@@ -26,41 +89,93 @@ impl BluetoothDevice {
         }
 
         let name = device.name().await?.unwrap_or_else(|| "Unknown Device".to_string());
-        let battery_percentage = device.battery_percentage().await?;
-        let device_type = Self::detect_device_type(&name, &device).await;
+        let mut battery_percentage = device.battery_percentage().await?;
+        if battery_percentage.is_none() {
+            battery_percentage = read_gatt_battery(&device).await.unwrap_or_else(|e| {
+                warn!("Failed to read GATT battery level for {}: {}", addr, e);
+                None
+            });
+        }
+
+        let quirk = quirks().for_bluetooth(&addr.to_string());
+        if let Some(quirk) = quirk {
+            battery_percentage = match quirk.battery {
+                Some(BatteryMethod::None) => None,
+                _ => battery_percentage.and_then(|raw| quirk.transform(raw)),
+            };
+        }
+        let quirk_type = quirk
+            .and_then(|quirk| quirk.device_type.as_deref())
+            .and_then(BluetoothDeviceType::from_quirk);
+        let device_type = match quirk_type {
+            Some(device_type) => device_type,
+            None => Self::detect_device_type(&name, &device).await,
+        };
+        let rssi = device.rssi().await?;
 
-        Ok(Some(BluetoothDevice {
+        let mut bt_device = BluetoothDevice {
             name,
+            alias: None,
             address: addr,
             battery_percentage,
+            batteries: Vec::new(),
             device_type,
-        }))
-    }
-
-    // fn classify_device(uuids: &[uuid::Uuid]) -> &'static str {
-    //     for uuid in uuids {
-    //         let short = uuid.as_u128() >> 96; // extract 16-bit portion if in Bluetooth base UUID
+            rssi,
+        };
 
-    //         match short {
-    //             0x1812 => return "Keyboard/Mouse (HID)",  // Human Interface Device
-    //             0x1108 | 0x111e => return "Headset/Handsfree",
-    //             0x1800 => return "Generic Access (Phone/Peripheral)",
-    //             0x180F => return "Battery-powered Device",
-    //             _ => {}
-    //         }
-    //     }
+        // BlueZ has no Battery1 levels for AirPods and Beats, but they
+        // advertise them in Apple's manufacturer data
+        if let Some(status) = device
+            .manufacturer_data()
+            .await?
+            .and_then(|data| apple::decode_manufacturer_data(&data))
+        {
+            bt_device.set_batteries(status.components());
+            // Separate left and right batteries only come with earbuds
+            if bt_device.batteries.iter().any(|b| b.label == "L" || b.label == "R") {
+                bt_device.device_type = BluetoothDeviceType::Buds;
+            }
+        }
 
-    //     "Unknown"
-    // }
+        Ok(Some(bt_device))
+    }
 
-    async fn detect_device_type(name: &str, _device: &Device) -> BluetoothDeviceType {
+    async fn detect_device_type(name: &str, device: &Device) -> BluetoothDeviceType {
         let name_lower = name.to_lowercase();
-        // let class = device.all_properties().await;
-        // println!("Device: {:?}", class);
+        let class = device.class().await.ok().flatten();
+        let uuids = device.uuids().await.ok().flatten().unwrap_or_default();
 
-        if name_lower.contains("headphone") || name_lower.contains("earbuds") ||
-           name_lower.contains("airpods") || name_lower.contains("buds") {
+        // Audio devices are sorted by their Class of Device first, since
+        // names like "WF-1000XM4" or "QC35" say nothing about the form factor
+        let minor_audio_class = class
+            .filter(|class| (class >> 8) & 0x1f == MAJOR_CLASS_AUDIO_VIDEO)
+            .map(|class| (class >> 2) & 0x3f);
+        let is_audio = minor_audio_class.is_some() || uuids.iter().any(|uuid| is_audio_profile(*uuid));
+        let says_buds = ["buds", "airpods", "earbuds", "pods", "tws"].iter().any(|n| name_lower.contains(n));
+        let says_headphones = name_lower.contains("headphone") || name_lower.contains("beats");
+
+        if says_buds {
+            BluetoothDeviceType::Buds
+        } else if says_headphones {
             BluetoothDeviceType::Headphones
+        } else if is_audio {
+            match minor_audio_class {
+                Some(MINOR_CLASS_HEADPHONES) => BluetoothDeviceType::Headphones,
+                Some(MINOR_CLASS_LOUDSPEAKER | MINOR_CLASS_PORTABLE_AUDIO) => BluetoothDeviceType::Speaker,
+                // True wireless earbuds report themselves as a wearable
+                // headset or hands-free device and offer HFP for their mics
+                Some(MINOR_CLASS_WEARABLE_HEADSET | MINOR_CLASS_HANDS_FREE)
+                    if uuids.iter().any(|uuid| short_uuid(*uuid) == Some(HANDSFREE_UUID)) =>
+                {
+                    BluetoothDeviceType::Buds
+                }
+                _ if name_lower.contains("speaker") || name_lower.contains("soundbar") => BluetoothDeviceType::Speaker,
+                _ => BluetoothDeviceType::Headphones,
+            }
+        } else if ["controller", "gamepad", "dualsense", "dualshock", "joy-con"].iter().any(|n| name_lower.contains(n)) {
+            BluetoothDeviceType::Gamepad
+        } else if name_lower.contains("stylus") || name_lower.contains(" pen") {
+            BluetoothDeviceType::Stylus
         } else if name_lower.contains("mouse") {
             BluetoothDeviceType::Mouse
         } else if name_lower.contains("phone") || name_lower.contains("iphone") ||
@@ -78,19 +193,35 @@ impl BluetoothDevice {
     pub fn get_icon(&self) -> &'static str {
         match self.device_type {
             BluetoothDeviceType::Headphones => "🎧",
+            BluetoothDeviceType::Buds => "🎧",
             BluetoothDeviceType::Mouse => "🖱️",
             BluetoothDeviceType::Phone => "📱",
             BluetoothDeviceType::Tablet => "📟",
             BluetoothDeviceType::Speaker => "🔊",
+            BluetoothDeviceType::Gamepad => "🎮",
+            BluetoothDeviceType::Stylus => "🖊️",
             BluetoothDeviceType::Unknown => "📻",
         }
     }
 
+    // Record per-component levels; the overall level is the lowest component
+    pub fn set_batteries(&mut self, batteries: Vec<BatteryComponent>) {
+        if let Some(lowest) = batteries.iter().map(|b| b.level).min() {
+            self.battery_percentage = Some(lowest);
+        }
+        self.batteries = batteries;
+    }
+
+    pub fn display_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+
     pub fn format_for_status(&self) -> String {
-        let short_name = if self.name.len() > 12 {
-            format!("{}...", &self.name[..9])
+        let name = self.display_name();
+        let short_name = if name.chars().count() > 12 {
+            format!("{}...", name.chars().take(9).collect::<String>())
         } else {
-            self.name.clone()
+            name.to_string()
         };
 
         match self.battery_percentage {
@@ -100,38 +231,104 @@ impl BluetoothDevice {
     }
 }
 
+// Some BLE devices only expose their battery over GATT. BlueZ hides the
+// Battery Service when it already provides Battery1, so this only finds it
+// for devices BlueZ doesn't handle itself.
+async fn battery_level_characteristic(device: &Device) -> bluer::Result<Option<Characteristic>> {
+    if !device.is_services_resolved().await? {
+        return Ok(None);
+    }
+
+    for service in device.services().await? {
+        if service.uuid().await? != BATTERY_SERVICE_UUID {
+            continue;
+        }
+        for characteristic in service.characteristics().await? {
+            if characteristic.uuid().await? == BATTERY_LEVEL_UUID {
+                return Ok(Some(characteristic));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+pub async fn read_gatt_battery(device: &Device) -> bluer::Result<Option<u8>> {
+    match battery_level_characteristic(device).await? {
+        Some(characteristic) => Ok(characteristic.read().await?.first().copied().filter(|level| *level <= 100)),
+        None => Ok(None),
+    }
+}
+
+// Subscribe to Battery Level notifications, if the device supports them
+pub async fn gatt_battery_notifications(device: &Device) -> bluer::Result<Option<impl Stream<Item = u8> + use<>>> {
+    let Some(characteristic) = battery_level_characteristic(device).await? else {
+        return Ok(None);
+    };
+    if !characteristic.flags().await?.notify {
+        return Ok(None);
+    }
+
+    let levels = characteristic
+        .notify()
+        .await?
+        .filter_map(|value| async move { value.first().copied().filter(|level| *level <= 100) });
+    Ok(Some(levels))
+}
+
 pub struct BluetoothManager {
     pub connected_devices: HashMap<Address, BluetoothDevice>,
+    // User-chosen names keyed by upper-cased MAC address
+    pub aliases: HashMap<String, String>,
+    pub battery_filter: BatteryFilter,
 }
 
 impl BluetoothManager {
     pub fn new() -> Self {
         Self {
             connected_devices: HashMap::new(),
+            aliases: HashMap::new(),
+            battery_filter: BatteryFilter::default(),
         }
     }
 
-    pub fn add_device(&mut self, device: BluetoothDevice) {
-        println!("Connected Bluetooth device: {} ({})", device.name, device.address);
+    fn apply_alias(&self, device: &mut BluetoothDevice) {
+        device.alias = self.aliases.get(&device.address.to_string().to_uppercase()).cloned();
+    }
+
+    pub fn add_device(&mut self, mut device: BluetoothDevice) {
+        self.apply_alias(&mut device);
+        device.battery_percentage = self.battery_filter.apply(&device.address.to_string(), device.battery_percentage);
+        info!("Connected Bluetooth device: {} ({})", device.name, device.address);
         if let Some(battery) = device.battery_percentage {
-            println!("  Battery: {}%", battery);
+            info!("Battery: {}%", battery);
         }
         self.connected_devices.insert(device.address, device);
     }
 
     pub fn remove_device(&mut self, addr: Address) -> bool {
         if let Some(device) = self.connected_devices.remove(&addr) {
-            println!("Bluetooth device disconnected: {} ({})", device.name, addr);
+            self.battery_filter.forget(&addr.to_string());
+            info!("Bluetooth device disconnected: {} ({})", device.name, addr);
             true
         } else {
             false
         }
     }
 
-    pub fn update_device(&mut self, addr: Address, updated_device: BluetoothDevice) -> bool {
+    pub fn update_device(&mut self, addr: Address, mut updated_device: BluetoothDevice) -> bool {
+        self.apply_alias(&mut updated_device);
+        updated_device.battery_percentage =
+            self.battery_filter.apply(&addr.to_string(), updated_device.battery_percentage);
         if let Some(existing_device) = self.connected_devices.get_mut(&addr) {
-            if existing_device.battery_percentage != updated_device.battery_percentage {
-                println!("Bluetooth battery updated for {}: {:?}%",
+            // Signal strength changes constantly, so it's kept up to date
+            // without counting as a change worth a status update
+            log_weak_signal(existing_device, updated_device.rssi);
+            existing_device.rssi = updated_device.rssi;
+
+            if existing_device.battery_percentage != updated_device.battery_percentage ||
+               existing_device.batteries != updated_device.batteries {
+                info!("Bluetooth battery updated for {}: {:?}%",
                     updated_device.name, updated_device.battery_percentage);
                 *existing_device = updated_device;
                 return true;
@@ -140,6 +337,22 @@ impl BluetoothManager {
         false
     }
 
+    // Apply a battery level pushed by the device, returning whether it changed
+    pub fn update_battery(&mut self, addr: Address, battery: u8) -> bool {
+        if !self.connected_devices.contains_key(&addr) {
+            return false;
+        }
+        let battery = self.battery_filter.apply(&addr.to_string(), Some(battery)).unwrap_or(battery);
+        match self.connected_devices.get_mut(&addr) {
+            Some(device) if device.battery_percentage != Some(battery) => {
+                info!("Bluetooth battery updated for {}: {}%", device.name, battery);
+                device.battery_percentage = Some(battery);
+                true
+            }
+            _ => false,
+        }
+    }
+
     pub fn get_status_text(&self) -> String {
         if self.connected_devices.is_empty() {
             return "No Bluetooth devices".to_string();
@@ -159,3 +372,27 @@ impl BluetoothManager {
         }
     }
 }
+
+// Log when a device's signal first drops below WEAK_SIGNAL_RSSI, so a device
+// vanishing from the list can be told apart from a battery that ran out
+fn log_weak_signal(device: &BluetoothDevice, rssi: Option<i16>) {
+    let is_weak = |rssi: Option<i16>| rssi.is_some_and(|rssi| rssi < WEAK_SIGNAL_RSSI);
+    if is_weak(rssi) && !is_weak(device.rssi) {
+        debug!(
+            "Weak signal from {} ({}): {} dBm, it may be going out of range",
+            device.name,
+            device.address,
+            rssi.unwrap_or_default()
+        );
+    }
+}
+
+// The 16-bit id of a UUID in the Bluetooth Base UUID range
+fn short_uuid(uuid: Uuid) -> Option<u16> {
+    let value = uuid.as_u128();
+    (value & !(0xffff_u128 << 96) == BASE_UUID).then(|| (value >> 96) as u16)
+}
+
+fn is_audio_profile(uuid: Uuid) -> bool {
+    matches!(short_uuid(uuid), Some(HEADSET_UUID | AUDIO_SINK_UUID | HANDSFREE_UUID))
+}
@@ -0,0 +1,265 @@
+use crate::completions::{self, CompletionShell};
+use crate::config::Config;
+use crate::history::ExportFormat;
+use crate::logging::LogFormat;
+use crate::notifications::NotificationType;
+use crate::output::{StatusOutput, DEFAULT_STATUS_FILE};
+use clap::{Args, Parser, Subcommand};
+use clap_complete::engine::ArgValueCandidates;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Monitor battery levels of Bluetooth devices and keyboards")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+
+    /// File the plain status text is written to
+    #[arg(long, value_name = "PATH", default_value = DEFAULT_STATUS_FILE)]
+    pub status_file: PathBuf,
+
+    /// Print Waybar custom module JSON to stdout on every update
+    #[arg(long)]
+    pub waybar: bool,
+
+    /// Write Waybar custom module JSON to this file on every update
+    #[arg(long, value_name = "PATH")]
+    pub waybar_file: Option<PathBuf>,
+
+    /// Don't show the system tray icon
+    #[arg(long)]
+    pub no_tray: bool,
+
+    /// Show the device details notification; if the monitor is already
+    /// running, ask it to show them instead of starting another one
+    #[arg(long)]
+    pub show_details: bool,
+
+    /// Scan once, send any battery alerts that are due, print the devices and exit,
+    /// with status 2 if any device is at or below the low battery threshold
+    #[arg(long, conflicts_with_all = ["show_details", "restore"])]
+    pub oneshot: bool,
+
+    /// Start from a state dumped by `ctl snapshot`: its devices are shown
+    /// alongside real ones and its alert suppression and mutes apply
+    #[arg(long, value_name = "PATH")]
+    pub restore: Option<PathBuf>,
+
+    /// Also write logs to this file, rotated daily with a week kept
+    #[arg(long, global = true, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Log as plain text or one JSON object per line
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+
+    #[command(flatten)]
+    pub overrides: ConfigOverrides,
+}
+
+impl Cli {
+    pub fn status_outputs(&self) -> Vec<StatusOutput> {
+        let mut outputs = vec![StatusOutput::PlainFile(self.status_file.clone())];
+
+        if let Some(path) = &self.waybar_file {
+            outputs.push(StatusOutput::WaybarFile(path.clone()));
+        }
+        if self.waybar {
+            outputs.push(StatusOutput::WaybarStdout);
+        }
+
+        outputs
+    }
+}
+
+// Config settings that can be given on the command line
+#[derive(Args, Clone, Debug, Default)]
+pub struct ConfigOverrides {
+    /// Seconds between battery refreshes, overriding [polling] interval_secs
+    #[arg(long, global = true, value_name = "SECONDS")]
+    pub polling_interval: Option<u64>,
+
+    /// Low battery threshold in percent, overriding the config file
+    #[arg(long, global = true, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub threshold: Option<u8>,
+
+    /// Critical battery threshold in percent, overriding the config file
+    #[arg(long, global = true, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub critical_threshold: Option<u8>,
+
+    /// Add fake devices with scripted battery levels, for trying things out without hardware
+    #[arg(long, global = true)]
+    pub mock_devices: bool,
+}
+
+impl ConfigOverrides {
+    // Flags win over the config file and environment
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(interval) = self.polling_interval {
+            config.polling.interval_secs = interval;
+        }
+        if let Some(threshold) = self.threshold {
+            config.notifications.low_battery_threshold = threshold;
+        }
+        if let Some(threshold) = self.critical_threshold {
+            config.notifications.critical_battery_threshold = threshold;
+        }
+        if self.mock_devices {
+            config.scanners.mock_devices = true;
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Scan once, print the connected devices and exit
+    Status {
+        /// Print the device list as JSON
+        #[arg(long, conflicts_with = "format")]
+        json: bool,
+        /// Print each device with a template using {name}, {level}, {icon} and {state},
+        /// all on one line, e.g. "{icon} {level}%"
+        #[arg(long, value_name = "TEMPLATE")]
+        format: Option<String>,
+        /// Keep running and print a new line whenever the devices change
+        #[arg(long)]
+        follow: bool,
+    },
+    /// Scan once without asking a running monitor and print a table of devices
+    List {
+        /// Print the device list as JSON
+        #[arg(long)]
+        json: bool,
+        /// Print nothing; exit with status 2 if any device is at or below the low battery threshold
+        #[arg(long, conflicts_with = "json")]
+        quiet_exit_code: bool,
+    },
+    /// Full-screen live view of the devices with battery bars and recent history
+    /// (needs the `tui` feature)
+    Watch,
+    /// Send a command to the running monitor (list, refresh, show-details, stats,
+    /// mute <device> [--for 2h], unmute <device>, set-threshold <low|critical> <percent>, reload,
+    /// snapshot [path], shutdown)
+    Ctl {
+        #[arg(
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            add = ArgValueCandidates::new(completions::ctl_candidates)
+        )]
+        command: Vec<String>,
+    },
+    /// Install a systemd user service for the monitor
+    InstallService {
+        /// Enable and start the service (default: the auto_start config setting)
+        #[arg(long)]
+        enable: bool,
+        /// Only write the unit file, even if auto_start is set
+        #[arg(long, conflicts_with = "enable")]
+        no_enable: bool,
+    },
+    /// Stop, disable and remove the systemd user service
+    UninstallService,
+    /// Show past low and critical battery alerts
+    Log {
+        /// Only alerts from this long ago onwards, e.g. 24h or 7d
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        since: Option<Duration>,
+        /// Only alerts for this device id or name
+        #[arg(long, add = ArgValueCandidates::new(completions::device_candidates))]
+        device: Option<String>,
+        /// Print the alerts as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Dump the recorded battery levels for analysis elsewhere
+    Export {
+        /// Output format
+        #[arg(long, value_enum, default_value = "csv")]
+        format: ExportFormat,
+        /// Only samples for this device id or name
+        #[arg(long, add = ArgValueCandidates::new(completions::device_candidates))]
+        device: Option<String>,
+        /// Only samples from this long ago onwards, e.g. 24h or 7d
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        since: Option<Duration>,
+        /// Write to this file instead of stdout
+        #[arg(long, short, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Summarize a device's recorded battery history: drain rate, how long a
+    /// charge lasts, last full charge and this month's alerts
+    Stats {
+        /// Device id or name
+        #[arg(add = ArgValueCandidates::new(completions::device_candidates))]
+        device: String,
+        /// Print the figures as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Send a made-up battery alert through the configured notification channels
+    TestNotification {
+        /// Which alert to send
+        #[arg(long = "type", value_enum, default_value = "low-battery")]
+        notification_type: NotificationType,
+        /// Only print the alert and where it would go
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check BlueZ, the Bluetooth adapter, HID permissions, notifications and the config
+    Doctor,
+    /// Print a report for bug reports: version, enabled scanners, the config with
+    /// secrets removed, the devices found and recent log lines
+    Diagnostics,
+    /// Print a shell completion script; device names are completed from the running monitor
+    Completions {
+        #[arg(value_enum)]
+        shell: CompletionShell,
+    },
+    /// Write man pages generated from this command line and the config defaults
+    GenerateMan {
+        /// Write battery_percentage.1 and one page per subcommand into this directory
+        /// instead of printing the main page
+        #[arg(long, value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+    },
+    /// Walk through a first configuration: check Bluetooth, pick alert thresholds
+    /// and autostart, and scan once
+    Setup,
+    /// Read or change settings in the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    /// Print a setting such as notifications.low_battery_threshold, or every setting without a key
+    Get { key: Option<String> },
+    /// Change a setting in the config file and tell the running monitor to reload it
+    Set { key: String, value: String },
+    /// Print where the config file is
+    Path,
+}
+
+// Parse durations like "90s", "30m", "2h" or "7d"; a bare number is minutes
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "m"),
+    };
+
+    let number: u64 = number.parse().map_err(|_| format!("invalid duration '{}'", value))?;
+    let seconds = match unit {
+        "s" => number,
+        "m" => number * 60,
+        "h" => number * 60 * 60,
+        "d" => number * 24 * 60 * 60,
+        _ => return Err(format!("invalid duration unit in '{}', expected s, m, h or d", value)),
+    };
+
+    Ok(Duration::from_secs(seconds))
+}
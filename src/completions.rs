@@ -0,0 +1,89 @@
+use crate::control::socket_path;
+use crate::status::DeviceStatus;
+use clap::ValueEnum;
+use clap_complete::engine::CompletionCandidate;
+use clap_complete::env::{Bash, Elvish, EnvCompleter, Fish, Powershell, Zsh};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+// Set by the shell when it calls back into the binary for completions
+pub const COMPLETE_VAR: &str = "COMPLETE";
+// Completion runs on every Tab, so a stuck monitor mustn't hang the shell
+const MONITOR_TIMEOUT: Duration = Duration::from_millis(500);
+// What `ctl` accepts as its first word
+const CTL_COMMANDS: &[&str] = &[
+    "list",
+    "refresh",
+    "show-details",
+    "stats",
+    "health",
+    "mute",
+    "unmute",
+    "set-threshold",
+    "reload",
+    "snapshot",
+    "shutdown",
+];
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
+    Elvish,
+    Powershell,
+}
+
+// The script a shell sources to complete our commands. It asks the binary
+// itself for candidates, so device names come from the running monitor.
+pub fn write_registration(shell: CompletionShell, out: &mut dyn Write) -> std::io::Result<()> {
+    let completer: &dyn EnvCompleter = match shell {
+        CompletionShell::Bash => &Bash,
+        CompletionShell::Zsh => &Zsh,
+        CompletionShell::Fish => &Fish,
+        CompletionShell::Elvish => &Elvish,
+        CompletionShell::Powershell => &Powershell,
+    };
+    let name = env!("CARGO_PKG_NAME");
+    let executable = std::env::current_exe()?;
+    completer.write_registration(COMPLETE_VAR, name, name, &executable.to_string_lossy(), out)
+}
+
+// Names of the running monitor's devices; none when it isn't running
+pub fn device_candidates() -> Vec<CompletionCandidate> {
+    running_devices()
+        .into_iter()
+        .map(|device| {
+            let help = match device.battery_percentage {
+                Some(level) => format!("{} {}%", device.device_type, level),
+                None => device.device_type.clone(),
+            };
+            CompletionCandidate::new(device.name).help(Some(help.into()))
+        })
+        .collect()
+}
+
+// `ctl` takes its command and arguments as free words, so offer both the
+// commands and the device names the ones like mute take
+pub fn ctl_candidates() -> Vec<CompletionCandidate> {
+    CTL_COMMANDS
+        .iter()
+        .map(|command| CompletionCandidate::new(command))
+        .chain(device_candidates())
+        .collect()
+}
+
+// The control socket without a runtime, since completion runs before main's does anything
+fn running_devices() -> Vec<DeviceStatus> {
+    let query = || -> std::io::Result<String> {
+        let mut stream = UnixStream::connect(socket_path())?;
+        stream.set_read_timeout(Some(MONITOR_TIMEOUT))?;
+        stream.set_write_timeout(Some(MONITOR_TIMEOUT))?;
+        stream.write_all(b"list\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    };
+    query().ok().and_then(|response| serde_json::from_str(&response).ok()).unwrap_or_default()
+}
@@ -0,0 +1,390 @@
+use crate::api::ApiConfig;
+use crate::history::HistoryConfig;
+use crate::hooks::HooksConfig;
+use crate::icons::IconMap;
+use crate::mqtt::MqttConfig;
+use crate::notifications::SinksConfig;
+use crate::output::StatusFileConfig;
+use crate::polling::PollingConfig;
+use crate::scanner::ScannersConfig;
+use crate::smoothing::SmoothingConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+const APP_DIR: &str = "battery-monitor";
+
+// Environment variables starting with this override config keys
+const ENV_PREFIX: &str = "BATTERY_MONITOR_";
+
+// Bumped whenever the config file layout changes, with a matching step in MIGRATIONS
+pub const CONFIG_VERSION: u32 = 1;
+
+// Each step upgrades a config file from version i to i + 1. They work on the
+// raw TOML table, so keys the current schema doesn't know about survive.
+const MIGRATIONS: [fn(&mut toml::Table); CONFIG_VERSION as usize] = [migrate_v0_to_v1];
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // Layout version of the file this was loaded from; missing means 0
+    pub version: u32,
+    // Enable the systemd user service when running install-service
+    pub auto_start: bool,
+    // Hand battery levels BlueZ can't read itself back to it, for GNOME
+    // Settings and other Battery1 readers
+    pub bluez_battery_provider: bool,
+    // Per-device settings keyed by Bluetooth MAC address or keyboard vendor:product id
+    pub devices: HashMap<String, DeviceSettings>,
+    pub mqtt: MqttConfig,
+    pub notifications: SinksConfig,
+    pub hooks: HooksConfig,
+    pub scanners: ScannersConfig,
+    pub polling: PollingConfig,
+    pub smoothing: SmoothingConfig,
+    pub history: HistoryConfig,
+    pub api: ApiConfig,
+    pub status_files: Vec<StatusFileConfig>,
+    // Tray icon per device type, as an icon name or an absolute file path
+    pub icons: HashMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            auto_start: false,
+            bluez_battery_provider: true,
+            devices: HashMap::new(),
+            mqtt: MqttConfig::default(),
+            notifications: SinksConfig::default(),
+            hooks: HooksConfig::default(),
+            scanners: ScannersConfig::default(),
+            polling: PollingConfig::default(),
+            smoothing: SmoothingConfig::default(),
+            history: HistoryConfig::default(),
+            api: ApiConfig::default(),
+            status_files: Vec::new(),
+            icons: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceSettings {
+    pub alias: Option<String>,
+    // Once any device is pinned, the tray only shows pinned devices
+    pub pinned: bool,
+    // Position among the pinned devices, lowest first
+    pub tray_order: Option<u32>,
+    // Tray icon for this device, over the one for its type
+    pub icon: Option<String>,
+}
+
+pub fn xdg_config_home() -> PathBuf {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+pub fn config_dir() -> PathBuf {
+    xdg_config_home().join(APP_DIR)
+}
+
+// Where runtime state such as the mute list is kept
+pub fn data_dir() -> PathBuf {
+    std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join(APP_DIR)
+}
+
+// Per-session runtime files such as the control socket and lock file
+pub fn runtime_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+pub fn config_path() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+impl Config {
+    // Defaults, overlaid with the config file, overlaid with BATTERY_MONITOR_*
+    // environment variables. CLI flags go on top in `ConfigOverrides::apply`.
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        let path = config_path();
+        let mut table = if path.exists() { Self::read_table(&path)? } else { toml::Table::new() };
+        apply_env_overrides(&mut table, std::env::vars());
+        Ok(Self::from_table(table, &path))
+    }
+
+    // Parse the config file, upgrading it first if it's from an older version
+    fn read_table(path: &Path) -> Result<toml::Table, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut table: toml::Table = toml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let version = table.get("version").and_then(|v| v.as_integer()).unwrap_or(0);
+        let version = u32::try_from(version).map_err(|_| format!("{}: invalid version {}", path.display(), version))?;
+        if version > CONFIG_VERSION {
+            warn!(
+                "{} is version {}, newer than this build understands ({}); loading what it can",
+                path.display(),
+                version,
+                CONFIG_VERSION
+            );
+        } else if version < CONFIG_VERSION {
+            migrate(&mut table, version);
+            save_migrated(path, &contents, &table, version);
+        }
+
+        Ok(table)
+    }
+
+    // Deserialize section by section when the whole file doesn't fit the
+    // schema, so one bad value only resets its own section to defaults
+    fn from_table(table: toml::Table, path: &Path) -> Self {
+        let error = match toml::Value::Table(table.clone()).try_into() {
+            Ok(config) => return config,
+            Err(e) => e,
+        };
+        warn!("{} doesn't match the expected layout: {}", path.display(), error);
+
+        let mut config = Config::default();
+        macro_rules! load_sections {
+            ($($section:ident),*) => {$(
+                if let Some(value) = table.get(stringify!($section)) {
+                    match value.clone().try_into() {
+                        Ok(value) => config.$section = value,
+                        Err(e) => warn!("Using defaults for `{}`: {}", stringify!($section), e),
+                    }
+                }
+            )*};
+        }
+        load_sections!(
+            version,
+            auto_start,
+            bluez_battery_provider,
+            devices,
+            mqtt,
+            notifications,
+            hooks,
+            scanners,
+            polling,
+            smoothing,
+            history,
+            api,
+            status_files,
+            icons
+        );
+        config
+    }
+
+    // Load the config, falling back to defaults so a typo never stops monitoring
+    pub fn load_or_default() -> Self {
+        Self::load().unwrap_or_else(|e| {
+            warn!("Failed to load config, using defaults: {}", e);
+            let mut table = toml::Table::new();
+            apply_env_overrides(&mut table, std::env::vars());
+            Self::from_table(table, &config_path())
+        })
+    }
+
+    // Catch values that parse but make no sense
+    pub fn validate(&self) -> Result<(), String> {
+        let notifications = &self.notifications;
+        if notifications.low_battery_threshold > 100 || notifications.critical_battery_threshold > 100 {
+            return Err("battery thresholds must be between 0 and 100".to_string());
+        }
+        if notifications.critical_battery_threshold > notifications.low_battery_threshold {
+            return Err("critical_battery_threshold can't be above low_battery_threshold".to_string());
+        }
+        if notifications.escalation_levels.iter().any(|level| *level > 100) {
+            return Err("escalation_levels must be between 0 and 100".to_string());
+        }
+        if self.polling.interval_secs == 0 || self.polling.fast_interval_secs == 0 || self.polling.idle_interval_secs == 0 {
+            return Err("polling intervals must be at least 1 second".to_string());
+        }
+        if self.scanners.timeout_secs == 0 {
+            return Err("scanners.timeout_secs must be at least 1 second".to_string());
+        }
+        if self.status_files.iter().any(|file| file.path.as_os_str().is_empty()) {
+            return Err("every status_files entry needs a path".to_string());
+        }
+        if self.api.listen.parse::<std::net::SocketAddr>().is_err() {
+            return Err(format!("api.listen '{}' isn't an address and port", self.api.listen));
+        }
+        Ok(())
+    }
+
+    // Look up a dotted key such as notifications.low_battery_threshold
+    pub fn get(&self, key: &str) -> Result<toml::Value, String> {
+        let value = toml::Value::try_from(self).map_err(|e| e.to_string())?;
+        let table = value.as_table().ok_or("config didn't serialize to a table")?;
+        get_path(table, &key_path(key)?)
+            .cloned()
+            .ok_or_else(|| format!("unknown or unset config key '{}'", key))
+    }
+
+    // Change one dotted key in the config file, leaving the rest of the file
+    // (including keys this build doesn't know) as it was
+    pub fn set(key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+        Self::set_all(&[(key, value)])
+    }
+
+    // Change several keys at once, checked together, so related values such as
+    // the two thresholds can move past each other
+    pub fn set_all(settings: &[(&str, &str)]) -> Result<(), Box<dyn std::error::Error>> {
+        let path = config_path();
+        let mut table = if path.exists() { Self::read_table(&path)? } else { toml::Table::new() };
+        table.entry("version").or_insert(toml::Value::Integer(CONFIG_VERSION.into()));
+
+        for (key, value) in settings {
+            set_path(&mut table, &key_path(key)?, parse_value(value.to_string()));
+        }
+
+        let config: Config = toml::Value::Table(table.clone())
+            .try_into()
+            .map_err(|e| {
+                let keys: Vec<&str> = settings.iter().map(|(key, _)| *key).collect();
+                format!("invalid value for {}: {}", keys.join(", "), e)
+            })?;
+        config.validate()?;
+        // Keys the schema doesn't have are dropped when deserializing
+        if let Some((key, _)) = settings.iter().find(|(key, _)| config.get(key).is_err()) {
+            return Err(format!("unknown config key '{}'", key).into());
+        }
+
+        std::fs::create_dir_all(config_dir())?;
+        std::fs::write(&path, toml::to_string(&table)?)?;
+        Ok(())
+    }
+
+    // Aliases keyed by upper-cased device id, so MAC addresses match regardless of case
+    pub fn aliases(&self) -> HashMap<String, String> {
+        self.devices
+            .iter()
+            .filter_map(|(id, settings)| settings.alias.clone().map(|alias| (id.to_uppercase(), alias)))
+            .collect()
+    }
+
+    pub fn icon_map(&self) -> IconMap {
+        IconMap {
+            types: self.icons.clone(),
+            devices: self
+                .devices
+                .iter()
+                .filter_map(|(id, settings)| settings.icon.clone().map(|icon| (id.to_uppercase(), icon)))
+                .collect(),
+        }
+    }
+
+    // Upper-cased ids of the devices pinned to the tray, in tray order
+    pub fn tray_pins(&self) -> Vec<String> {
+        let mut pinned: Vec<(Option<u32>, String)> = self
+            .devices
+            .iter()
+            .filter(|(_, settings)| settings.pinned)
+            .map(|(id, settings)| (settings.tray_order, id.to_uppercase()))
+            .collect();
+        // Devices without an order go last, by id
+        pinned.sort_by_key(|(order, id)| (order.unwrap_or(u32::MAX), id.clone()));
+        pinned.into_iter().map(|(_, id)| id).collect()
+    }
+}
+
+// Set keys from BATTERY_MONITOR_<SECTION>__<KEY> variables, e.g.
+// BATTERY_MONITOR_POLLING__INTERVAL_SECS=10 sets interval_secs under [polling].
+// Values are read as TOML where possible and as plain strings otherwise.
+fn apply_env_overrides(table: &mut toml::Table, vars: impl Iterator<Item = (String, String)>) {
+    for (name, raw) in vars {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let path: Vec<String> = key.split("__").map(|part| part.to_lowercase()).collect();
+        if path.iter().any(String::is_empty) {
+            continue;
+        }
+
+        set_path(table, &path, parse_value(raw));
+    }
+}
+
+// Read a value as TOML where possible and as a plain string otherwise, so
+// 15, true and [15, 5] get their types and Bose doesn't need quoting
+fn parse_value(raw: String) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut parsed| parsed.remove("value"))
+        .unwrap_or(toml::Value::String(raw))
+}
+
+// Set a nested key, creating (or replacing non-table values with) tables on the way
+fn set_path(table: &mut toml::Table, path: &[String], value: toml::Value) {
+    let Some((last, sections)) = path.split_last() else {
+        return;
+    };
+    let mut current = table;
+    for section in sections {
+        let entry = current
+            .entry(section.clone())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        if !entry.is_table() {
+            *entry = toml::Value::Table(toml::Table::new());
+        }
+        current = entry.as_table_mut().expect("just made a table");
+    }
+    current.insert(last.clone(), value);
+}
+
+fn get_path<'a>(table: &'a toml::Table, path: &[String]) -> Option<&'a toml::Value> {
+    let (first, rest) = path.split_first()?;
+    rest.iter().try_fold(table.get(first)?, |value, key| value.get(key))
+}
+
+// Split a dotted key such as notifications.webhook.url
+fn key_path(key: &str) -> Result<Vec<String>, String> {
+    let path: Vec<String> = key.split('.').map(str::to_string).collect();
+    if path.iter().any(String::is_empty) {
+        return Err(format!("invalid key '{}'", key));
+    }
+    Ok(path)
+}
+
+// Run every migration step from `version` up to CONFIG_VERSION
+fn migrate(table: &mut toml::Table, version: u32) {
+    for step in &MIGRATIONS[version as usize..] {
+        step(table);
+    }
+    table.insert("version".to_string(), toml::Value::Integer(CONFIG_VERSION.into()));
+}
+
+// Write the upgraded file back, keeping the original next to it
+fn save_migrated(path: &Path, original: &str, table: &toml::Table, version: u32) {
+    let backup = path.with_extension(format!("toml.v{}.bak", version));
+    let result = toml::to_string(table)
+        .map_err(std::io::Error::other)
+        .and_then(|migrated| {
+            std::fs::write(&backup, original)?;
+            std::fs::write(path, migrated)
+        });
+
+    match result {
+        Ok(()) => info!(
+            "Upgraded {} from version {} to {} (old file saved as {})",
+            path.display(),
+            version,
+            CONFIG_VERSION,
+            backup.display()
+        ),
+        Err(e) => warn!("Failed to save upgraded config to {}: {}", path.display(), e),
+    }
+}
+
+// Files from before the version key have the same layout as version 1
+fn migrate_v0_to_v1(_table: &mut toml::Table) {}
@@ -0,0 +1,153 @@
+use crate::cli::parse_duration;
+use crate::config::runtime_dir;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+const SOCKET_NAME: &str = "battery-monitor.sock";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ControlCommand {
+    List,
+    Refresh,
+    ShowDetails,
+    Stats,
+    // Whether the scanners are working, as JSON
+    Health,
+    Mute { device: String, duration: Option<Duration> },
+    Unmute(String),
+    SetThreshold { tier: ThresholdTier, value: u8 },
+    // Re-read the config file after `config set` or a manual edit
+    Reload,
+    // Dump the monitor's state to this file, or one in the data dir
+    Snapshot(Option<PathBuf>),
+    Shutdown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ThresholdTier {
+    Low,
+    Critical,
+}
+
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    pub reply: oneshot::Sender<String>,
+}
+
+impl ControlCommand {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut parts = line.split_whitespace();
+        let command = parts.next().ok_or("empty command")?;
+        let args: Vec<&str> = parts.collect();
+
+        match (command, args.as_slice()) {
+            ("list", []) => Ok(ControlCommand::List),
+            ("refresh", []) => Ok(ControlCommand::Refresh),
+            ("show-details", []) => Ok(ControlCommand::ShowDetails),
+            ("stats", []) => Ok(ControlCommand::Stats),
+            ("health", []) => Ok(ControlCommand::Health),
+            // Device names may contain spaces, so everything up to --for is the device
+            ("mute", [device @ .., "--for", duration]) if !device.is_empty() => Ok(ControlCommand::Mute {
+                device: device.join(" "),
+                duration: Some(parse_duration(duration)?),
+            }),
+            ("mute", device) if !device.is_empty() => Ok(ControlCommand::Mute {
+                device: device.join(" "),
+                duration: None,
+            }),
+            ("unmute", device) if !device.is_empty() => Ok(ControlCommand::Unmute(device.join(" "))),
+            ("set-threshold", [tier, value]) => {
+                let tier = match *tier {
+                    "low" => ThresholdTier::Low,
+                    "critical" => ThresholdTier::Critical,
+                    _ => return Err(format!("unknown threshold '{}', expected low or critical", tier)),
+                };
+                let value = value
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|v| *v <= 100)
+                    .ok_or_else(|| format!("invalid threshold '{}'", value))?;
+                Ok(ControlCommand::SetThreshold { tier, value })
+            }
+            ("reload", []) => Ok(ControlCommand::Reload),
+            ("snapshot", []) => Ok(ControlCommand::Snapshot(None)),
+            ("snapshot", [path]) => Ok(ControlCommand::Snapshot(Some(PathBuf::from(path)))),
+            ("shutdown", []) => Ok(ControlCommand::Shutdown),
+            _ => Err(format!("unknown command '{}'", line.trim())),
+        }
+    }
+}
+
+pub fn socket_path() -> PathBuf {
+    runtime_dir().join(SOCKET_NAME)
+}
+
+// Hand a command to the monitor loop and wait for its answer
+pub async fn request(sender: &mpsc::UnboundedSender<ControlRequest>, command: ControlCommand) -> String {
+    let (reply, response) = oneshot::channel();
+    if sender.send(ControlRequest { command, reply }).is_err() {
+        return "error: monitor is shutting down".to_string();
+    }
+    response.await.unwrap_or_else(|_| "error: no response".to_string())
+}
+
+// Bind the control socket and forward each parsed request to the monitor
+// loop through `sender`, which other front ends such as the HTTP API share
+pub fn start_server(sender: mpsc::UnboundedSender<ControlRequest>) -> std::io::Result<()> {
+    let path = socket_path();
+
+    // A leftover socket from a previous run would make bind fail
+    if path.exists() {
+        std::fs::remove_file(&path)?;
+    }
+
+    let listener = UnixListener::bind(&path)?;
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let sender = sender.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(stream, sender).await {
+                            warn!("Control connection error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Failed to accept control connection: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn handle_connection(stream: UnixStream, sender: mpsc::UnboundedSender<ControlRequest>) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+
+    let response = match ControlCommand::parse(&line) {
+        Ok(command) => request(&sender, command).await,
+        Err(e) => format!("error: {}", e),
+    };
+
+    writer.write_all(response.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.shutdown().await
+}
+
+// Send a single command to the running monitor and return its response
+pub async fn send_command(command: &str) -> std::io::Result<String> {
+    let mut stream = UnixStream::connect(socket_path()).await?;
+    stream.write_all(command.as_bytes()).await?;
+    stream.write_all(b"\n").await?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    Ok(response.trim_end().to_string())
+}
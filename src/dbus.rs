@@ -0,0 +1,161 @@
+use crate::scanner::MonitorHealth;
+use crate::status::DeviceStatus;
+use crate::thresholds::ThresholdCrossed;
+use tracing::info;
+use zbus::object_server::SignalEmitter;
+use zbus::{connection, interface, Connection};
+
+// Where the monitor can be found on the session bus, for shell extensions
+// and other desktop widgets
+pub const BUS_NAME: &str = "io.github.dreygur.BatteryMonitor";
+pub const OBJECT_PATH: &str = "/io/github/dreygur/BatteryMonitor";
+
+struct MonitorInterface {
+    devices: Vec<DeviceStatus>,
+    status_text: String,
+    bluetooth_available: bool,
+    health: MonitorHealth,
+}
+
+impl Default for MonitorInterface {
+    fn default() -> Self {
+        Self {
+            devices: Vec::new(),
+            status_text: String::new(),
+            bluetooth_available: true,
+            health: MonitorHealth::default(),
+        }
+    }
+}
+
+#[interface(name = "io.github.dreygur.BatteryMonitor1")]
+impl MonitorInterface {
+    // The device list as JSON, in the same format as `status --json`
+    fn get_devices(&self) -> String {
+        serde_json::to_string(&self.devices).unwrap_or_else(|_| "[]".to_string())
+    }
+
+    // The one-line summary that also goes to the status file
+    #[zbus(property)]
+    fn status_text(&self) -> String {
+        self.status_text.clone()
+    }
+
+    // False while there's no Bluetooth adapter, e.g. after it was unplugged
+    // or bluetoothd stopped
+    #[zbus(property)]
+    fn bluetooth_available(&self) -> bool {
+        self.bluetooth_available
+    }
+
+    // Scanner health as JSON, in the same format as `ctl health`
+    fn get_health(&self) -> String {
+        serde_json::to_string(&self.health).unwrap_or_else(|_| "{}".to_string())
+    }
+
+    // False while some scanner keeps failing
+    #[zbus(property)]
+    fn healthy(&self) -> bool {
+        self.health.is_healthy()
+    }
+
+    // Emitted when the Bluetooth adapter goes away or comes back
+    #[zbus(signal)]
+    async fn adapter_state_changed(emitter: &SignalEmitter<'_>, available: bool) -> zbus::Result<()>;
+
+    // Emitted when a device's battery drops to a configured level ("down")
+    // or charges back above it ("up")
+    #[zbus(signal)]
+    async fn threshold_crossed(
+        emitter: &SignalEmitter<'_>,
+        device_id: &str,
+        threshold: u8,
+        direction: &str,
+        battery: u8,
+    ) -> zbus::Result<()>;
+
+    // Emitted with the new JSON device list whenever the status changes
+    #[zbus(signal)]
+    async fn devices_changed(emitter: &SignalEmitter<'_>, devices: &str) -> zbus::Result<()>;
+}
+
+pub struct DbusService {
+    connection: Connection,
+}
+
+impl DbusService {
+    pub async fn start() -> zbus::Result<Self> {
+        let connection = connection::Builder::session()?
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, MonitorInterface::default())?
+            .build()
+            .await?;
+        info!("Serving device status on D-Bus as {}", BUS_NAME);
+        Ok(Self { connection })
+    }
+
+    pub async fn update(&self, devices: &[DeviceStatus], status_text: &str) -> zbus::Result<()> {
+        let interface = self
+            .connection
+            .object_server()
+            .interface::<_, MonitorInterface>(OBJECT_PATH)
+            .await?;
+        let emitter = interface.signal_emitter();
+
+        let mut monitor = interface.get_mut().await;
+        monitor.devices = devices.to_vec();
+        monitor.status_text = status_text.to_string();
+        monitor.status_text_changed(emitter).await?;
+        MonitorInterface::devices_changed(emitter, &monitor.get_devices()).await
+    }
+
+    pub async fn threshold_crossed(&self, crossing: &ThresholdCrossed) -> zbus::Result<()> {
+        let interface = self
+            .connection
+            .object_server()
+            .interface::<_, MonitorInterface>(OBJECT_PATH)
+            .await?;
+        MonitorInterface::threshold_crossed(
+            interface.signal_emitter(),
+            &crossing.device_id,
+            crossing.threshold,
+            crossing.direction.key(),
+            crossing.battery,
+        )
+        .await
+    }
+
+    pub async fn set_bluetooth_available(&self, available: bool) -> zbus::Result<()> {
+        let interface = self
+            .connection
+            .object_server()
+            .interface::<_, MonitorInterface>(OBJECT_PATH)
+            .await?;
+        let emitter = interface.signal_emitter();
+
+        let mut monitor = interface.get_mut().await;
+        if monitor.bluetooth_available == available {
+            return Ok(());
+        }
+        monitor.bluetooth_available = available;
+        monitor.bluetooth_available_changed(emitter).await?;
+        MonitorInterface::adapter_state_changed(emitter, available).await
+    }
+
+    pub async fn set_health(&self, health: &MonitorHealth) -> zbus::Result<()> {
+        let interface = self
+            .connection
+            .object_server()
+            .interface::<_, MonitorInterface>(OBJECT_PATH)
+            .await?;
+        let emitter = interface.signal_emitter();
+
+        let mut monitor = interface.get_mut().await;
+        let was_healthy = monitor.health.is_healthy();
+        monitor.health = health.clone();
+        if was_healthy != health.is_healthy() {
+            monitor.healthy_changed(emitter).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use crate::config::{config_path, Config};
+use crate::output;
+use crate::service::SERVICE_NAME;
+use crate::status::DeviceStatus;
+use std::fmt::Write;
+use std::path::Path;
+use std::process::Command;
+
+// Log lines included in a report
+const LOG_LINES: usize = 50;
+// Config keys that may hold credentials or private endpoints
+const REDACTED_KEYS: &[&[&str]] = &[&["mqtt", "username"], &["mqtt", "password"], &["notifications", "webhook", "url"]];
+
+// What the scanners and devices looked like when the report was made
+pub struct ScanResults {
+    // Where the results came from, e.g. the running monitor
+    pub source: &'static str,
+    pub scanners: serde_json::Value,
+    pub devices: Vec<DeviceStatus>,
+}
+
+// Everything a bug report needs in one paste: version and environment, the
+// config with secrets blanked out, what the scanners saw and the latest log lines
+pub fn report(config: &Config, scan: Result<ScanResults, String>, log_file: Option<&Path>) -> String {
+    let mut report = String::new();
+    let _ = writeln!(report, "battery_percentage {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(report, "Features: {}", enabled_features().join(", "));
+    for (name, value) in environment() {
+        let _ = writeln!(report, "{}: {}", name, value);
+    }
+
+    let _ = writeln!(report, "\n## Config ({})\n```toml", config_path().display());
+    match sanitized_config(config) {
+        Ok(config) => report.push_str(&config),
+        Err(e) => {
+            let _ = writeln!(report, "# couldn't serialize: {}", e);
+        }
+    }
+    report.push_str("```\n");
+
+    match scan {
+        Ok(scan) => {
+            let scanners = serde_json::to_string_pretty(&scan.scanners).unwrap_or_default();
+            let _ = writeln!(report, "\n## Scanners ({})\n```json\n{}\n```", scan.source, scanners);
+            let _ = writeln!(report, "\n## Devices\n```\n{}```", output::device_table(&scan.devices));
+        }
+        Err(e) => {
+            let _ = writeln!(report, "\n## Scanners\nScan failed: {}", e);
+        }
+    }
+
+    let _ = writeln!(report, "\n## Recent log\n```");
+    let lines = recent_log_lines(log_file);
+    if lines.is_empty() {
+        report.push_str("(no log found)\n");
+    }
+    for line in lines {
+        let _ = writeln!(report, "{}", line);
+    }
+    report.push_str("```\n");
+    report
+}
+
+fn enabled_features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "api") {
+        features.push("api");
+    }
+    if cfg!(feature = "tui") {
+        features.push("tui");
+    }
+    if cfg!(feature = "osd") {
+        features.push("osd");
+    }
+    if features.is_empty() {
+        features.push("none");
+    }
+    features
+}
+
+fn environment() -> Vec<(&'static str, String)> {
+    let os = std::fs::read_to_string("/etc/os-release")
+        .ok()
+        .and_then(|release| {
+            release
+                .lines()
+                .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+                .map(|name| name.trim_matches('"').to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    let kernel = std::fs::read_to_string("/proc/sys/kernel/osrelease")
+        .map(|release| release.trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+    let bluez = command_output("bluetoothd", &["--version"]).unwrap_or_else(|| "not found".to_string());
+    let desktop = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_else(|_| "unknown".to_string());
+    let session = std::env::var("XDG_SESSION_TYPE").unwrap_or_else(|_| "unknown".to_string());
+
+    vec![
+        ("OS", os),
+        ("Kernel", kernel),
+        ("BlueZ", bluez),
+        ("Desktop", format!("{} ({})", desktop, session)),
+    ]
+}
+
+fn sanitized_config(config: &Config) -> Result<String, Box<dyn std::error::Error>> {
+    let mut value = toml::Value::try_from(config)?;
+    for key in REDACTED_KEYS {
+        let (last, parents) = key.split_last().ok_or("empty key")?;
+        let table = parents.iter().try_fold(&mut value, |value, part| value.get_mut(*part));
+        if let Some(secret) = table.and_then(|table| table.get_mut(*last))
+            && secret.as_str().is_some_and(|secret| !secret.is_empty())
+        {
+            *secret = toml::Value::String("<redacted>".to_string());
+        }
+    }
+    Ok(toml::to_string(&value)?)
+}
+
+// The end of the --log-file log when there is one, otherwise the service's journal
+fn recent_log_lines(log_file: Option<&Path>) -> Vec<String> {
+    let contents = match log_file {
+        Some(path) => newest_log_file(path).and_then(|path| std::fs::read_to_string(path).ok()),
+        None => command_output(
+            "journalctl",
+            &["--user", "-u", SERVICE_NAME, "-n", &LOG_LINES.to_string(), "--no-pager", "-o", "short-iso"],
+        ),
+    };
+    let lines: Vec<String> = contents.unwrap_or_default().lines().map(str::to_string).collect();
+    lines[lines.len().saturating_sub(LOG_LINES)..].to_vec()
+}
+
+// The log file rotates daily into `<name>.<date>` files next to it
+fn newest_log_file(path: &Path) -> Option<std::path::PathBuf> {
+    let name = path.file_name()?.to_string_lossy().to_string();
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&name))
+        .max_by_key(|entry| entry.metadata().and_then(|metadata| metadata.modified()).ok())
+        .map(|entry| entry.path())
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
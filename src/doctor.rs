@@ -0,0 +1,316 @@
+use crate::config::{config_path, Config};
+use crate::notifications::portal::{self, DesktopBackend};
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::process::Command;
+use zbus::fdo::{DBusProxy, IntrospectableProxy};
+use zbus::names::BusName;
+use zbus::Connection;
+
+// Gives the logged-in user access to hidraw devices
+pub const HIDRAW_UDEV_RULE: &str = r#"KERNEL=="hidraw*", SUBSYSTEM=="hidraw", TAG+="uaccess""#;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    name: &'static str,
+    status: CheckStatus,
+    detail: String,
+    // What to do about a warning or failure
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn print(&self) {
+        let mark = match self.status {
+            CheckStatus::Pass => "✓",
+            CheckStatus::Warn => "!",
+            CheckStatus::Fail => "✗",
+        };
+        println!("{} {}: {}", mark, self.name, self.detail);
+        if let Some(hint) = &self.hint {
+            for line in hint.lines() {
+                println!("    {}", line);
+            }
+        }
+    }
+}
+
+// Check everything the monitor depends on and print what's wrong and how to
+// fix it. Returns whether nothing failed; warnings don't count.
+pub async fn run() -> bool {
+    let mut checks = vec![check_config()];
+
+    let system_bus = Connection::system().await;
+    checks.push(match &system_bus {
+        Ok(_) => Check::pass("System D-Bus", "reachable"),
+        Err(e) => Check::fail(
+            "System D-Bus",
+            format!("can't connect: {}", e),
+            "Bluetooth devices are read from BlueZ over the system bus; make sure dbus is running",
+        ),
+    });
+    if let Ok(connection) = &system_bus {
+        checks.push(check_bluez(connection).await);
+        checks.push(check_adapter().await);
+        checks.extend(check_battery_providers(connection).await);
+    }
+
+    checks.push(check_hidraw());
+    checks.push(check_notifications().await);
+
+    for check in &checks {
+        check.print();
+    }
+
+    let failed = checks.iter().filter(|check| check.status == CheckStatus::Fail).count();
+    let warned = checks.iter().filter(|check| check.status == CheckStatus::Warn).count();
+    println!();
+    match (failed, warned) {
+        (0, 0) => println!("Everything looks good"),
+        (0, _) => println!("{} warning(s), nothing blocking", warned),
+        _ => println!("{} problem(s) found, {} warning(s)", failed, warned),
+    }
+    failed == 0
+}
+
+fn check_config() -> Check {
+    let path = config_path();
+    if !path.exists() {
+        return Check::pass("Config", format!("{} doesn't exist, using defaults", path.display()));
+    }
+
+    match Config::load().map_err(|e| e.to_string()).and_then(|config| config.validate().map(|_| config)) {
+        Ok(_) => Check::pass("Config", format!("{} is valid", path.display())),
+        Err(e) => Check::fail(
+            "Config",
+            format!("{}: {}", path.display(), e),
+            "Fix the value, or check it with `battery_percentage config get <key>`",
+        ),
+    }
+}
+
+async fn name_has_owner(connection: &Connection, name: &str) -> zbus::Result<bool> {
+    let proxy = DBusProxy::new(connection).await?;
+    Ok(proxy.name_has_owner(BusName::try_from(name)?).await?)
+}
+
+async fn check_bluez(connection: &Connection) -> Check {
+    let version = Command::new("bluetoothd")
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+
+    match name_has_owner(connection, "org.bluez").await {
+        Ok(true) => Check::pass("BlueZ", format!("running, version {}", version.as_deref().unwrap_or("unknown"))),
+        Ok(false) if version.is_some() => Check::fail(
+            "BlueZ",
+            "installed but bluetoothd isn't running",
+            "sudo systemctl enable --now bluetooth",
+        ),
+        Ok(false) => Check::fail("BlueZ", "not installed", "Install BlueZ, e.g. `sudo apt install bluez`"),
+        Err(e) if portal::is_sandboxed() => Check::fail(
+            "BlueZ",
+            format!("can't query the system bus: {}", e),
+            "The sandbox needs access to BlueZ, e.g. `flatpak override --user --system-talk-name=org.bluez <app id>`",
+        ),
+        Err(e) => Check::fail("BlueZ", format!("can't query the system bus: {}", e), "Check that dbus is running"),
+    }
+}
+
+async fn check_adapter() -> Check {
+    let adapter = match bluer::Session::new().await {
+        Ok(session) => session.default_adapter().await,
+        Err(e) => Err(e),
+    };
+    let adapter = match adapter {
+        Ok(adapter) => adapter,
+        Err(e) => {
+            return Check::fail(
+                "Bluetooth adapter",
+                format!("none found: {}", e),
+                "Plug in a Bluetooth adapter, or check `rfkill list` for a blocked one",
+            );
+        }
+    };
+
+    match adapter.is_powered().await {
+        Ok(true) => Check::pass("Bluetooth adapter", format!("{} is powered on", adapter.name())),
+        Ok(false) => Check::warn(
+            "Bluetooth adapter",
+            format!("{} is powered off", adapter.name()),
+            "The monitor powers it on at startup; if that fails, try `rfkill unblock bluetooth`",
+        ),
+        Err(e) => Check::fail(
+            "Bluetooth adapter",
+            format!("can't read {}: {}", adapter.name(), e),
+            "Restart bluetoothd: sudo systemctl restart bluetooth",
+        ),
+    }
+}
+
+// Headsets that only report their battery through HFP AT commands
+// (+XAPL/+IPHONEACCEV) get it through PipeWire, which hands it to BlueZ as a
+// battery provider, and from there it shows up like any other Battery1
+// level. BlueZ only takes providers with its experimental features on.
+// None without an adapter, which check_adapter already complains about.
+async fn check_battery_providers(connection: &Connection) -> Option<Check> {
+    let adapter = bluer::Session::new().await.ok()?.default_adapter().await.ok()?;
+    let path = format!("/org/bluez/{}", adapter.name());
+    let introspection = async {
+        IntrospectableProxy::builder(connection)
+            .destination("org.bluez")?
+            .path(path.as_str())?
+            .build()
+            .await?
+            .introspect()
+            .await
+            .map_err(zbus::Error::from)
+    };
+
+    Some(match introspection.await {
+        Ok(xml) if xml.contains("org.bluez.BatteryProviderManager1") => {
+            Check::pass("HFP headset batteries", "BlueZ accepts battery providers")
+        }
+        Ok(_) => Check::warn(
+            "HFP headset batteries",
+            "BlueZ runs without experimental features, so levels headsets only report over HFP are dropped",
+            "Set `Experimental = true` under [General] in /etc/bluetooth/main.conf and restart bluetooth",
+        ),
+        Err(e) => Check::warn(
+            "HFP headset batteries",
+            format!("can't inspect {}: {}", path, e),
+            "Restart bluetoothd: sudo systemctl restart bluetooth",
+        ),
+    })
+}
+
+// Keyboard and dongle batteries are read over hidraw, which is root-only
+// unless a udev rule hands the devices to the logged-in user
+fn check_hidraw() -> Check {
+    let devices: Vec<_> = std::fs::read_dir("/dev")
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.file_name().is_some_and(|name| name.to_string_lossy().starts_with("hidraw")))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if devices.is_empty() {
+        return Check::warn(
+            "HID access",
+            "no /dev/hidraw* devices",
+            "Only needed for USB keyboards and wireless dongles; plug one in to check access",
+        );
+    }
+
+    let denied: Vec<&Path> = devices
+        .iter()
+        .filter(|path| OpenOptions::new().read(true).write(true).open(path).is_err())
+        .map(|path| path.as_path())
+        .collect();
+    if denied.is_empty() {
+        return Check::pass("HID access", format!("{} hidraw device(s) readable", devices.len()));
+    }
+
+    let names = denied.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ");
+    Check::warn(
+        "HID access",
+        format!("no permission for {}", names),
+        format!(
+            "Keyboard batteries can't be read. Add a udev rule, e.g. in {}:\n  {}\n\
+             then run `sudo udevadm control --reload && sudo udevadm trigger` and replug",
+            "/etc/udev/rules.d/70-battery-monitor.rules", HIDRAW_UDEV_RULE
+        ),
+    )
+}
+
+async fn check_notifications() -> Check {
+    if Config::load_or_default().notifications.desktop_backend.resolve() == DesktopBackend::Portal {
+        return check_notification_portal().await;
+    }
+
+    let has_notify_send = Command::new("notify-send").arg("--version").output().is_ok();
+    if !has_notify_send {
+        return Check::fail(
+            "Notifications",
+            "notify-send not found",
+            "Install libnotify, e.g. `sudo apt install libnotify-bin`",
+        );
+    }
+
+    let daemon = match Connection::session().await {
+        Ok(connection) => name_has_owner(&connection, "org.freedesktop.Notifications").await,
+        Err(e) => Err(e),
+    };
+    match daemon {
+        Ok(true) => Check::pass("Notifications", "notification daemon is running"),
+        Ok(false) => Check::warn(
+            "Notifications",
+            "no notification daemon on the session bus",
+            "Desktop alerts won't show; start one (mako, dunst, your desktop's own) or use another [notifications] sink",
+        ),
+        Err(e) => Check::warn(
+            "Notifications",
+            format!("can't reach the session bus: {}", e),
+            "Desktop alerts need a graphical session; other [notifications] sinks still work",
+        ),
+    }
+}
+
+async fn check_notification_portal() -> Check {
+    let reason = if portal::is_sandboxed() { "sandboxed" } else { "desktop_backend = \"portal\"" };
+    let available = match Connection::session().await {
+        Ok(connection) => name_has_owner(&connection, portal::PORTAL_NAME).await,
+        Err(e) => Err(e),
+    };
+    match available {
+        Ok(true) => Check::pass("Notifications", format!("{}, using the desktop portal", reason)),
+        Ok(false) => Check::warn(
+            "Notifications",
+            format!("{}, but no desktop portal on the session bus", reason),
+            "Install xdg-desktop-portal and a backend for your desktop, or set desktop_backend = \"notify_send\"",
+        ),
+        Err(e) => Check::warn(
+            "Notifications",
+            format!("can't reach the session bus: {}", e),
+            "Desktop alerts need a graphical session; other [notifications] sinks still work",
+        ),
+    }
+}
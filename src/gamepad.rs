@@ -0,0 +1,200 @@
+use crate::bluetooth::BatteryComponent;
+use crate::hid::ControllerProtocol;
+use crate::power_supply::{is_mac_address, read_attribute, POWER_SUPPLY_DIR};
+use crate::scanner::DeviceScanner;
+use crate::status::{DeviceStatus, Transport};
+use futures::future::LocalBoxFuture;
+use hidapi::{BusType, DeviceInfo, HidApi};
+use std::fs;
+use std::path::Path;
+use tracing::{debug, warn};
+
+// power_supply names the kernel's controller drivers use, each followed by
+// the controller's MAC address: hid-sony (DualShock 3/4), hid-playstation
+// (DualSense) and hid-nintendo (Switch Pro Controller, Joy-Cons)
+const CONTROLLER_SUPPLY_PREFIXES: [&str; 3] = [
+    "sony_controller_battery_",
+    "ps-controller-battery-",
+    "nintendo_switch_controller_battery_",
+];
+
+// Game controllers from their kernel power_supply entries, or read over
+// hidraw for controllers whose kernel doesn't report their battery.
+// Controllers that report through BlueZ instead (Xbox over Bluetooth) are
+// picked up as Bluetooth devices; with the same MAC both end up as one device.
+#[derive(Default)]
+pub struct GamepadScanner {
+    // Opened on the first scan
+    hid_api: Option<HidApi>,
+}
+
+impl DeviceScanner for GamepadScanner {
+    fn name(&self) -> &'static str {
+        "gamepad"
+    }
+
+    fn scan(&mut self) -> LocalBoxFuture<'_, Result<Vec<DeviceStatus>, Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            let mut devices = scan_controllers(Path::new(POWER_SUPPLY_DIR))?;
+            // Reading a report waits on the controller, so HID runs off the event loop
+            let known: Vec<String> = devices.iter().map(|device| device.id.clone()).collect();
+            let hid_api = self.hid_api.take();
+            let (hid_api, hid_devices) = tokio::task::spawn_blocking(move || scan_hid(hid_api, &known)).await?;
+            self.hid_api = hid_api;
+            devices.extend(hid_devices);
+            devices.sort_by(|a, b| a.id.cmp(&b.id));
+            Ok(devices)
+        })
+    }
+}
+
+// Controllers the HID protocols know that power_supply didn't report, read
+// directly; none without HID access. Hands back the HidApi to reuse next scan.
+fn scan_hid(hid_api: Option<HidApi>, known: &[String]) -> (Option<HidApi>, Vec<DeviceStatus>) {
+    let hid_api = match hid_api {
+        Some(mut hid_api) => {
+            if let Err(e) = hid_api.refresh_devices() {
+                warn!("Failed to list HID devices: {}", e);
+            }
+            hid_api
+        }
+        None => match HidApi::new() {
+            Ok(hid_api) => hid_api,
+            Err(e) => {
+                debug!("Can't read controllers over HID: {}", e);
+                return (None, Vec::new());
+            }
+        },
+    };
+
+    let mut devices: Vec<DeviceStatus> = Vec::new();
+    for info in hid_api.device_list() {
+        // A controller shows up once per interface
+        let id = controller_id(info);
+        if known.contains(&id) || devices.iter().any(|device| device.id == id) {
+            continue;
+        }
+        devices.extend(read_hid_controller(&hid_api, info, id));
+    }
+    (Some(hid_api), devices)
+}
+
+// Bluetooth controllers report their MAC as the serial number, which
+// matches the power_supply entry and BlueZ
+fn controller_id(info: &DeviceInfo) -> String {
+    info.serial_number()
+        .filter(|serial| is_mac_address(serial))
+        .map(str::to_uppercase)
+        .unwrap_or_else(|| format!("{:04x}:{:04x}", info.vendor_id(), info.product_id()))
+}
+
+fn read_hid_controller(hid_api: &HidApi, info: &DeviceInfo, id: String) -> Option<DeviceStatus> {
+    let protocol = ControllerProtocol::detect(info.vendor_id(), info.product_id())?;
+    let name = info
+        .product_string()
+        .filter(|name| !name.is_empty())
+        .unwrap_or(protocol.name(info.product_id()))
+        .to_string();
+    let over_bluetooth = info.bus_type() == BusType::Bluetooth;
+
+    let device = match hid_api.open_path(info.path()) {
+        Ok(device) => device,
+        Err(e) => {
+            debug!("Failed to open {}: {}", name, e);
+            return None;
+        }
+    };
+    let battery = match protocol.query_battery(&device, info.product_id(), over_bluetooth) {
+        Ok(battery) => battery?,
+        Err(e) => {
+            warn!("Failed to read the battery of {}: {}", name, e);
+            return None;
+        }
+    };
+
+    Some(DeviceStatus {
+        id,
+        name,
+        device_type: "Gamepad".to_string(),
+        transport: if over_bluetooth { Transport::Bluetooth } else { Transport::Usb },
+        extra_transports: Vec::new(),
+        battery_percentage: Some(battery.level),
+        batteries: vec![BatteryComponent {
+            label: "Battery".to_string(),
+            level: battery.level,
+            charging: battery.charging,
+        }],
+        rssi: None,
+        icon: "🎮".to_string(),
+        stale: false,
+    })
+}
+
+pub fn is_controller_supply(supply_name: &str) -> bool {
+    CONTROLLER_SUPPLY_PREFIXES.iter().any(|prefix| supply_name.starts_with(prefix))
+}
+
+fn scan_controllers(dir: &Path) -> Result<Vec<DeviceStatus>, Box<dyn std::error::Error>> {
+    let mut devices = Vec::new();
+
+    for entry in fs::read_dir(dir)?.flatten() {
+        let supply_name = entry.file_name().to_string_lossy().to_string();
+        let Some(address) = controller_address(&supply_name) else {
+            continue;
+        };
+        let path = entry.path();
+
+        // Some drivers only give a coarse capacity_level
+        let battery = read_attribute(&path, "capacity")
+            .and_then(|c| c.parse::<u8>().ok())
+            .or_else(|| read_attribute(&path, "capacity_level").and_then(|level| capacity_from_level(&level)));
+
+        // The same entry exists whether the controller is plugged in or paired
+        let over_bluetooth = fs::canonicalize(path.join("device"))
+            .map(|device| device.to_string_lossy().contains("/bluetooth/"))
+            .unwrap_or(false);
+
+        devices.push(DeviceStatus {
+            id: address,
+            name: read_attribute(&path, "model_name").unwrap_or_else(|| controller_name(&supply_name).to_string()),
+            device_type: "Gamepad".to_string(),
+            transport: if over_bluetooth { Transport::Bluetooth } else { Transport::Usb },
+            extra_transports: Vec::new(),
+            battery_percentage: battery.map(|b| b.min(100)),
+            batteries: Vec::new(),
+            rssi: None,
+            icon: "🎮".to_string(),
+            stale: false,
+        });
+    }
+
+    devices.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(devices)
+}
+
+fn controller_address(supply_name: &str) -> Option<String> {
+    let address = CONTROLLER_SUPPLY_PREFIXES
+        .iter()
+        .find_map(|prefix| supply_name.strip_prefix(prefix))?;
+    is_mac_address(address).then(|| address.to_uppercase())
+}
+
+// Fallback name when the driver sets no model_name
+fn controller_name(supply_name: &str) -> &'static str {
+    if supply_name.starts_with("nintendo") {
+        "Switch Controller"
+    } else {
+        "PlayStation Controller"
+    }
+}
+
+fn capacity_from_level(level: &str) -> Option<u8> {
+    match level {
+        "Full" => Some(100),
+        "High" => Some(80),
+        "Normal" => Some(50),
+        "Low" => Some(20),
+        "Critical" => Some(5),
+        _ => None,
+    }
+}
@@ -0,0 +1,70 @@
+use hidapi::{DeviceInfo, HidDevice};
+
+const CORSAIR_VENDOR_ID: u16 = 0x1b1c;
+
+// Slipstream receivers speak Corsair's "Bragi" protocol
+const SLIPSTREAM_PRODUCT_IDS: &[u16] = &[
+    0x1ba6, // Slipstream receiver (Dark Core RGB Pro)
+    0x1bdc, // Slipstream receiver (Sabre/Katar/M65 RGB Ultra)
+    0x2b00, // Slipstream receiver (HS80/Virtuoso)
+];
+
+const REPORT_LEN: usize = 65;
+// The receiver itself is device 0x08, the first paired device is 0x09
+const PAIRED_DEVICE: u8 = 0x09;
+const COMMAND_GET: u8 = 0x02;
+const PROPERTY_BATTERY_LEVEL: u8 = 0x0f;
+
+pub fn supports(vendor_id: u16, product_id: u16) -> bool {
+    vendor_id == CORSAIR_VENDOR_ID && SLIPSTREAM_PRODUCT_IDS.contains(&product_id)
+}
+
+pub fn is_control_interface(device_info: &DeviceInfo) -> bool {
+    device_info.usage_page() == 0xff42
+}
+
+pub fn query_battery(device: &HidDevice) -> Result<Option<u8>, Box<dyn std::error::Error>> {
+    let mut request = [0u8; REPORT_LEN];
+    request[1] = PAIRED_DEVICE;
+    request[2] = COMMAND_GET;
+    request[3] = PROPERTY_BATTERY_LEVEL;
+    device.write(&request)?;
+
+    let mut response = [0u8; REPORT_LEN];
+    let size = device.read_timeout(&mut response, 500)?;
+    Ok(parse_battery(&response[..size]))
+}
+
+// The level from a battery reply; None for a short read or a failed query
+fn parse_battery(response: &[u8]) -> Option<u8> {
+    // Responses echo the device and command, then a status byte and the
+    // value. A non-zero status usually means the mouse is asleep.
+    if response.len() < 5 || response[1] != COMMAND_GET || response[2] != 0x00 {
+        return None;
+    }
+
+    // Battery level comes back in tenths of a percent
+    let level = u16::from_le_bytes([response[3], response[4]]) / 10;
+    Some(level.min(100) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_tenths_of_a_percent() {
+        // 0x0302 is 770 tenths: 77%
+        assert_eq!(parse_battery(&[0x00, COMMAND_GET, 0x00, 0x02, 0x03, 0x00]), Some(77));
+        assert_eq!(parse_battery(&[0x00, COMMAND_GET, 0x00, 0xe8, 0x03]), Some(100));
+        // Readings past 100% are capped
+        assert_eq!(parse_battery(&[0x00, COMMAND_GET, 0x00, 0xff, 0xff]), Some(100));
+    }
+
+    #[test]
+    fn rejects_short_reads_and_sleeping_mice() {
+        assert_eq!(parse_battery(&[0x00, COMMAND_GET, 0x00, 0x02]), None);
+        assert_eq!(parse_battery(&[0x00, COMMAND_GET, 0x03, 0x02, 0x03]), None);
+        assert_eq!(parse_battery(&[0x00, 0x01, 0x00, 0x02, 0x03]), None);
+    }
+}
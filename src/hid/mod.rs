@@ -0,0 +1,95 @@
+// Battery protocols for 2.4 GHz gaming dongles, which don't show up in
+// BlueZ or the kernel's power_supply class, and for game controllers
+// without a kernel driver that reports their battery
+pub mod corsair;
+pub mod nintendo;
+pub mod playstation;
+pub mod probe;
+pub mod razer;
+pub mod steelseries;
+
+use hidapi::{DeviceInfo, HidDevice};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DongleProtocol {
+    Razer,
+    SteelSeries,
+    Corsair,
+}
+
+impl DongleProtocol {
+    // Pick the protocol for a dongle by vendor and product id
+    pub fn detect(vendor_id: u16, product_id: u16) -> Option<Self> {
+        if razer::supports(vendor_id, product_id) {
+            Some(DongleProtocol::Razer)
+        } else if steelseries::supports(vendor_id, product_id) {
+            Some(DongleProtocol::SteelSeries)
+        } else if corsair::supports(vendor_id, product_id) {
+            Some(DongleProtocol::Corsair)
+        } else {
+            None
+        }
+    }
+
+    // Dongles expose several HID interfaces; only one of them answers battery queries
+    pub fn is_control_interface(&self, device_info: &DeviceInfo) -> bool {
+        match self {
+            DongleProtocol::Razer => razer::is_control_interface(device_info),
+            DongleProtocol::SteelSeries => steelseries::is_control_interface(device_info),
+            DongleProtocol::Corsair => corsair::is_control_interface(device_info),
+        }
+    }
+
+    pub fn query_battery(&self, device: &HidDevice, product_id: u16) -> Result<Option<u8>, Box<dyn std::error::Error>> {
+        match self {
+            DongleProtocol::Razer => razer::query_battery(device),
+            DongleProtocol::SteelSeries => steelseries::query_battery(device, product_id),
+            DongleProtocol::Corsair => corsair::query_battery(device),
+        }
+    }
+}
+
+// A controller's battery as its own input reports give it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ControllerBattery {
+    pub level: u8,
+    pub charging: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControllerProtocol {
+    Nintendo,
+    PlayStation,
+}
+
+impl ControllerProtocol {
+    pub fn detect(vendor_id: u16, product_id: u16) -> Option<Self> {
+        if nintendo::supports(vendor_id, product_id) {
+            Some(ControllerProtocol::Nintendo)
+        } else if playstation::supports(vendor_id, product_id) {
+            Some(ControllerProtocol::PlayStation)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self, product_id: u16) -> &'static str {
+        match self {
+            ControllerProtocol::Nintendo => nintendo::name(product_id),
+            ControllerProtocol::PlayStation => playstation::name(product_id),
+        }
+    }
+
+    pub fn query_battery(
+        &self,
+        device: &HidDevice,
+        product_id: u16,
+        bluetooth: bool,
+    ) -> Result<Option<ControllerBattery>, Box<dyn std::error::Error>> {
+        match self {
+            ControllerProtocol::Nintendo if bluetooth => nintendo::query_battery(device),
+            ControllerProtocol::Nintendo => Ok(None),
+            ControllerProtocol::PlayStation => playstation::query_battery(device, product_id, bluetooth),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use super::ControllerBattery;
+use hidapi::HidDevice;
+
+const NINTENDO_VENDOR_ID: u16 = 0x057e;
+const JOYCON_LEFT: u16 = 0x2006;
+const JOYCON_RIGHT: u16 = 0x2007;
+const PRO_CONTROLLER: u16 = 0x2009;
+
+// Output report carrying a rumble frame and a subcommand
+const SUBCOMMAND_REPORT: u8 = 0x01;
+// Input report answering a subcommand
+const SUBCOMMAND_REPLY: u8 = 0x21;
+// Full-mode input report, sent at 60 Hz once something switched the controller to it
+const STANDARD_REPORT: u8 = 0x30;
+// Get regulated voltage; any subcommand would do, this one has no side effects
+const SUBCOMMAND_GET_VOLTAGE: u8 = 0x50;
+// Rumble data that leaves the motors idle
+const NEUTRAL_RUMBLE: [u8; 8] = [0x00, 0x01, 0x40, 0x40, 0x00, 0x01, 0x40, 0x40];
+const REPORT_LEN: usize = 64;
+const READ_TIMEOUT_MS: i32 = 500;
+// Controllers in full mode stream reports, so give up after this many
+const MAX_REPORTS: usize = 16;
+
+pub fn supports(vendor_id: u16, product_id: u16) -> bool {
+    vendor_id == NINTENDO_VENDOR_ID && [JOYCON_LEFT, JOYCON_RIGHT, PRO_CONTROLLER].contains(&product_id)
+}
+
+pub fn name(product_id: u16) -> &'static str {
+    match product_id {
+        JOYCON_LEFT => "Joy-Con (L)",
+        JOYCON_RIGHT => "Joy-Con (R)",
+        _ => "Pro Controller",
+    }
+}
+
+// Only over Bluetooth; a Pro Controller on USB needs a handshake first and
+// charges anyway
+pub fn query_battery(device: &HidDevice) -> Result<Option<ControllerBattery>, Box<dyn std::error::Error>> {
+    let mut request = vec![SUBCOMMAND_REPORT, 0x00];
+    request.extend(NEUTRAL_RUMBLE);
+    request.push(SUBCOMMAND_GET_VOLTAGE);
+    device.write(&request)?;
+
+    let mut report = [0u8; REPORT_LEN];
+    for _ in 0..MAX_REPORTS {
+        let size = device.read_timeout(&mut report, READ_TIMEOUT_MS)?;
+        if size == 0 {
+            return Ok(None);
+        }
+        // Every subcommand reply and full-mode report starts with the battery
+        let answers = match report[0] {
+            SUBCOMMAND_REPLY => size > 14 && report[14] == SUBCOMMAND_GET_VOLTAGE,
+            STANDARD_REPORT => size > 2,
+            _ => false,
+        };
+        if answers {
+            return Ok(Some(decode_battery(report[2])));
+        }
+    }
+    Ok(None)
+}
+
+// High nibble of the third byte: level 0-8 in steps of 2 (empty, critical,
+// low, medium, full) and a charging bit
+fn decode_battery(byte: u8) -> ControllerBattery {
+    let level = (byte >> 5).min(4);
+    ControllerBattery {
+        level: level * 25,
+        charging: byte & 0x10 != 0,
+    }
+}
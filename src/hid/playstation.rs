@@ -0,0 +1,204 @@
+use super::ControllerBattery;
+use hidapi::HidDevice;
+
+const SONY_VENDOR_ID: u16 = 0x054c;
+const DUALSHOCK4_PRODUCT_IDS: &[u16] = &[
+    0x05c4, // DualShock 4
+    0x09cc, // DualShock 4 (second generation)
+    0x0ba0, // DualShock 4 USB wireless adapter
+];
+const DUALSENSE_PRODUCT_IDS: &[u16] = &[
+    0x0ce6, // DualSense
+    0x0df2, // DualSense Edge
+];
+
+// Full input reports: 0x01 on USB, 0x11 (DualShock 4) and 0x31 (DualSense)
+// over Bluetooth
+const USB_REPORT: u8 = 0x01;
+const DUALSHOCK4_BT_REPORT: u8 = 0x11;
+const DUALSENSE_BT_REPORT: u8 = 0x31;
+// Reading the calibration feature report switches a Bluetooth controller
+// from its reduced reports, which carry no battery, to the full ones
+const CALIBRATION_REPORT: u8 = 0x05;
+const REPORT_LEN: usize = 78;
+const READ_TIMEOUT_MS: i32 = 500;
+const MAX_REPORTS: usize = 16;
+
+// Offset of the status byte, report id included
+const DUALSHOCK4_USB_STATUS: usize = 30;
+const DUALSHOCK4_BT_STATUS: usize = 32;
+const DUALSENSE_USB_STATUS: usize = 53;
+const DUALSENSE_BT_STATUS: usize = 54;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Model {
+    DualShock4,
+    DualSense,
+}
+
+fn model(product_id: u16) -> Option<Model> {
+    if DUALSHOCK4_PRODUCT_IDS.contains(&product_id) {
+        Some(Model::DualShock4)
+    } else if DUALSENSE_PRODUCT_IDS.contains(&product_id) {
+        Some(Model::DualSense)
+    } else {
+        None
+    }
+}
+
+pub fn supports(vendor_id: u16, product_id: u16) -> bool {
+    vendor_id == SONY_VENDOR_ID && model(product_id).is_some()
+}
+
+pub fn name(product_id: u16) -> &'static str {
+    match model(product_id) {
+        Some(Model::DualSense) => "DualSense Wireless Controller",
+        _ => "DualShock 4 Wireless Controller",
+    }
+}
+
+// Controllers stream input reports constantly, so this is just a matter of
+// waiting for a full one
+pub fn query_battery(
+    device: &HidDevice,
+    product_id: u16,
+    bluetooth: bool,
+) -> Result<Option<ControllerBattery>, Box<dyn std::error::Error>> {
+    let Some(model) = model(product_id) else {
+        return Ok(None);
+    };
+    if bluetooth {
+        let mut calibration = [0u8; REPORT_LEN];
+        calibration[0] = CALIBRATION_REPORT;
+        // Already in full mode if this fails, e.g. with a driver bound
+        let _ = device.get_feature_report(&mut calibration);
+    }
+
+    let mut report = [0u8; REPORT_LEN];
+    for _ in 0..MAX_REPORTS {
+        let size = device.read_timeout(&mut report, READ_TIMEOUT_MS)?;
+        if size == 0 {
+            return Ok(None);
+        }
+        if let Some(battery) = parse_report(model, &report[..size]) {
+            return Ok(Some(battery));
+        }
+    }
+    Ok(None)
+}
+
+fn parse_report(model: Model, report: &[u8]) -> Option<ControllerBattery> {
+    let offset = match (model, *report.first()?) {
+        (Model::DualShock4, USB_REPORT) => DUALSHOCK4_USB_STATUS,
+        (Model::DualShock4, DUALSHOCK4_BT_REPORT) => DUALSHOCK4_BT_STATUS,
+        (Model::DualSense, USB_REPORT) => DUALSENSE_USB_STATUS,
+        (Model::DualSense, DUALSENSE_BT_REPORT) => DUALSENSE_BT_STATUS,
+        _ => return None,
+    };
+    let status = *report.get(offset)?;
+    match model {
+        Model::DualShock4 => decode_dualshock4(status),
+        Model::DualSense => decode_dualsense(status),
+    }
+}
+
+// Low nibble: level 0-10 in tens. Bit 4: cable plugged in, in which case
+// 11 means fully charged
+fn decode_dualshock4(status: u8) -> Option<ControllerBattery> {
+    let level = status & 0x0f;
+    let plugged_in = status & 0x10 != 0;
+    match (plugged_in, level) {
+        (true, 0..=9) => Some(ControllerBattery {
+            level: level * 10 + 5,
+            charging: true,
+        }),
+        (true, 10 | 11) => Some(ControllerBattery {
+            level: 100,
+            charging: false,
+        }),
+        // Anything else is a charging error
+        (true, _) => None,
+        (false, _) => Some(ControllerBattery {
+            level: (level.min(10) * 10 + 5).min(100),
+            charging: false,
+        }),
+    }
+}
+
+// Low nibble: level 0-10 in tens. High nibble: 0 discharging, 1 charging,
+// 2 full, anything else an error
+fn decode_dualsense(status: u8) -> Option<ControllerBattery> {
+    let level = ((status & 0x0f).min(10) * 10 + 5).min(100);
+    match status >> 4 {
+        0 => Some(ControllerBattery { level, charging: false }),
+        1 => Some(ControllerBattery { level, charging: true }),
+        2 => Some(ControllerBattery {
+            level: 100,
+            charging: false,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An input report with this id and the status byte where the controller puts it
+    fn report(id: u8, offset: usize, status: u8) -> [u8; REPORT_LEN] {
+        let mut report = [0u8; REPORT_LEN];
+        report[0] = id;
+        report[offset] = status;
+        report
+    }
+
+    fn battery(level: u8, charging: bool) -> Option<ControllerBattery> {
+        Some(ControllerBattery { level, charging })
+    }
+
+    #[test]
+    fn reads_dualshock4_over_usb_and_bluetooth() {
+        // Level 7 on battery, then charging at level 3
+        let usb = report(USB_REPORT, DUALSHOCK4_USB_STATUS, 0x07);
+        assert_eq!(parse_report(Model::DualShock4, &usb), battery(75, false));
+        let bluetooth = report(DUALSHOCK4_BT_REPORT, DUALSHOCK4_BT_STATUS, 0x13);
+        assert_eq!(parse_report(Model::DualShock4, &bluetooth), battery(35, true));
+        // The Bluetooth offset is two bytes later than on USB
+        let misplaced = report(DUALSHOCK4_BT_REPORT, DUALSHOCK4_USB_STATUS, 0x13);
+        assert_eq!(parse_report(Model::DualShock4, &misplaced), battery(5, false));
+    }
+
+    #[test]
+    fn reads_dualshock4_charge_states() {
+        assert_eq!(decode_dualshock4(0x1b), battery(100, false));
+        assert_eq!(decode_dualshock4(0x0a), battery(100, false));
+        assert_eq!(decode_dualshock4(0x00), battery(5, false));
+        // Plugged in with a level past 11 is a charging error
+        assert_eq!(decode_dualshock4(0x1e), None);
+    }
+
+    #[test]
+    fn reads_dualsense_over_usb_and_bluetooth() {
+        // Level 6 discharging, then charging at level 2
+        let usb = report(USB_REPORT, DUALSENSE_USB_STATUS, 0x06);
+        assert_eq!(parse_report(Model::DualSense, &usb), battery(65, false));
+        let bluetooth = report(DUALSENSE_BT_REPORT, DUALSENSE_BT_STATUS, 0x12);
+        assert_eq!(parse_report(Model::DualSense, &bluetooth), battery(25, true));
+        // Fully charged, and a charging error
+        assert_eq!(decode_dualsense(0x2a), battery(100, false));
+        assert_eq!(decode_dualsense(0xf3), None);
+    }
+
+    #[test]
+    fn ignores_reduced_short_and_other_reports() {
+        // The reduced Bluetooth report also has id 0x01 but stops before the battery
+        let reduced = report(USB_REPORT, DUALSHOCK4_USB_STATUS, 0x07);
+        assert_eq!(parse_report(Model::DualShock4, &reduced[..10]), None);
+        // Each model only knows its own Bluetooth report id
+        let dualsense = report(DUALSENSE_BT_REPORT, DUALSHOCK4_BT_STATUS, 0x07);
+        assert_eq!(parse_report(Model::DualShock4, &dualsense), None);
+        let short = report(DUALSENSE_BT_REPORT, DUALSENSE_BT_STATUS, 0x07);
+        assert_eq!(parse_report(Model::DualSense, &short[..DUALSENSE_BT_STATUS]), None);
+        assert_eq!(parse_report(Model::DualSense, &[]), None);
+    }
+}
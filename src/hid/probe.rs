@@ -0,0 +1,170 @@
+use hidapi::HidDevice;
+use serde::Deserialize;
+use std::time::Duration;
+
+const REPORT_LEN: usize = 65;
+// How long to wait for an input report answering a probe
+const READ_TIMEOUT_MS: i32 = 500;
+// Unrelated input reports (key presses, mouse movement) to skip while waiting
+const MAX_UNMATCHED_REPORTS: usize = 8;
+
+// How a probe's request is sent
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteKind {
+    #[default]
+    Output,
+    Feature,
+}
+
+// Where a probe's answer comes from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadKind {
+    #[default]
+    Feature,
+    Input,
+}
+
+// A battery query described in a quirks file: optionally send a request,
+// then read a report and take the level from it
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct HidProbe {
+    pub report_id: u8,
+    // Request bytes after the report id; nothing is sent when empty
+    pub write: Vec<u8>,
+    pub write_kind: WriteKind,
+    pub read: ReadKind,
+    // Pause between request and read, for slow firmware
+    pub delay_ms: u64,
+    // Only accept answers starting with these bytes, report id included
+    pub expect: Vec<u8>,
+    // Byte of the answer holding the level, counting the report id as 0
+    pub offset: usize,
+    // 2 for a little-endian 16-bit value
+    pub width: Option<usize>,
+    // Multiply the raw value, e.g. 14 for a 0-7 gauge or 0.1 for tenths of a percent
+    pub scale: Option<f32>,
+}
+
+impl HidProbe {
+    pub fn run(&self, device: &HidDevice) -> Result<Option<u8>, Box<dyn std::error::Error>> {
+        if !self.write.is_empty() {
+            let mut request = vec![self.report_id];
+            request.extend(&self.write);
+            match self.write_kind {
+                WriteKind::Output => {
+                    device.write(&request)?;
+                }
+                WriteKind::Feature => device.send_feature_report(&request)?,
+            }
+        }
+        if self.delay_ms > 0 {
+            std::thread::sleep(Duration::from_millis(self.delay_ms));
+        }
+
+        let mut answer = [0u8; REPORT_LEN];
+        let size = match self.read {
+            ReadKind::Feature => {
+                answer[0] = self.report_id;
+                device.get_feature_report(&mut answer)?
+            }
+            ReadKind::Input => match self.read_input(device, &mut answer)? {
+                Some(size) => size,
+                None => return Ok(None),
+            },
+        };
+
+        self.level(&answer[..size])
+    }
+
+    // The level in an answer, None when it doesn't match or is out of range
+    fn level(&self, answer: &[u8]) -> Result<Option<u8>, Box<dyn std::error::Error>> {
+        if !answer.starts_with(&self.expect) {
+            return Ok(None);
+        }
+        let raw = match self.width.unwrap_or(1) {
+            1 => answer.get(self.offset).map(|&byte| byte as f32),
+            2 => answer
+                .get(self.offset..self.offset + 2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as f32),
+            width => return Err(format!("unsupported probe width {}", width).into()),
+        };
+        let Some(raw) = raw else {
+            return Err(format!("answer has {} bytes, no offset {}", answer.len(), self.offset).into());
+        };
+
+        let level = (raw * self.scale.unwrap_or(1.0)).round();
+        Ok((0.0..=100.0).contains(&level).then_some(level as u8))
+    }
+
+    // The first input report that looks like an answer, skipping others
+    fn read_input(&self, device: &HidDevice, answer: &mut [u8]) -> Result<Option<usize>, Box<dyn std::error::Error>> {
+        for _ in 0..MAX_UNMATCHED_REPORTS {
+            let size = device.read_timeout(answer, READ_TIMEOUT_MS)?;
+            if size == 0 {
+                return Ok(None);
+            }
+            if answer[..size].starts_with(&self.expect) {
+                return Ok(Some(size));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A headset answering report 0x06 with its level in byte 3
+    fn probe() -> HidProbe {
+        HidProbe { report_id: 0x06, expect: vec![0x06, 0xb0], offset: 3, ..HidProbe::default() }
+    }
+
+    #[test]
+    fn reads_a_byte_at_the_offset() {
+        assert_eq!(probe().level(&[0x06, 0xb0, 0x00, 0x4b, 0x00]).unwrap(), Some(75));
+    }
+
+    #[test]
+    fn skips_answers_that_dont_match() {
+        assert_eq!(probe().level(&[0x07, 0xb0, 0x00, 0x4b]).unwrap(), None);
+        assert_eq!(probe().level(&[0x06]).unwrap(), None);
+    }
+
+    #[test]
+    fn fails_on_an_offset_past_the_answer() {
+        let error = probe().level(&[0x06, 0xb0, 0x00]).unwrap_err();
+        assert_eq!(error.to_string(), "answer has 3 bytes, no offset 3");
+
+        // Both bytes of a 16-bit value have to be there
+        let wide = HidProbe { width: Some(2), ..probe() };
+        assert!(wide.level(&[0x06, 0xb0, 0x00, 0x4b]).is_err());
+    }
+
+    #[test]
+    fn reads_little_endian_16_bit_values_and_scales_them() {
+        // 0x0310 is 784 tenths of a percent
+        let tenths = HidProbe { width: Some(2), scale: Some(0.1), ..probe() };
+        assert_eq!(tenths.level(&[0x06, 0xb0, 0x00, 0x10, 0x03]).unwrap(), Some(78));
+
+        // Without a scale the raw value is out of range and dropped
+        let unscaled = HidProbe { width: Some(2), ..probe() };
+        assert_eq!(unscaled.level(&[0x06, 0xb0, 0x00, 0x10, 0x03]).unwrap(), None);
+    }
+
+    #[test]
+    fn scales_a_small_gauge() {
+        let gauge = HidProbe { scale: Some(14.0), ..probe() };
+        assert_eq!(gauge.level(&[0x06, 0xb0, 0x00, 0x05]).unwrap(), Some(70));
+        assert_eq!(gauge.level(&[0x06, 0xb0, 0x00, 0x07]).unwrap(), Some(98));
+    }
+
+    #[test]
+    fn rejects_unsupported_widths() {
+        let odd = HidProbe { width: Some(3), ..probe() };
+        assert!(odd.level(&[0x06, 0xb0, 0x00, 0x4b, 0x00, 0x00]).is_err());
+    }
+}
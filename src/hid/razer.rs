@@ -0,0 +1,114 @@
+use hidapi::{DeviceInfo, HidDevice};
+
+const RAZER_VENDOR_ID: u16 = 0x1532;
+
+// HyperSpeed receivers and the wireless mode of Razer mice
+const HYPERSPEED_PRODUCT_IDS: &[u16] = &[
+    0x007b, // Viper Ultimate
+    0x007d, // DeathAdder V2 Pro
+    0x0083, // Basilisk X HyperSpeed
+    0x0088, // Basilisk Ultimate
+    0x0090, // Naga Pro
+    0x0094, // Orochi V2
+    0x00a6, // Viper V2 Pro
+    0x00ab, // Basilisk V3 Pro
+    0x00b7, // DeathAdder V3 Pro
+];
+
+// Razer reports are 90 bytes, sent as feature reports with report id 0
+const REPORT_LEN: usize = 90;
+const TRANSACTION_ID: u8 = 0x1f;
+const COMMAND_CLASS_POWER: u8 = 0x07;
+const COMMAND_GET_BATTERY: u8 = 0x80;
+const STATUS_SUCCESS: u8 = 0x02;
+
+pub fn supports(vendor_id: u16, product_id: u16) -> bool {
+    vendor_id == RAZER_VENDOR_ID && HYPERSPEED_PRODUCT_IDS.contains(&product_id)
+}
+
+pub fn is_control_interface(device_info: &DeviceInfo) -> bool {
+    device_info.interface_number() == 0
+}
+
+pub fn query_battery(device: &HidDevice) -> Result<Option<u8>, Box<dyn std::error::Error>> {
+    // Layout: status, transaction id, remaining packets (2), protocol type,
+    // data size, command class, command id, 80 argument bytes, crc, reserved
+    let mut report = [0u8; REPORT_LEN + 1];
+    report[2] = TRANSACTION_ID;
+    report[6] = 0x02;
+    report[7] = COMMAND_CLASS_POWER;
+    report[8] = COMMAND_GET_BATTERY;
+    report[89] = crc(&report[1..]);
+
+    device.send_feature_report(&report)?;
+
+    // The mouse needs a moment to answer through the receiver
+    std::thread::sleep(std::time::Duration::from_millis(50));
+
+    let mut response = [0u8; REPORT_LEN + 1];
+    let size = device.get_feature_report(&mut response)?;
+    Ok(parse_battery(&response[..size]))
+}
+
+// The level from a battery reply, report id first; None for a short or failed reply
+fn parse_battery(response: &[u8]) -> Option<u8> {
+    // The reply echoes the command class and id it answers
+    if response.len() < 11
+        || response[1] != STATUS_SUCCESS
+        || response[7] != COMMAND_CLASS_POWER
+        || response[8] != COMMAND_GET_BATTERY
+    {
+        return None;
+    }
+
+    // Battery is the second argument byte, scaled 0-255
+    Some((response[10] as u16 * 100 / 255) as u8)
+}
+
+// XOR of bytes 2 to 87 of the 90 byte report
+fn crc(report: &[u8]) -> u8 {
+    report[2..88].iter().fold(0, |crc, byte| crc ^ byte)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A battery reply as a Viper Ultimate's receiver sends it, with the level in argument byte 1
+    fn reply(level: u8) -> [u8; REPORT_LEN + 1] {
+        let mut reply = [0u8; REPORT_LEN + 1];
+        reply[1] = STATUS_SUCCESS;
+        reply[2] = TRANSACTION_ID;
+        reply[6] = 0x02;
+        reply[7] = COMMAND_CLASS_POWER;
+        reply[8] = COMMAND_GET_BATTERY;
+        reply[10] = level;
+        reply
+    }
+
+    #[test]
+    fn scales_the_level_to_a_percentage() {
+        assert_eq!(parse_battery(&reply(0xff)), Some(100));
+        assert_eq!(parse_battery(&reply(0x80)), Some(50));
+        assert_eq!(parse_battery(&reply(0x00)), Some(0));
+    }
+
+    #[test]
+    fn rejects_short_reads() {
+        assert_eq!(parse_battery(&reply(0xff)[..10]), None);
+        assert_eq!(parse_battery(&[]), None);
+    }
+
+    #[test]
+    fn rejects_failed_or_unrelated_replies() {
+        // 0x01 is busy: the mouse is asleep or out of range
+        let mut busy = reply(0xff);
+        busy[1] = 0x01;
+        assert_eq!(parse_battery(&busy), None);
+
+        // A charging status reply (command 0x84) carries its flag where the level would be
+        let mut charging = reply(0x01);
+        charging[8] = 0x84;
+        assert_eq!(parse_battery(&charging), None);
+    }
+}
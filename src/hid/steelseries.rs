@@ -0,0 +1,81 @@
+use hidapi::{DeviceInfo, HidDevice};
+
+const STEELSERIES_VENDOR_ID: u16 = 0x1038;
+
+#[derive(Clone, Copy)]
+enum Query {
+    // Arctis 7/Pro generation: percentage in byte 2
+    Percentage,
+    // Arctis 7+/Nova generation: level 0-4 in byte 2
+    Levels,
+}
+
+fn query_for(product_id: u16) -> Option<Query> {
+    match product_id {
+        0x1260 | 0x12ad | 0x1290 | 0x12b3 => Some(Query::Percentage), // Arctis 7, 7 2019, Pro Wireless, 1 Wireless
+        0x220e | 0x2202 | 0x2206 | 0x2258 => Some(Query::Levels),     // Arctis 7+, Nova 7, Nova 7X, Nova 5
+        _ => None,
+    }
+}
+
+pub fn supports(vendor_id: u16, product_id: u16) -> bool {
+    vendor_id == STEELSERIES_VENDOR_ID && query_for(product_id).is_some()
+}
+
+pub fn is_control_interface(device_info: &DeviceInfo) -> bool {
+    // Headset controls live on the vendor defined usage page
+    device_info.usage_page() >= 0xff00
+}
+
+pub fn query_battery(device: &HidDevice, product_id: u16) -> Result<Option<u8>, Box<dyn std::error::Error>> {
+    let Some(query) = query_for(product_id) else {
+        return Ok(None);
+    };
+
+    let request: &[u8] = match query {
+        Query::Percentage => &[0x06, 0x18],
+        Query::Levels => &[0x00, 0xb0],
+    };
+    device.write(request)?;
+
+    let mut response = [0u8; 8];
+    let size = device.read_timeout(&mut response, 500)?;
+    Ok(parse_battery(query, &response[..size]))
+}
+
+// The level from a battery reply; None for a short read or an unknown level
+fn parse_battery(query: Query, response: &[u8]) -> Option<u8> {
+    let raw = *response.get(2)?;
+    match query {
+        Query::Percentage => Some(raw.min(100)),
+        Query::Levels if raw <= 4 => Some(raw * 25),
+        Query::Levels => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_percentage_replies() {
+        // Arctis 7 at 83%
+        assert_eq!(parse_battery(Query::Percentage, &[0x06, 0x18, 0x53, 0x00, 0, 0, 0, 0]), Some(83));
+        assert_eq!(parse_battery(Query::Percentage, &[0x06, 0x18, 0xff]), Some(100));
+    }
+
+    #[test]
+    fn reads_level_replies_whether_or_not_charging() {
+        // Arctis Nova 7 at 3 of 4, then the same on the charger (byte 3 set)
+        assert_eq!(parse_battery(Query::Levels, &[0xb0, 0x03, 0x03, 0x00, 0, 0, 0, 0]), Some(75));
+        assert_eq!(parse_battery(Query::Levels, &[0xb0, 0x03, 0x03, 0x01, 0, 0, 0, 0]), Some(75));
+        assert_eq!(parse_battery(Query::Levels, &[0xb0, 0x03, 0x00, 0x01]), Some(0));
+        assert_eq!(parse_battery(Query::Levels, &[0xb0, 0x03, 0x05, 0x00]), None);
+    }
+
+    #[test]
+    fn rejects_short_reads() {
+        assert_eq!(parse_battery(Query::Percentage, &[0x06, 0x18]), None);
+        assert_eq!(parse_battery(Query::Levels, &[]), None);
+    }
+}
@@ -0,0 +1,429 @@
+use crate::config::data_dir;
+use crate::status::DeviceStatus;
+use chrono::{Datelike, Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+// Battery level recording, from the [history] config section
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    // Record every battery level change for `battery_percentage export`
+    pub enabled: bool,
+    // Samples older than this are dropped when the monitor starts (0 keeps everything)
+    pub retention_days: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: 90,
+        }
+    }
+}
+
+// One line of the battery history
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BatterySample {
+    // Unix timestamp (seconds)
+    pub timestamp: u64,
+    pub device_id: String,
+    pub device_name: String,
+    pub level: u8,
+    // Charge cycles counted for the device when the sample was taken
+    #[serde(default)]
+    pub cycles: u32,
+}
+
+impl BatterySample {
+    fn local_time(&self) -> String {
+        Local
+            .timestamp_opt(self.timestamp as i64, 0)
+            .single()
+            .map(|time| time.to_rfc3339())
+            .unwrap_or_else(|| self.timestamp.to_string())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+// Every battery level change, one JSON object per line in the data dir, so
+// rundown curves can be pulled out with `battery_percentage export`
+pub struct BatteryHistory {
+    // Last recorded level per device id, so unchanged levels aren't written again
+    last_levels: HashMap<String, u8>,
+    cycles: ChargeCycles,
+}
+
+impl BatteryHistory {
+    pub fn path() -> PathBuf {
+        data_dir().join("battery-history.jsonl")
+    }
+
+    pub fn new(config: &HistoryConfig) -> Self {
+        if config.retention_days > 0 {
+            let cutoff = now().saturating_sub(config.retention_days * DAY_SECS);
+            if let Err(e) = Self::prune(cutoff) {
+                warn!("Failed to prune battery history: {}", e);
+            }
+        }
+
+        Self {
+            last_levels: HashMap::new(),
+            cycles: ChargeCycles::load(),
+        }
+    }
+
+    // Append a sample for every device whose level changed since the last call
+    pub fn record(&mut self, devices: &[DeviceStatus]) {
+        let timestamp = now();
+        let mut samples = Vec::new();
+        for device in devices {
+            let Some(level) = device.battery_percentage else {
+                continue;
+            };
+            let cycles = self.cycles.update(&device.id, level);
+            if self.last_levels.insert(device.id.clone(), level) != Some(level) {
+                samples.push(BatterySample {
+                    timestamp,
+                    device_id: device.id.clone(),
+                    device_name: device.name.clone(),
+                    level,
+                    cycles,
+                });
+            }
+        }
+        self.cycles.save_if_changed();
+
+        if !samples.is_empty()
+            && let Err(e) = Self::append(&samples)
+        {
+            warn!("Failed to write battery history: {}", e);
+        }
+    }
+
+    fn append(samples: &[BatterySample]) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(data_dir())?;
+        let mut file = OpenOptions::new().create(true).append(true).open(Self::path())?;
+        for sample in samples {
+            writeln!(file, "{}", serde_json::to_string(sample)?)?;
+        }
+        Ok(())
+    }
+
+    // Samples at or after `since` (a Unix timestamp), optionally only for the
+    // device with this id or name. Lines that don't parse are skipped.
+    pub fn read(since: u64, device: Option<&str>) -> Result<Vec<BatterySample>, Box<dyn std::error::Error>> {
+        let contents = match std::fs::read_to_string(Self::path()) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str::<BatterySample>(line).ok())
+            .filter(|sample| sample.timestamp >= since)
+            .filter(|sample| {
+                device.is_none_or(|query| sample.device_id == query || sample.device_name.eq_ignore_ascii_case(query))
+            })
+            .collect())
+    }
+
+    // Rewrite the file without samples from before `cutoff`
+    fn prune(cutoff: u64) -> Result<(), Box<dyn std::error::Error>> {
+        let samples = Self::read(cutoff, None)?;
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(());
+        }
+
+        let temp_path = path.with_extension("jsonl.tmp");
+        let mut file = std::fs::File::create(&temp_path)?;
+        for sample in &samples {
+            writeln!(file, "{}", serde_json::to_string(sample)?)?;
+        }
+        std::fs::rename(temp_path, path)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct CycleState {
+    // Total percent discharged over the device's lifetime
+    discharged: u32,
+    last_level: Option<u8>,
+}
+
+// Charge cycles per device id, counted as equivalent full cycles: every 100%
+// discharged adds one, whether it went 100->0 once or 100->50 twice. Kept
+// apart from the history file so pruning old samples doesn't reset them.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChargeCycles {
+    devices: HashMap<String, CycleState>,
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl ChargeCycles {
+    pub fn path() -> PathBuf {
+        data_dir().join("charge-cycles.json")
+    }
+
+    pub fn load() -> Self {
+        std::fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn cycles(&self, device_id: &str) -> Option<u32> {
+        self.devices.get(device_id).map(|state| state.discharged / 100)
+    }
+
+    // Count the drop since the last reading and return the cycle count
+    fn update(&mut self, device_id: &str, level: u8) -> u32 {
+        let state = self.devices.entry(device_id.to_string()).or_default();
+        if state.last_level != Some(level) {
+            if let Some(last_level) = state.last_level
+                && level < last_level
+            {
+                state.discharged += u32::from(last_level - level);
+            }
+            state.last_level = Some(level);
+            self.changed = true;
+        }
+        state.discharged / 100
+    }
+
+    fn save_if_changed(&mut self) {
+        if !self.changed {
+            return;
+        }
+        self.changed = false;
+
+        let path = Self::path();
+        let result = std::fs::create_dir_all(data_dir())
+            .and_then(|_| std::fs::write(&path, serde_json::to_string_pretty(self).unwrap_or_default()));
+        if let Err(e) = result {
+            warn!("Failed to save charge cycles to {}: {}", path.display(), e);
+        }
+    }
+}
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+// Recent runtime is averaged over this many days...
+const RECENT_WINDOW_DAYS: u64 = 30;
+// ...and compared against discharges from at least this long ago
+const BASELINE_MIN_AGE_DAYS: u64 = 60;
+// Stretches with the device off or out of range would read as slow drain
+const MAX_SAMPLE_GAP_SECS: u64 = 12 * 60 * 60;
+// Too little discharge in a window makes the rate meaningless
+const MIN_WINDOW_DROP: u32 = 20;
+
+// How long a full charge lasts now compared to a few months back
+#[derive(Clone, Debug)]
+pub struct HealthEstimate {
+    pub recent_runtime_hours: f64,
+    pub baseline_runtime_hours: f64,
+    // Rough age of the baseline discharges
+    pub baseline_age_days: u64,
+}
+
+impl HealthEstimate {
+    // Negative when the battery runs down faster than it used to
+    pub fn runtime_change_percent(&self) -> f64 {
+        (self.recent_runtime_hours / self.baseline_runtime_hours - 1.0) * 100.0
+    }
+
+    // e.g. "Runtime down ~25% vs. 3 months ago"
+    pub fn caption(&self) -> String {
+        let change = self.runtime_change_percent().round() as i64;
+        let months = (self.baseline_age_days as f64 / 30.0).round().max(1.0) as u64;
+        let ago = if months == 1 { "1 month ago".to_string() } else { format!("{} months ago", months) };
+
+        match change {
+            0 => format!("Runtime unchanged vs. {}", ago),
+            change if change < 0 => format!("Runtime down ~{}% vs. {}", -change, ago),
+            change => format!("Runtime up ~{}% vs. {}", change, ago),
+        }
+    }
+}
+
+// A stretch of samples where the level only went down
+struct Discharge {
+    start: u64,
+    seconds: u64,
+    drop: u32,
+}
+
+// Compare full-charge runtime over the last month with discharges from a
+// couple of months back, for one device's samples in time order. None until
+// there's enough history on both sides.
+pub fn health_estimate(samples: &[BatterySample], now: u64) -> Option<HealthEstimate> {
+    let discharges = discharges(samples);
+    let recent_start = now.saturating_sub(RECENT_WINDOW_DAYS * DAY_SECS);
+    let baseline_end = now.saturating_sub(BASELINE_MIN_AGE_DAYS * DAY_SECS);
+
+    let recent: Vec<&Discharge> = discharges.iter().filter(|d| d.start >= recent_start).collect();
+    let baseline: Vec<&Discharge> = discharges.iter().filter(|d| d.start < baseline_end).collect();
+
+    let baseline_start = baseline.iter().map(|d| d.start).sum::<u64>() / baseline.len().max(1) as u64;
+    Some(HealthEstimate {
+        recent_runtime_hours: runtime_hours(&recent)?,
+        baseline_runtime_hours: runtime_hours(&baseline)?,
+        baseline_age_days: now.saturating_sub(baseline_start) / DAY_SECS,
+    })
+}
+
+fn discharges(samples: &[BatterySample]) -> Vec<Discharge> {
+    let mut discharges = Vec::new();
+    let mut current: Option<Discharge> = None;
+
+    for pair in samples.windows(2) {
+        let (previous, sample) = (&pair[0], &pair[1]);
+        let gap = sample.timestamp.saturating_sub(previous.timestamp);
+
+        if sample.level < previous.level && gap <= MAX_SAMPLE_GAP_SECS {
+            let discharge = current.get_or_insert(Discharge {
+                start: previous.timestamp,
+                seconds: 0,
+                drop: 0,
+            });
+            discharge.seconds += gap;
+            discharge.drop += u32::from(previous.level - sample.level);
+        } else if let Some(discharge) = current.take() {
+            discharges.push(discharge);
+        }
+    }
+    discharges.extend(current);
+
+    discharges
+}
+
+// Hours a 100% drop would take at the average rate of these discharges
+fn runtime_hours(discharges: &[&Discharge]) -> Option<f64> {
+    let drop: u32 = discharges.iter().map(|d| d.drop).sum();
+    let seconds: u64 = discharges.iter().map(|d| d.seconds).sum();
+    if drop < MIN_WINDOW_DROP || seconds == 0 {
+        return None;
+    }
+    Some(seconds as f64 / 3600.0 * 100.0 / f64::from(drop))
+}
+
+// Width of each band in DeviceStats::hours_by_level
+pub const LEVEL_BAND: u8 = 20;
+
+// Usage figures for `battery_percentage stats`
+#[derive(Clone, Debug, Serialize)]
+pub struct DeviceStats {
+    pub device_id: String,
+    pub device_name: String,
+    pub samples: usize,
+    // Unix timestamp of the oldest sample the figures are based on
+    pub since: u64,
+    // Average percent lost per hour while discharging
+    pub drain_per_hour: Option<f64>,
+    // Median length of a discharge, i.e. how long a charge usually lasts in practice
+    pub typical_charge_hours: Option<f64>,
+    // Unix timestamp of the last reading at 100%
+    pub last_full_charge: Option<u64>,
+    // Low and critical alerts since the start of the month
+    pub alerts_this_month: usize,
+    // Hours spent at 0-19%, 20-39% and so on
+    pub hours_by_level: [f64; 5],
+}
+
+// Figures for one device's samples in time order, None without any samples
+pub fn device_stats(samples: &[BatterySample], alerts_this_month: usize) -> Option<DeviceStats> {
+    let (first, last) = (samples.first()?, samples.last()?);
+    let discharges = discharges(samples);
+
+    let drop: u32 = discharges.iter().map(|d| d.drop).sum();
+    let seconds: u64 = discharges.iter().map(|d| d.seconds).sum();
+    let drain_per_hour = (seconds > 0).then(|| f64::from(drop) / (seconds as f64 / 3600.0));
+
+    // Short blips would drag the median down
+    let mut charge_lengths: Vec<u64> =
+        discharges.iter().filter(|d| d.drop >= MIN_WINDOW_DROP).map(|d| d.seconds).collect();
+    charge_lengths.sort_unstable();
+    let typical_charge_hours = charge_lengths.get(charge_lengths.len() / 2).map(|&seconds| seconds as f64 / 3600.0);
+
+    let mut hours_by_level = [0.0; 5];
+    for pair in samples.windows(2) {
+        let gap = pair[1].timestamp.saturating_sub(pair[0].timestamp);
+        if gap <= MAX_SAMPLE_GAP_SECS {
+            let band = usize::from(pair[0].level.min(99) / LEVEL_BAND);
+            hours_by_level[band] += gap as f64 / 3600.0;
+        }
+    }
+
+    Some(DeviceStats {
+        device_id: last.device_id.clone(),
+        device_name: last.device_name.clone(),
+        samples: samples.len(),
+        since: first.timestamp,
+        drain_per_hour,
+        typical_charge_hours,
+        last_full_charge: samples.iter().rev().find(|s| s.level == 100).map(|s| s.timestamp),
+        alerts_this_month,
+        hours_by_level,
+    })
+}
+
+pub fn export(samples: &[BatterySample], format: ExportFormat) -> Result<String, Box<dyn std::error::Error>> {
+    match format {
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(samples)?),
+        ExportFormat::Csv => {
+            let mut csv = String::from("timestamp,time,device_id,device_name,level,cycles\n");
+            for sample in samples {
+                csv.push_str(&format!(
+                    "{},{},{},{},{},{}\n",
+                    sample.timestamp,
+                    sample.local_time(),
+                    csv_field(&sample.device_id),
+                    csv_field(&sample.device_name),
+                    sample.level,
+                    sample.cycles
+                ));
+            }
+            Ok(csv)
+        }
+    }
+}
+
+// Quote fields containing separators, doubling any quotes inside
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+// Unix timestamp of local midnight on the first of this month
+pub fn month_start() -> u64 {
+    Local::now()
+        .date_naive()
+        .with_day(1)
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+        .map_or(0, |start| start.timestamp().max(0) as u64)
+}
@@ -0,0 +1,136 @@
+use crate::notifications::{BatteryEvent, NotificationSink};
+use crate::status::DeviceStatus;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+// User commands to run on device events, from the [hooks] config section.
+// {device_name}, {device_id}, {level} and {event} are replaced with
+// shell-quoted values before the command runs through sh -c.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    pub on_low_battery: Option<String>,
+    pub on_connect: Option<String>,
+    pub on_disconnect: Option<String>,
+    // Hooks still running after this long are killed
+    pub timeout_secs: u64,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            on_low_battery: None,
+            on_connect: None,
+            on_disconnect: None,
+            timeout_secs: 30,
+        }
+    }
+}
+
+// Runs on_low_battery through the notification manager, so hooks get the
+// same thresholds, mutes and repeat suppression as notifications
+pub struct LowBatteryHook {
+    pub command: String,
+    pub timeout: Duration,
+}
+
+impl NotificationSink for LowBatteryHook {
+    fn name(&self) -> &'static str {
+        "on_low_battery hook"
+    }
+
+    fn send(&self, event: &BatteryEvent) -> Result<(), Box<dyn std::error::Error>> {
+        if !event.notification_type.is_alert() {
+            return Ok(());
+        }
+        let level = event.battery.to_string();
+        let vars = [
+            ("device_name", event.device_name.as_str()),
+            ("device_id", event.device_id.as_str()),
+            ("level", level.as_str()),
+            ("event", event.notification_type.key()),
+        ];
+        run_hook("on_low_battery", &self.command, &vars, self.timeout);
+        Ok(())
+    }
+}
+
+// Fires on_connect/on_disconnect by comparing successive device lists
+pub struct ConnectionHooks {
+    config: HooksConfig,
+    known: HashMap<String, DeviceStatus>,
+}
+
+impl ConnectionHooks {
+    pub fn new(config: HooksConfig) -> Self {
+        Self {
+            config,
+            known: HashMap::new(),
+        }
+    }
+
+    pub fn update(&mut self, devices: &[DeviceStatus]) {
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        let current: HashMap<String, DeviceStatus> = devices.iter().map(|d| (d.id.clone(), d.clone())).collect();
+
+        if let Some(command) = &self.config.on_connect {
+            for device in current.values().filter(|d| !self.known.contains_key(&d.id)) {
+                run_hook("on_connect", command, &device_vars(device, "connect"), timeout);
+            }
+        }
+
+        if let Some(command) = &self.config.on_disconnect {
+            for device in self.known.values().filter(|d| !current.contains_key(&d.id)) {
+                run_hook("on_disconnect", command, &device_vars(device, "disconnect"), timeout);
+            }
+        }
+
+        self.known = current;
+    }
+}
+
+fn device_vars<'a>(device: &'a DeviceStatus, event: &'a str) -> Vec<(&'a str, String)> {
+    vec![
+        ("device_name", device.name.clone()),
+        ("device_id", device.id.clone()),
+        ("level", device.battery_percentage.map(|b| b.to_string()).unwrap_or_default()),
+        ("event", event.to_string()),
+    ]
+}
+
+// Run a hook in the background, logging how it ended
+fn run_hook<V: AsRef<str>>(hook: &'static str, template: &str, vars: &[(&str, V)], timeout: Duration) {
+    let command_line = substitute(template, vars);
+
+    let mut child = match Command::new("sh").arg("-c").arg(&command_line).kill_on_drop(true).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed to run {} hook '{}': {}", hook, command_line, e);
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        match tokio::time::timeout(timeout, child.wait()).await {
+            Ok(Ok(status)) if status.success() => info!("{} hook finished: {}", hook, command_line),
+            Ok(Ok(status)) => warn!("{} hook '{}' exited with {}", hook, command_line, status),
+            Ok(Err(e)) => warn!("Failed to wait for {} hook '{}': {}", hook, command_line, e),
+            // Dropping the child kills it
+            Err(_) => warn!("{} hook '{}' timed out after {}s and was killed", hook, command_line, timeout.as_secs()),
+        }
+    });
+}
+
+fn substitute<V: AsRef<str>>(template: &str, vars: &[(&str, V)]) -> String {
+    vars.iter().fold(template.to_string(), |command, (name, value)| {
+        command.replace(&format!("{{{}}}", name), &shell_quote(value.as_ref()))
+    })
+}
+
+// Quote a value so device names with spaces or quotes stay one argument
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
@@ -0,0 +1,97 @@
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource, FluentValue};
+use std::sync::OnceLock;
+use tracing::warn;
+use unic_langid::LanguageIdentifier;
+
+// Translations compiled into the binary, as (language, Fluent source).
+// English comes first and is the fallback for anything missing elsewhere.
+const LOCALES: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.ftl")),
+    ("de", include_str!("../locales/de.ftl")),
+];
+
+struct Localizer {
+    // The user's language, if there's a translation for it
+    preferred: Option<FluentBundle<FluentResource>>,
+    fallback: FluentBundle<FluentResource>,
+}
+
+static LOCALIZER: OnceLock<Localizer> = OnceLock::new();
+
+// Look up a message in the user's language
+pub fn tr(id: &str) -> String {
+    tr_args(id, &[])
+}
+
+// Look up a message, filling in its { $name } placeholders
+pub fn tr_args(id: &str, args: &[(&str, FluentValue)]) -> String {
+    let localizer = LOCALIZER.get_or_init(Localizer::new);
+    let mut fluent_args = FluentArgs::new();
+    for (name, value) in args {
+        fluent_args.set(*name, value.clone());
+    }
+
+    localizer
+        .preferred
+        .iter()
+        .chain(std::iter::once(&localizer.fallback))
+        .find_map(|bundle| format_message(bundle, id, &fluent_args))
+        .unwrap_or_else(|| id.to_string())
+}
+
+fn format_message(bundle: &FluentBundle<FluentResource>, id: &str, args: &FluentArgs) -> Option<String> {
+    let pattern = bundle.get_message(id)?.value()?;
+    let mut errors = Vec::new();
+    let message = bundle.format_pattern(pattern, Some(args), &mut errors);
+    if !errors.is_empty() {
+        warn!("Failed to format message '{}': {:?}", id, errors);
+    }
+    Some(message.into_owned())
+}
+
+impl Localizer {
+    fn new() -> Self {
+        let language = detect_language();
+        let preferred = LOCALES
+            .iter()
+            .skip(1)
+            .find(|(code, _)| language.as_deref() == Some(*code))
+            .and_then(|(code, source)| load_bundle(code, source));
+
+        Self {
+            preferred,
+            fallback: load_bundle(LOCALES[0].0, LOCALES[0].1).expect("built-in English messages are valid"),
+        }
+    }
+}
+
+fn load_bundle(code: &str, source: &str) -> Option<FluentBundle<FluentResource>> {
+    let language: LanguageIdentifier = code.parse().ok()?;
+    let resource = match FluentResource::try_new(source.to_string()) {
+        Ok(resource) => resource,
+        Err((_, errors)) => {
+            warn!("Invalid {} translation: {:?}", code, errors);
+            return None;
+        }
+    };
+
+    let mut bundle = FluentBundle::new_concurrent(vec![language]);
+    // Unicode isolation marks show up as boxes in some notification daemons
+    bundle.set_use_isolating(false);
+    bundle.add_resource(resource).ok()?;
+    Some(bundle)
+}
+
+// The language part of the first locale variable that's set, e.g. "de" for
+// LANG=de_DE.UTF-8
+fn detect_language() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| {
+            let language = value.split(['_', '.', '@']).next()?.to_lowercase();
+            (!language.is_empty() && language != "c" && language != "posix").then_some(language)
+        })
+}
@@ -0,0 +1,78 @@
+use crate::status::DeviceStatus;
+use std::collections::HashMap;
+use std::path::Path;
+
+// Emoji shown next to a device in text output, by device type as in `status --json`
+pub fn emoji_for_type(device_type: &str) -> &'static str {
+    match device_type {
+        "Headphones" | "Buds" => "🎧",
+        "Speaker" => "🔊",
+        "Mouse" => "🖱️",
+        "Phone" => "📱",
+        "Tablet" => "📟",
+        "Gamepad" => "🎮",
+        "Stylus" => "🖊️",
+        "Beacon" => "📡",
+        "AjazzAK870" | "Mechanical" | "Membrane" => "⌨️",
+        t if t.starts_with("WirelessDongle") => "🖱️",
+        _ => "🔋",
+    }
+}
+
+// Freedesktop icon name for a device type, for the tray
+pub fn icon_name_for_type(device_type: &str) -> &'static str {
+    match device_type {
+        "Headphones" | "Buds" => "audio-headphones-symbolic",
+        "Speaker" => "audio-speakers-symbolic",
+        "Mouse" => "input-mouse-symbolic",
+        "Phone" => "phone-symbolic",
+        "Tablet" => "computer-apple-ipad-symbolic",
+        "Gamepad" => "input-gaming-symbolic",
+        "Stylus" => "input-tablet-symbolic",
+        "Beacon" => "network-wireless-symbolic",
+        "AjazzAK870" | "Mechanical" | "Membrane" => "input-keyboard-symbolic",
+        t if t.starts_with("WirelessDongle") => "input-mouse-symbolic",
+        _ => "bluetooth-active-symbolic",
+    }
+}
+
+// Icon names or files from the [icons] section and per-device `icon`
+// settings, over the built-in names
+#[derive(Clone, Debug, Default)]
+pub struct IconMap {
+    // By device type
+    pub types: HashMap<String, String>,
+    // By upper-cased device id
+    pub devices: HashMap<String, String>,
+}
+
+impl IconMap {
+    // A configured icon for the device itself, then for its type, then the built-in one
+    pub fn icon_for(&self, device: &DeviceStatus) -> String {
+        let id = device.id.to_uppercase();
+        // Keyboards are configured by vendor:product id, like aliases and pins
+        let configured = self.devices.get(&id).or_else(|| {
+            self.devices
+                .iter()
+                .find(|(configured, _)| id.starts_with(&format!("{}:", configured)))
+                .map(|(_, icon)| icon)
+        });
+        configured
+            .or_else(|| self.types.get(&device.device_type))
+            .cloned()
+            .unwrap_or_else(|| icon_name_for_type(&device.device_type).to_string())
+    }
+}
+
+// Icon themes don't cover every device type, so an icon can also be a file.
+// Tray hosts look names up in a theme directory the tray names, so a file
+// is split into that directory and its name without the extension.
+pub fn split_icon_path(icon: &str) -> Option<(String, String)> {
+    let path = Path::new(icon);
+    if !path.is_absolute() {
+        return None;
+    }
+    let dir = path.parent()?.to_string_lossy().to_string();
+    let name = path.file_stem()?.to_string_lossy().to_string();
+    Some((dir, name))
+}
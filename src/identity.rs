@@ -0,0 +1,62 @@
+use crate::config::data_dir;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tracing::warn;
+
+// Ids for devices that have neither a MAC address nor a serial number, kept
+// on disk so a device keeps its id (and with it its mutes, aliases and
+// history) across replugs and restarts. Keyed by a fingerprint of whatever
+// the device does say about itself, such as its USB ids and product name.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IdentityMap {
+    ids: HashMap<String, String>,
+}
+
+impl IdentityMap {
+    pub fn path() -> PathBuf {
+        data_dir().join("identities.json")
+    }
+
+    pub fn load() -> Self {
+        std::fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let path = Self::path();
+        let result = std::fs::create_dir_all(data_dir())
+            .and_then(|_| std::fs::write(&path, serde_json::to_string_pretty(self).unwrap_or_default()));
+        if let Err(e) = result {
+            warn!("Failed to save device identities to {}: {}", path.display(), e);
+        }
+    }
+
+    // The id recorded for this fingerprint, or a new one based on
+    // `preferred` (with a #2, #3... suffix if another device already has it)
+    pub fn id_for(&mut self, fingerprint: &str, preferred: &str) -> String {
+        if let Some(id) = self.ids.get(fingerprint) {
+            return id.clone();
+        }
+
+        // Another scanner or process may have recorded it since we loaded
+        *self = Self::load();
+        if let Some(id) = self.ids.get(fingerprint) {
+            return id.clone();
+        }
+
+        let mut id = preferred.to_string();
+        let mut suffix = 2;
+        while self.ids.values().any(|taken| *taken == id) {
+            id = format!("{}#{}", preferred, suffix);
+            suffix += 1;
+        }
+
+        self.ids.insert(fingerprint.to_string(), id.clone());
+        self.save();
+        id
+    }
+}
@@ -0,0 +1,60 @@
+use crate::config::runtime_dir;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+
+// Held for as long as the monitor runs; the lock is released when the file is
+// closed, so a crashed monitor never leaves a stale lock behind
+pub struct InstanceLock {
+    _file: File,
+}
+
+pub enum LockError {
+    // Another monitor holds the lock; its pid, if it could be read
+    AlreadyRunning(Option<u32>),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for LockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockError::AlreadyRunning(Some(pid)) => write!(f, "battery monitor is already running (pid {})", pid),
+            LockError::AlreadyRunning(None) => write!(f, "battery monitor is already running"),
+            LockError::Io(e) => write!(f, "failed to lock {}: {}", lock_path().display(), e),
+        }
+    }
+}
+
+pub fn lock_path() -> PathBuf {
+    runtime_dir().join("battery-monitor.lock")
+}
+
+impl InstanceLock {
+    pub fn acquire() -> Result<Self, LockError> {
+        let path = lock_path();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(LockError::Io)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut contents = String::new();
+                let _ = file.read_to_string(&mut contents);
+                return Err(LockError::AlreadyRunning(contents.trim().parse().ok()));
+            }
+            Err(TryLockError::Error(e)) => return Err(LockError::Io(e)),
+        }
+
+        // Record our pid for the error message other instances show
+        file.set_len(0).map_err(LockError::Io)?;
+        file.rewind().map_err(LockError::Io)?;
+        write!(file, "{}", std::process::id()).map_err(LockError::Io)?;
+
+        Ok(Self { _file: file })
+    }
+}
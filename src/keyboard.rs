@@ -1,9 +1,17 @@
-use std::collections::HashMap;
-use hidapi::{HidApi, HidDevice, DeviceInfo};
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::CStr;
+use std::sync::Mutex;
+use hidapi::{HidApi, HidDevice, HidError, DeviceInfo};
+use crate::hid::DongleProtocol;
+use crate::identity::IdentityMap;
+use crate::quirks::{quirks, BatteryMethod, Quirk};
+use crate::smoothing::BatteryFilter;
+use tracing::{debug, info, warn};
 
 #[derive(Clone, Debug)]
 pub struct Keyboard {
     pub name: String,
+    pub alias: Option<String>,
     pub vendor_id: u16,
     pub product_id: u16,
     pub battery_percentage: Option<u8>,
@@ -17,24 +25,44 @@ pub enum KeyboardType {
     AjazzAK870,
     Mechanical,
     Membrane,
+    // Mouse or headset behind a 2.4 GHz gaming dongle
+    WirelessDongle(DongleProtocol),
     Unknown,
 }
 
+impl KeyboardType {
+    // The type named by a quirk's device_type
+    fn from_quirk(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "ajazzak870" => Some(KeyboardType::AjazzAK870),
+            "mechanical" => Some(KeyboardType::Mechanical),
+            "membrane" => Some(KeyboardType::Membrane),
+            _ => None,
+        }
+    }
+}
+
 impl Keyboard {
     pub fn get_icon(&self) -> &'static str {
         match self.keyboard_type {
             KeyboardType::AjazzAK870 => "⌨️",
             KeyboardType::Mechanical => "🔧",
             KeyboardType::Membrane => "⌨️",
+            KeyboardType::WirelessDongle(_) => "🖱️",
             KeyboardType::Unknown => "⌨️",
         }
     }
 
+    pub fn display_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+
     pub fn format_for_status(&self) -> String {
-        let short_name = if self.name.len() > 12 {
-            format!("{}...", &self.name[..9])
+        let name = self.display_name();
+        let short_name = if name.chars().count() > 12 {
+            format!("{}...", name.chars().take(9).collect::<String>())
         } else {
-            self.name.clone()
+            name.to_string()
         };
 
         match self.battery_percentage {
@@ -50,7 +78,14 @@ impl Keyboard {
 
 pub struct KeyboardManager {
     pub connected_keyboards: HashMap<String, Keyboard>,
-    hid_api: HidApi,
+    // User-chosen names keyed by upper-cased device key or vendor:product id
+    pub aliases: HashMap<String, String>,
+    pub battery_filter: BatteryFilter,
+    identities: IdentityMap,
+    // None only in the stand-in left behind while the manager is on a blocking thread
+    hid_api: Option<HidApi>,
+    // Devices from the last scan whose hidraw node we weren't allowed to open
+    access_denied: Mutex<BTreeSet<String>>,
 }
 
 impl KeyboardManager {
@@ -58,25 +93,85 @@ impl KeyboardManager {
         let hid_api = HidApi::new()?;
         Ok(Self {
             connected_keyboards: HashMap::new(),
-            hid_api,
+            aliases: HashMap::new(),
+            battery_filter: BatteryFilter::default(),
+            identities: IdentityMap::load(),
+            hid_api: Some(hid_api),
+            access_denied: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    // Keyboard reads wait on the devices, up to half a second for some
+    // dongles, so the monitor runs them on a blocking thread instead of the
+    // event loop. The manager moves there and back; meanwhile the one left in
+    // its place has no devices.
+    async fn run_blocking(
+        &mut self,
+        work: impl FnOnce(&mut Self) -> Result<(), Box<dyn std::error::Error>> + Send + 'static,
+    ) -> Result<(), String> {
+        let stand_in = Self {
+            connected_keyboards: HashMap::new(),
+            aliases: HashMap::new(),
+            battery_filter: BatteryFilter::default(),
+            identities: IdentityMap::default(),
+            hid_api: None,
+            access_denied: Mutex::new(BTreeSet::new()),
+        };
+        let mut manager = std::mem::replace(self, stand_in);
+        // Keep the caller's span, e.g. scan.usb, on what the work logs
+        let span = tracing::Span::current();
+        let (manager, result) = tokio::task::spawn_blocking(move || {
+            let result = span.in_scope(|| work(&mut manager)).map_err(|e| e.to_string());
+            (manager, result)
         })
+        .await
+        .map_err(|e| format!("keyboard scan panicked: {}", e))?;
+        *self = manager;
+        result
+    }
+
+    pub async fn scan_for_keyboards_off_thread(&mut self) -> Result<(), String> {
+        self.run_blocking(Self::scan_for_keyboards).await
+    }
+
+    pub async fn update_battery_levels_off_thread(&mut self) -> Result<(), String> {
+        self.run_blocking(Self::update_battery_levels).await
     }
 
     pub fn scan_for_keyboards(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         self.connected_keyboards.clear();
+        self.access_denied.get_mut().unwrap_or_else(|e| e.into_inner()).clear();
 
         // Refresh the device list
-        self.hid_api.refresh_devices()?;
+        let Some(hid_api) = &mut self.hid_api else { return Ok(()) };
+        hid_api.refresh_devices()?;
 
         // Enumerate all HID devices
-        for device_info in self.hid_api.device_list() {
-            if let Some(keyboard) = self.analyze_hid_device(device_info)? {
-                let device_key = format!("{}:{}", keyboard.path, keyboard.device_id());
-                println!("Found keyboard: {} ({})", keyboard.name, keyboard.device_id());
-                println!("  Type: {:?}", keyboard.keyboard_type);
-                println!("  Serial Number: {:?}", keyboard.serial_number);
+        let Some(hid_api) = &self.hid_api else { return Ok(()) };
+        for device_info in hid_api.device_list() {
+            if let Some(mut keyboard) = self.analyze_hid_device(device_info)? {
+                // The HID path changes on every replug, so key by serial
+                // number where there is one and a remembered id otherwise
+                let device_key = match keyboard.serial_number.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+                    Some(serial) => format!("{}:{}", keyboard.device_id(), serial),
+                    None => self.identities.id_for(&format!("usb:{}:{}", keyboard.device_id(), keyboard.name), &keyboard.device_id()),
+                };
+                // Identical devices without serials, or several interfaces of
+                // one device, share that key; tell them apart by path
+                let device_key = if self.connected_keyboards.contains_key(&device_key) {
+                    format!("{}:{}", device_key, keyboard.path)
+                } else {
+                    device_key
+                };
+                keyboard.alias = self.aliases.get(&device_key.to_uppercase())
+                    .or_else(|| self.aliases.get(&keyboard.device_id().to_uppercase()))
+                    .cloned();
+                keyboard.battery_percentage = self.battery_filter.apply(&device_key, keyboard.battery_percentage);
+                info!("Found keyboard: {} ({})", keyboard.name, keyboard.device_id());
+                debug!("Type: {:?}", keyboard.keyboard_type);
+                debug!("Serial Number: {:?}", keyboard.serial_number);
                 if let Some(battery) = keyboard.battery_percentage {
-                    println!("  Battery: {}%", battery);
+                    info!("Battery: {}%", battery);
                 }
                 self.connected_keyboards.insert(device_key, keyboard);
             }
@@ -85,6 +180,24 @@ impl KeyboardManager {
         Ok(())
     }
 
+    // Names of the devices the last scan couldn't open for lack of permission
+    pub fn access_denied(&self) -> Vec<String> {
+        self.access_denied.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+    }
+
+    fn open_device(&self, device_info: &DeviceInfo) -> Result<HidDevice, HidError> {
+        let Some(hid_api) = &self.hid_api else {
+            return Err(HidError::HidApiError { message: "no HID access".to_string() });
+        };
+        let result = hid_api.open_path(device_info.path());
+        if result.is_err() && is_permission_denied(device_info.path()) {
+            let name = device_info.product_string().unwrap_or("Unknown device");
+            let name = format!("{} ({:04x}:{:04x})", name, device_info.vendor_id(), device_info.product_id());
+            self.access_denied.lock().unwrap_or_else(|e| e.into_inner()).insert(name);
+        }
+        result
+    }
+
     fn analyze_hid_device(&self, device_info: &DeviceInfo) -> Result<Option<Keyboard>, Box<dyn std::error::Error>> {
         // Check if this might be a keyboard
         let is_keyboard = self.is_likely_keyboard(device_info);
@@ -109,6 +222,7 @@ impl KeyboardManager {
 
         Ok(Some(Keyboard {
             name,
+            alias: None,
             vendor_id,
             product_id,
             battery_percentage,
@@ -119,6 +233,19 @@ impl KeyboardManager {
     }
 
     fn is_likely_keyboard(&self, device_info: &DeviceInfo) -> bool {
+        // Gaming dongles with a known battery protocol, on their control interface only
+        if let Some(protocol) = DongleProtocol::detect(device_info.vendor_id(), device_info.product_id()) {
+            return protocol.is_control_interface(device_info);
+        }
+
+        // Anything in the quirks table is a device we know how to handle
+        if let Some(quirk) = quirks().for_usb(device_info.vendor_id(), device_info.product_id()) {
+            return match quirk.battery.and_then(BatteryMethod::dongle_protocol) {
+                Some(protocol) => protocol.is_control_interface(device_info),
+                None => true,
+            };
+        }
+
         // Check usage page and usage for keyboard indicators
         let usage_page = device_info.usage_page();
         let usage = device_info.usage();
@@ -138,23 +265,27 @@ impl KeyboardManager {
             }
         }
 
-        // Check specific vendor/product ID combinations
-        let vendor_id = device_info.vendor_id();
-        let product_id = device_info.product_id();
+        false
+    }
 
-        // Your specific AK870
-        if vendor_id == 0x05ac && product_id == 0x024f {
-            return true;
+    fn detect_keyboard_type(&self, name: &str, vendor_id: u16, product_id: u16) -> KeyboardType {
+        if let Some(protocol) = DongleProtocol::detect(vendor_id, product_id) {
+            return KeyboardType::WirelessDongle(protocol);
         }
 
-        // Other known keyboard vendor IDs
-        match vendor_id {
-            0x0483 | 0x1ea7 => true, // Known Ajazz vendor IDs
-            _ => false,
+        if let Some(quirk) = quirks().for_usb(vendor_id, product_id) {
+            debug!("{:04x}:{:04x} matches quirk {}", vendor_id, product_id, quirk.name.as_deref().unwrap_or(&quirk.matches));
+            if let Some(protocol) = quirk.battery.and_then(BatteryMethod::dongle_protocol) {
+                return KeyboardType::WirelessDongle(protocol);
+            }
+            if let Some(keyboard_type) = quirk.device_type.as_deref().and_then(KeyboardType::from_quirk) {
+                return keyboard_type;
+            }
+            if quirk.battery == Some(BatteryMethod::Ajazz) {
+                return KeyboardType::AjazzAK870;
+            }
         }
-    }
 
-    fn detect_keyboard_type(&self, name: &str, vendor_id: u16, product_id: u16) -> KeyboardType {
         let name_lower = name.to_lowercase();
 
         // Check for Ajazz AK870 specifically by name
@@ -162,13 +293,8 @@ impl KeyboardManager {
             return KeyboardType::AjazzAK870;
         }
 
-        // Check vendor/product ID for known keyboards
+        // Known vendor/product IDs are in the quirks table
         match (vendor_id, product_id) {
-            // Specific Ajazz AK870 device ID: 05ac:024f
-            (0x05ac, 0x024f) => KeyboardType::AjazzAK870,
-            // Other known Ajazz vendor IDs
-            (0x0483, _) => KeyboardType::AjazzAK870,
-            (0x1ea7, _) => KeyboardType::AjazzAK870,
             // Apple vendor ID but could be used by other manufacturers for AK870
             (0x05ac, _) => {
                 if name_lower.contains("keyboard") || name_lower.contains("ak") {
@@ -190,8 +316,57 @@ impl KeyboardManager {
     }
 
     fn get_hid_battery(&self, device_info: &DeviceInfo, keyboard_type: &KeyboardType) -> Result<Option<u8>, Box<dyn std::error::Error>> {
+        let quirk = quirks().for_usb(device_info.vendor_id(), device_info.product_id());
+        if let Some(quirk) = quirk {
+            match quirk.battery {
+                Some(BatteryMethod::None) => return Ok(None),
+                Some(BatteryMethod::FeatureReport | BatteryMethod::Probe) => {
+                    return Ok(self.run_quirk_probes(device_info, quirk));
+                }
+                _ => {}
+            }
+        }
+
+        let battery = self.get_known_hid_battery(device_info, keyboard_type)?;
+        // A quirk's scale only applies to raw readings, which the generic
+        // probes below can't make sense of anyway
+        Ok(match quirk.filter(|quirk| quirk.multiply.is_some()) {
+            Some(quirk) => battery.and_then(|raw| quirk.transform(raw)),
+            None => battery,
+        })
+    }
+
+    // Run a quirk's probes until one comes back with a level
+    fn run_quirk_probes(&self, device_info: &DeviceInfo, quirk: &Quirk) -> Option<u8> {
+        let device = match self.open_device(device_info) {
+            Ok(device) => device,
+            Err(e) => {
+                warn!("Failed to open HID device: {}", e);
+                return None;
+            }
+        };
+
+        quirk.probes().iter().find_map(|probe| {
+            probe.run(&device).unwrap_or_else(|e| {
+                warn!("Battery probe on report 0x{:02x} failed: {}", probe.report_id, e);
+                None
+            })
+        })
+    }
+
+    fn get_known_hid_battery(&self, device_info: &DeviceInfo, keyboard_type: &KeyboardType) -> Result<Option<u8>, Box<dyn std::error::Error>> {
         match keyboard_type {
             KeyboardType::AjazzAK870 => self.get_ajazz_ak870_hid_battery(device_info),
+            KeyboardType::WirelessDongle(protocol) => {
+                // A sleeping mouse or an unreadable dongle shouldn't stop the scan
+                let battery = self.open_device(device_info)
+                    .map_err(|e| e.into())
+                    .and_then(|device| protocol.query_battery(&device, device_info.product_id()));
+                Ok(battery.unwrap_or_else(|e| {
+                    warn!("Failed to query {:?} dongle battery: {}", protocol, e);
+                    None
+                }))
+            }
             _ => Ok(None),
         }
     }
@@ -203,10 +378,10 @@ impl KeyboardManager {
             .unwrap_or(false);
 
         // Try to open the HID device
-        match self.hid_api.open_path(device_info.path()) {
+        match self.open_device(device_info) {
             Ok(device) => {
                 if is_wireless_receiver {
-                    println!("Detected wireless receiver, using specialized detection...");
+                    debug!("Detected wireless receiver, using specialized detection...");
                     // For wireless receivers, use different approach
                     if let Some(battery) = self.try_wireless_battery_detection(&device)? {
                         return Ok(Some(battery));
@@ -238,7 +413,7 @@ impl KeyboardManager {
             }
             Err(e) => {
                 // If we can't open the device, try alternative methods
-                println!("Failed to open HID device: {}", e);
+                warn!("Failed to open HID device: {}", e);
 
                 // Fall back to system battery interfaces
                 self.get_system_battery_for_device(device_info.vendor_id(), device_info.product_id())
@@ -309,7 +484,7 @@ impl KeyboardManager {
     }
 
     fn try_wireless_battery_detection(&self, device: &HidDevice) -> Result<Option<u8>, Box<dyn std::error::Error>> {
-        println!("Trying wireless receiver battery detection methods...");
+        debug!("Trying wireless receiver battery detection methods...");
 
         // Method 1: Try to send battery query command to wireless receiver
         if let Some(battery) = self.try_wireless_battery_query(&device)? {
@@ -352,7 +527,7 @@ impl KeyboardManager {
                             // Look for battery response pattern
                             if buf[0] == 0x10 && buf[2] == 0x8F {
                                 if buf[4] <= 100 && buf[4] > 0 {
-                                    println!("Found battery level via wireless query: {}%", buf[4]);
+                                    debug!("Found battery level via wireless query: {}%", buf[4]);
                                     return Ok(Some(buf[4]));
                                 }
                             }
@@ -375,7 +550,7 @@ impl KeyboardManager {
             let mut buf = [0u8; 65];
             match device.read(&mut buf) {
                 Ok(size) if size > 0 => {
-                    println!("Input report {}: {:02x?}", attempt, &buf[0..size.min(8)]);
+                    debug!("Input report {}: {:02x?}", attempt, &buf[0..size.min(8)]);
 
                     // Look for battery information patterns in wireless reports
                     // Many wireless keyboards send battery info in specific patterns
@@ -386,7 +561,7 @@ impl KeyboardManager {
                                 let value = buf[pos];
                                 if value <= 100 && value > 0 && value % 5 == 0 {
                                     // Wireless keyboards often report in 5% increments
-                                    println!("Found potential battery value at pos {}: {}%", pos, value);
+                                    debug!("Found potential battery value at pos {}: {}%", pos, value);
                                     return Ok(Some(value));
                                 }
                             }
@@ -397,7 +572,7 @@ impl KeyboardManager {
                             for i in 1..size.min(8) {
                                 let value = buf[i];
                                 if value <= 100 && value >= 5 && value % 5 == 0 {
-                                    println!("Found battery in notification: {}%", value);
+                                    debug!("Found battery in notification: {}%", value);
                                     return Ok(Some(value));
                                 }
                             }
@@ -423,13 +598,13 @@ impl KeyboardManager {
 
             match device.get_feature_report(&mut buf) {
                 Ok(size) if size > 1 => {
-                    println!("Safe feature report ID 0x{:02x}: {} bytes", report_id, size);
+                    debug!("Safe feature report ID 0x{:02x}: {} bytes", report_id, size);
 
                     for i in 1..size.min(16) {
                         let value = buf[i];
                         if value <= 100 && value > 0 {
                             if self.validate_battery_value(value, &buf[1..size]) {
-                                println!("Found battery in safe feature report: {}%", value);
+                                debug!("Found battery in safe feature report: {}%", value);
                                 return Ok(Some(value));
                             }
                         }
@@ -565,24 +740,28 @@ impl KeyboardManager {
 
     pub fn update_battery_levels(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         // Refresh device list to get current state
-        self.hid_api.refresh_devices()?;
+        let Some(hid_api) = &mut self.hid_api else { return Ok(()) };
+        hid_api.refresh_devices()?;
 
         // Update battery levels for known keyboards
         let keyboard_paths: Vec<_> = self.connected_keyboards.keys().cloned().collect();
 
         for keyboard_key in keyboard_paths {
             if let Some(keyboard) = self.connected_keyboards.get(&keyboard_key) {
-                if keyboard.keyboard_type == KeyboardType::AjazzAK870 {
+                let has_quirk = quirks().for_usb(keyboard.vendor_id, keyboard.product_id)
+                    .is_some_and(|quirk| quirk.battery.is_some());
+                if has_quirk || matches!(keyboard.keyboard_type, KeyboardType::AjazzAK870 | KeyboardType::WirelessDongle(_)) {
                     // Find the device in the current device list
-                    if let Some(device_info) = self.hid_api.device_list()
+                    if let Some(device_info) = self.hid_api.iter().flat_map(|hid_api| hid_api.device_list())
                         .find(|d| d.vendor_id() == keyboard.vendor_id &&
                                   d.product_id() == keyboard.product_id &&
                                   d.path().to_string_lossy() == keyboard.path) {
 
                         if let Ok(Some(new_battery)) = self.get_hid_battery(device_info, &keyboard.keyboard_type) {
+                            let new_battery = self.battery_filter.apply(&keyboard_key, Some(new_battery)).unwrap_or(new_battery);
                             if let Some(kb) = self.connected_keyboards.get_mut(&keyboard_key) {
                                 if kb.battery_percentage != Some(new_battery) {
-                                    println!("Keyboard battery updated for {}: {}%", kb.name, new_battery);
+                                    info!("Keyboard battery updated for {}: {}%", kb.name, new_battery);
                                     kb.battery_percentage = Some(new_battery);
                                 }
                             }
@@ -595,3 +774,13 @@ impl KeyboardManager {
         Ok(())
     }
 }
+
+// hidapi only hands back an error string, so ask the kernel directly
+// whether it was the permissions on the hidraw node
+fn is_permission_denied(path: &CStr) -> bool {
+    let Ok(path) = path.to_str() else { return false };
+    matches!(
+        std::fs::OpenOptions::new().read(true).write(true).open(path),
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied
+    )
+}
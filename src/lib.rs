@@ -1,2 +1,43 @@
+pub mod adapter;
+pub mod advertisement;
+pub mod api;
+pub mod apple;
+pub mod battery_provider;
 pub mod bluetooth;
+pub mod cli;
+pub mod completions;
+pub mod config;
+pub mod control;
+pub mod gamepad;
+pub mod dbus;
+pub mod diagnostics;
+pub mod doctor;
+pub mod hid;
+pub mod history;
+pub mod i18n;
+pub mod icons;
+pub mod hooks;
+pub mod identity;
+pub mod instance;
 pub mod keyboard;
+pub mod logging;
+pub mod logind;
+pub mod man;
+pub mod mock;
+pub mod mqtt;
+pub mod notifications;
+pub mod output;
+pub mod polling;
+pub mod power_supply;
+pub mod quirks;
+pub mod scanner;
+pub mod service;
+pub mod setup;
+pub mod smoothing;
+pub mod snapshot;
+pub mod status;
+pub mod systemd;
+pub mod thresholds;
+pub mod tray;
+#[cfg(feature = "tui")]
+pub mod tui;
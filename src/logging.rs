@@ -0,0 +1,89 @@
+use std::future::Future;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tracing::{Instrument, Span};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, EnvFilter};
+
+// Rotated log files older than this many days are deleted
+const MAX_LOG_FILES: usize = 7;
+
+// Set while a full-screen terminal UI would be scribbled over by stderr logs
+static STDERR_MUTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+// Log to stderr and, with a log file, also to a file rotated daily. RUST_LOG
+// picks the level (info by default). The returned guard flushes the file on
+// drop, so keep it until exit.
+pub fn init(log_file: Option<&Path>, format: LogFormat) -> Result<Option<WorkerGuard>, Box<dyn std::error::Error>> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    // The journal adds its own timestamps
+    let stderr = fmt::layer().with_writer(stderr_writer).with_target(false).without_time();
+    let stderr = match format {
+        LogFormat::Text => stderr.boxed(),
+        LogFormat::Json => stderr.json().boxed(),
+    };
+
+    let (file, guard) = match log_file {
+        Some(path) => {
+            let name = path.file_name().ok_or("log file path has no file name")?;
+            let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            let appender = RollingFileAppender::builder()
+                .rotation(Rotation::DAILY)
+                .filename_prefix(name.to_string_lossy())
+                .max_log_files(MAX_LOG_FILES)
+                .build(dir)?;
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+
+            // Span close events carry each scan's timing
+            let layer = fmt::layer().with_writer(writer).with_ansi(false).with_span_events(FmtSpan::CLOSE);
+            let layer = match format {
+                LogFormat::Text => layer.boxed(),
+                LogFormat::Json => layer.json().boxed(),
+            };
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry().with(filter).with(stderr).with(file).try_init()?;
+    Ok(guard)
+}
+
+// Drop stderr logs until unmuted; the log file, if any, still gets them
+pub fn mute_stderr(muted: bool) {
+    STDERR_MUTED.store(muted, Ordering::Relaxed);
+}
+
+fn stderr_writer() -> Box<dyn Write> {
+    if STDERR_MUTED.load(Ordering::Relaxed) { Box::new(std::io::sink()) } else { Box::new(std::io::stderr()) }
+}
+
+// Run `future` in `span` and record how long it took in the span's
+// elapsed_ms field, which the span has to declare
+pub async fn timed<F: Future>(span: Span, future: F) -> F::Output {
+    let started = Instant::now();
+    let output = future.instrument(span.clone()).await;
+    span.record("elapsed_ms", started.elapsed().as_millis() as u64);
+    output
+}
+
+// The same for blocking work
+pub fn timed_sync<T>(span: Span, work: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let output = span.in_scope(work);
+    span.record("elapsed_ms", started.elapsed().as_millis() as u64);
+    output
+}
@@ -0,0 +1,110 @@
+use futures::StreamExt;
+use tracing::warn;
+use zbus::proxy::PropertyStream;
+use zbus::zvariant::{OwnedFd, OwnedObjectPath};
+use zbus::{proxy, Connection};
+
+#[proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait Login1Manager {
+    fn inhibit(&self, what: &str, who: &str, why: &str, mode: &str) -> zbus::Result<OwnedFd>;
+
+    fn get_session(&self, session_id: &str) -> zbus::Result<OwnedObjectPath>;
+
+    #[zbus(signal)]
+    fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+}
+
+#[proxy(interface = "org.freedesktop.login1.Session", default_service = "org.freedesktop.login1")]
+trait Login1Session {
+    #[zbus(property)]
+    fn locked_hint(&self) -> zbus::Result<bool>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SleepEvent {
+    Suspending,
+    Resumed,
+}
+
+// Suspend and resume as logind announces them. A delay inhibitor is held
+// while awake so the announcement comes before the system actually sleeps.
+pub struct SleepWatch {
+    manager: Login1ManagerProxy<'static>,
+    signals: PrepareForSleepStream,
+    inhibitor: Option<OwnedFd>,
+}
+
+impl SleepWatch {
+    pub async fn start() -> zbus::Result<Self> {
+        let connection = Connection::system().await?;
+        let manager = Login1ManagerProxy::new(&connection).await?;
+        let signals = manager.receive_prepare_for_sleep().await?;
+        let mut watch = Self {
+            manager,
+            signals,
+            inhibitor: None,
+        };
+        watch.inhibit().await;
+        Ok(watch)
+    }
+
+    async fn inhibit(&mut self) {
+        match self.manager.inhibit("sleep", "Battery Monitor", "Pausing device scans", "delay").await {
+            Ok(fd) => self.inhibitor = Some(fd),
+            Err(e) => warn!("Failed to take a sleep inhibitor: {}", e),
+        }
+    }
+
+    // The inhibitor is let go as soon as suspend is announced: the caller
+    // only has to stop starting new scans, which it does right away
+    pub async fn next(&mut self) -> Option<SleepEvent> {
+        let signal = self.signals.next().await?;
+        if signal.args().ok()?.start {
+            self.inhibitor = None;
+            Some(SleepEvent::Suspending)
+        } else {
+            self.inhibit().await;
+            Some(SleepEvent::Resumed)
+        }
+    }
+}
+
+// Whether the user's session is locked, from logind's LockedHint. GNOME, KDE
+// and lockers that integrate with logind keep it up to date.
+pub struct LockWatch {
+    _session: Login1SessionProxy<'static>,
+    changes: PropertyStream<'static, bool>,
+}
+
+impl LockWatch {
+    // The watch and whether the session is locked right now
+    pub async fn start() -> zbus::Result<(Self, bool)> {
+        let connection = Connection::system().await?;
+        // "auto" is the caller's session, or the user's graphical one when
+        // running as a service outside any session. Change signals come from
+        // the real path, so resolve it first.
+        let path = Login1ManagerProxy::new(&connection).await?.get_session("auto").await?;
+        let session = Login1SessionProxy::builder(&connection).path(path)?.build().await?;
+        let locked = session.locked_hint().await?;
+        let changes = session.receive_locked_hint_changed().await;
+        Ok((
+            Self {
+                _session: session,
+                changes,
+            },
+            locked,
+        ))
+    }
+
+    pub async fn next(&mut self) -> Option<bool> {
+        loop {
+            if let Ok(locked) = self.changes.next().await?.get().await {
+                return Some(locked);
+            }
+        }
+    }
+}
@@ -1,34 +1,107 @@
-mod bluetooth;
-mod keyboard;
-
-use bluer::{AdapterEvent, DeviceEvent, DiscoveryFilter, DiscoveryTransport};
-use bluetooth::{BluetoothDevice, BluetoothManager};
-use futures::{pin_mut, stream::SelectAll, StreamExt};
-use keyboard::KeyboardManager;
+use battery_percentage::adapter::{self, AdapterWatch, BluetoothLink, LinkEvent};
+use battery_percentage::api::{ApiEvent, ApiServer};
+use battery_percentage::battery_provider::BatteryProvider;
+use battery_percentage::bluetooth::{BluetoothDevice, BluetoothManager};
+use battery_percentage::cli::{Cli, Commands, ConfigAction, ConfigOverrides};
+use battery_percentage::completions::{self, COMPLETE_VAR};
+use battery_percentage::config::{config_path, Config};
+use battery_percentage::control::{self, ControlCommand, ControlRequest, ThresholdTier};
+use battery_percentage::dbus::DbusService;
+use battery_percentage::diagnostics::{self, ScanResults};
+use battery_percentage::doctor;
+use battery_percentage::hooks::{ConnectionHooks, LowBatteryHook};
+use battery_percentage::i18n::{tr, tr_args};
+use battery_percentage::instance::{InstanceLock, LockError};
+use battery_percentage::keyboard::KeyboardManager;
+use battery_percentage::logging::{self, timed};
+use battery_percentage::mqtt::MqttPublisher;
+use battery_percentage::history::{self, BatteryHistory, ChargeCycles, ExportFormat};
+use battery_percentage::notifications::history::NotificationLog;
+use battery_percentage::notifications::{
+    NotificationAction, NotificationActionEvent, NotificationConfig, NotificationManager, NotificationType,
+    SNOOZE_DURATION,
+};
+use battery_percentage::output::{self, StatusOutput, TemplateFile};
+use battery_percentage::polling::PollScheduler;
+use battery_percentage::scanner::{MonitorHealth, ScannerRegistry};
+use battery_percentage::service;
+use battery_percentage::setup;
+use battery_percentage::logind::{LockWatch, SleepEvent, SleepWatch};
+use battery_percentage::man;
+use battery_percentage::smoothing::BatteryFilter;
+use battery_percentage::snapshot::{LastKnown, SavedAlerts, Snapshot, SnapshotScanner};
+use battery_percentage::status::{
+    collect_device_status, combined_status_text, find_device, DeviceStatus, LockedChanges, Transport,
+};
+use battery_percentage::systemd;
+use battery_percentage::thresholds::ThresholdTracker;
+use battery_percentage::tray::{BatteryTray, TrayAction, TrayHandle};
+use bluer::{Adapter, Address, AdapterEvent, Device, DeviceEvent, DeviceProperty, SessionEvent};
+use chrono::TimeZone;
+use clap::{CommandFactory, Parser};
+use clap_complete::CompleteEnv;
+use futures::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::io::{IsTerminal, Write};
+use std::path::Path;
 use std::process::Command;
-use tokio::time::{sleep, Duration};
-
-fn update_status_display(bt_manager: &BluetoothManager, kb_manager: &KeyboardManager) {
-    let bt_status = bt_manager.get_status_text();
-    let kb_status = kb_manager.get_status_text();
-
-    let combined_status = if bt_status.contains("No Bluetooth") && kb_status.contains("No keyboards") {
-        "No devices connected".to_string()
-    } else if bt_status.contains("No Bluetooth") {
-        kb_status
-    } else if kb_status.contains("No keyboards") {
-        bt_status
-    } else {
-        format!("{} | {}", kb_status, bt_status)
-    };
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::time::{interval_at, sleep_until, Duration, Instant, Interval};
+use tracing::{debug, field, info, info_span, warn};
 
-    // Write to status file for GNOME integration
-    let indicator_file = "/tmp/bluetooth-battery-status";
-    let _ = std::fs::write(indicator_file, &combined_status);
+const KEYBOARD_RESCAN_INTERVAL: Duration = Duration::from_secs(120);
+// How often `status --follow` checks for changes
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(5);
+// Longest bar in the `stats` histogram
+const HISTOGRAM_WIDTH: usize = 20;
+// `list --quiet-exit-code` found a low device; 1 is taken by errors
+const LOW_BATTERY_EXIT_CODE: i32 = 2;
+
+// Everything that gets told about the latest device status
+struct StatusSinks {
+    outputs: Vec<StatusOutput>,
+    tray: Option<TrayHandle>,
+    mqtt: Option<MqttPublisher>,
+    dbus: Option<DbusService>,
+    hooks: Option<ConnectionHooks>,
+    history: Option<BatteryHistory>,
+    api: Option<ApiServer>,
+    // Turns each status into the threshold crossings alerts are sent for
+    thresholds: ThresholdTracker,
+    // What was connected when the screen locked, while it stays locked
+    screen_lock: LockedChanges,
+    // Devices from the last run, shown as stale in the tray and details until
+    // the first full refresh has seen what is really there
+    last_known: Vec<DeviceStatus>,
+    // Last health pushed to the tray and D-Bus
+    health: MonitorHealth,
+    // Whether the user was told about unreadable HID devices this run
+    hid_access_reported: bool,
+    // Hands levels BlueZ can't read itself back to it
+    battery_provider: Option<BatteryProvider>,
+}
+
+async fn update_status_display(
+    bt_manager: &BluetoothManager,
+    kb_manager: &KeyboardManager,
+    scanners: &ScannerRegistry,
+    notifier: &mut NotificationManager,
+    sinks: &mut StatusSinks,
+) {
+    let combined_status = combined_status_text(bt_manager, kb_manager, scanners);
+
+    // Write to status files and status bar outputs
+    let devices = collect_device_status(bt_manager, kb_manager, scanners);
+    for output in &sinks.outputs {
+        if let Err(e) = output.write(&combined_status, &devices, notifier.config.low_battery_threshold) {
+            warn!("Failed to write status output {:?}: {}", output, e);
+        }
+    }
 
     // Send desktop notification
-    let has_battery_info = bt_manager.connected_devices.values().any(|d| d.battery_percentage.is_some()) ||
-                          kb_manager.connected_keyboards.values().any(|k| k.battery_percentage.is_some());
+    let has_battery_info = devices.iter().any(|d| d.battery_percentage.is_some());
 
     let notification_text = if has_battery_info {
         format!("🔋 {}", combined_status)
@@ -36,141 +109,1561 @@ fn update_status_display(bt_manager: &BluetoothManager, kb_manager: &KeyboardMan
         format!("📱 {}", combined_status)
     };
 
+    // Nobody's there to see it while the screen is locked
+    if !sinks.screen_lock.is_locked() {
+        let _ = Command::new("notify-send")
+            .arg(tr("device-status-title"))
+            .arg(&notification_text)
+            .arg("-t")
+            .arg("3000")
+            .arg("-u")
+            .arg("low")
+            .output();
+    }
+
+    info!("Status: {}", combined_status);
+
+    // Once a run is enough; nothing changes until the permissions are fixed
+    if !sinks.hid_access_reported {
+        let denied = kb_manager.access_denied();
+        if !denied.is_empty() {
+            report_hid_access_denied(&denied);
+            sinks.hid_access_reported = true;
+        }
+    }
+
+    if let Some(mqtt) = &mut sinks.mqtt {
+        mqtt.publish_devices(&devices).await;
+    }
+
+    if let Some(dbus) = &sinks.dbus
+        && let Err(e) = dbus.update(&devices, &combined_status).await
+    {
+        warn!("Failed to update D-Bus status: {}", e);
+    }
+
+    if let Some(provider) = &mut sinks.battery_provider
+        && let Err(e) = provider.update(&provided_batteries(bt_manager, scanners)).await
+    {
+        warn!("Failed to publish battery levels to BlueZ: {}", e);
+    }
+
+    if let Some(hooks) = &mut sinks.hooks {
+        hooks.update(&devices);
+    }
+
+    if let Some(history) = &mut sinks.history {
+        history.record(&devices);
+    }
+
+    for crossing in sinks.thresholds.update(&devices, &notifier.config.thresholds()) {
+        debug!(
+            "{} crossed {}% going {} at {}%",
+            crossing.device_id,
+            crossing.threshold,
+            crossing.direction.key(),
+            crossing.battery
+        );
+        notifier.handle_crossing(&crossing);
+        if let Some(api) = &sinks.api {
+            api.publish(ApiEvent::Threshold(crossing.clone()));
+        }
+        if let Some(dbus) = &sinks.dbus
+            && let Err(e) = dbus.threshold_crossed(&crossing).await
+        {
+            warn!("Failed to signal threshold crossing on D-Bus: {}", e);
+        }
+    }
+    notifier.withdraw_resolved(&devices);
+    notifier.flush_held();
+
+    if let Some(api) = &sinks.api {
+        api.publish(ApiEvent::Devices(devices.clone()));
+    }
+
+    if let Some(tray) = &sinks.tray {
+        BatteryTray::update_devices(tray, with_last_known(devices, &sinks.last_known)).await;
+    }
+}
+
+// Bluetooth batteries BlueZ has no Battery1 level for: AirPods decoded from
+// their advertisements and whatever the scanners read, e.g. controllers over HID
+fn provided_batteries(bt_manager: &BluetoothManager, scanners: &ScannerRegistry) -> Vec<(String, u8)> {
+    let advertised = bt_manager
+        .connected_devices
+        .values()
+        .filter(|device| !device.batteries.is_empty())
+        .filter_map(|device| Some((device.address.to_string(), device.battery_percentage?)));
+    let scanned = scanners
+        .devices()
+        .filter(|device| device.transport == Transport::Bluetooth && device.id.parse::<Address>().is_ok())
+        .filter_map(|device| Some((device.id.to_uppercase(), device.battery_percentage?)));
+    advertised.chain(scanned).collect()
+}
+
+// Add the remembered devices that haven't turned up yet
+fn with_last_known(mut devices: Vec<DeviceStatus>, last_known: &[DeviceStatus]) -> Vec<DeviceStatus> {
+    for device in last_known {
+        if !devices.iter().any(|d| d.id == device.id) {
+            devices.push(device.clone());
+        }
+    }
+    devices
+}
+
+fn show_details(
+    bt_manager: &BluetoothManager,
+    kb_manager: &KeyboardManager,
+    scanners: &ScannerRegistry,
+    last_known: &[DeviceStatus],
+) {
+    let devices = with_last_known(collect_device_status(bt_manager, kb_manager, scanners), last_known);
+    let cycles = ChargeCycles::load();
+    let details = if devices.is_empty() {
+        tr("no-devices")
+    } else {
+        devices
+            .iter()
+            .map(|d| {
+                let mut line = d.format_line();
+                if let Some(cycles) = cycles.cycles(&d.id).filter(|cycles| *cycles > 0) {
+                    line = format!("{}, {}", line, tr_args("charge-cycles", &[("cycles", cycles.into())]));
+                }
+                match d.health_estimate() {
+                    Some(health) => format!("{}\n    {}", line, health.caption()),
+                    None => line,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
     let _ = Command::new("notify-send")
-        .arg("Device Battery Status")
-        .arg(&notification_text)
+        .arg(tr("device-status-title"))
+        .arg(&details)
         .arg("-t")
-        .arg("3000")
+        .arg("10000")
+        .output();
+}
+
+// One popup for the devices that came and went while the screen was locked
+fn show_unlock_summary(connected: &[String], disconnected: &[String]) {
+    let mut lines = Vec::new();
+    if !connected.is_empty() {
+        lines.push(tr_args("unlock-summary-connected", &[("devices", connected.join(", ").into())]));
+    }
+    if !disconnected.is_empty() {
+        lines.push(tr_args("unlock-summary-disconnected", &[("devices", disconnected.join(", ").into())]));
+    }
+    if lines.is_empty() {
+        return;
+    }
+
+    let _ = Command::new("notify-send")
+        .arg(tr("unlock-summary-title"))
+        .arg(lines.join("\n"))
+        .arg("-t")
+        .arg("5000")
         .arg("-u")
         .arg("low")
         .output();
+}
+
+// Keyboards and dongles whose hidraw node is root-only show up without a
+// battery, so say why and how to fix it
+fn report_hid_access_denied(devices: &[String]) {
+    warn!(
+        "No permission to read {}. Add the udev rule `{}` (e.g. to /etc/udev/rules.d/70-battery-monitor.rules), \
+         reload udev and replug, or run `battery_percentage doctor`",
+        devices.join(", "),
+        doctor::HIDRAW_UDEV_RULE
+    );
+
+    let _ = Command::new("notify-send")
+        .arg(tr("hid-access-title"))
+        .arg(tr_args("hid-access-body", &[("devices", devices.join(", ").into())]))
+        .arg("-u")
+        .arg("normal")
+        .output();
+}
+
+// Wait on an optional channel, never resolving when it isn't set up
+async fn recv_or_pending<T>(receiver: &mut Option<UnboundedReceiver<T>>) -> Option<T> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Signals get the same handling as the matching control socket command. The
+// command is queued for the main loop, which picks it up on its next turn.
+fn run_for_signal(control: &mpsc::UnboundedSender<ControlRequest>, command: ControlCommand) {
+    let control = control.clone();
+    tokio::spawn(async move {
+        let response = control::request(&control, command).await;
+        match response.strip_prefix("error: ") {
+            Some(e) => warn!("{}", e),
+            None => debug!("Signal handled: {}", response),
+        }
+    });
+}
+
+// Wait for the next tick of an optional interval, never resolving when it isn't set up
+async fn tick_or_pending(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+// The next event from the Bluetooth adapter, never resolving while there is none
+async fn next_link_event(bluetooth: &mut Option<BluetoothLink>) -> LinkEvent {
+    match bluetooth {
+        Some(link) => link.next_event().await,
+        None => std::future::pending().await,
+    }
+}
+
+// The next adapter added or removed, never resolving when they can't be watched
+async fn next_adapter_change(watch: &mut Option<AdapterWatch>) -> Option<SessionEvent> {
+    match watch {
+        Some(watch) => watch.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_sleep_event(watch: &mut Option<SleepWatch>) -> Option<SleepEvent> {
+    match watch {
+        Some(watch) => watch.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+// A control reply as JSON; a failure only fails that request, not the monitor
+fn json_reply<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_else(|e| {
+        warn!("Failed to serialize a control reply: {}", e);
+        format!("error: {}", e)
+    })
+}
+
+// Sleep until the first held-back disconnect is due; never without one
+async fn next_debounced_disconnect(pending: &HashMap<Address, Instant>) {
+    match pending.values().min() {
+        Some(deadline) => sleep_until(*deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+// Sleep until the rate limit lets held-back alerts out; never with none held
+async fn next_held_flush(notifier: &NotificationManager) {
+    match notifier.held_flush_at() {
+        Some(at) => sleep_until(at.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_lock_change(watch: &mut Option<LockWatch>) -> Option<bool> {
+    match watch {
+        Some(watch) => watch.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Watch the session's lock state, holding popups back right away if it's already locked
+async fn start_lock_watch(
+    sinks: &mut StatusSinks,
+    bt_manager: &BluetoothManager,
+    kb_manager: &KeyboardManager,
+    scanners: &ScannerRegistry,
+) -> Option<LockWatch> {
+    match LockWatch::start().await {
+        Ok((watch, locked)) => {
+            if locked {
+                sinks.screen_lock.lock(collect_device_status(bt_manager, kb_manager, scanners));
+            }
+            Some(watch)
+        }
+        Err(e) => {
+            warn!("Failed to watch the session's lock state: {}", e);
+            None
+        }
+    }
+}
+
+async fn start_battery_provider(adapter: Option<&Adapter>) -> Option<BatteryProvider> {
+    match BatteryProvider::start(adapter?.name()).await {
+        Ok(provider) => Some(provider),
+        Err(e) => {
+            info!("Not publishing battery levels to BlueZ, which needs its experimental features on: {}", e);
+            None
+        }
+    }
+}
+
+fn adapter_of(bluetooth: &Option<BluetoothLink>) -> Option<&Adapter> {
+    bluetooth.as_ref().map(|link| &link.adapter)
+}
+
+// Set up Bluetooth monitoring, logging why when there's no adapter to use
+async fn connect_bluetooth() -> Option<BluetoothLink> {
+    match BluetoothLink::connect().await {
+        Ok(link) => Some(link),
+        Err(e) => {
+            warn!("Bluetooth unavailable: {}", e);
+            None
+        }
+    }
+}
+
+// Tell the tray and D-Bus clients whether Bluetooth devices can be monitored
+async fn report_adapter_state(sinks: &StatusSinks, available: bool) {
+    if let Some(tray) = &sinks.tray {
+        BatteryTray::set_bluetooth_available(tray, available).await;
+    }
+    if let Some(dbus) = &sinks.dbus
+        && let Err(e) = dbus.set_bluetooth_available(available).await
+    {
+        warn!("Failed to update D-Bus adapter state: {}", e);
+    }
+}
+
+// Tell the tray and D-Bus when a scanner starts or stops failing
+async fn report_health(sinks: &mut StatusSinks, health: MonitorHealth) {
+    if health == sinks.health {
+        return;
+    }
+    if let Some(tray) = &sinks.tray {
+        let failing = health.failing.iter().map(|scanner| scanner.name.to_string()).collect();
+        BatteryTray::set_failing_scanners(tray, failing).await;
+    }
+    if let Some(dbus) = &sinks.dbus
+        && let Err(e) = dbus.set_health(&health).await
+    {
+        warn!("Failed to update D-Bus health: {}", e);
+    }
+    sinks.health = health;
+}
+
+// How long connecting from the tray may take before giving up
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+async fn connect_device(adapter: &Adapter, device_id: &str) -> Result<(Address, Device), Box<dyn std::error::Error>> {
+    let addr: Address = device_id.parse()?;
+    let device = adapter.device(addr)?;
+    info!("Connecting to {}...", addr);
+    tokio::time::timeout(CONNECT_TIMEOUT, device.connect())
+        .await
+        .map_err(|_| format!("timed out after {}s", CONNECT_TIMEOUT.as_secs()))??;
+    Ok((addr, device))
+}
+
+async fn disconnect_device(adapter: &Adapter, device_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let addr: Address = device_id.parse()?;
+    adapter.device(addr)?.disconnect().await?;
+    Ok(())
+}
+
+async fn forget_device(adapter: &Adapter, device_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let addr: Address = device_id.parse()?;
+    adapter.remove_device(addr).await?;
+    info!("Removed pairing for {}", addr);
+    Ok(())
+}
+
+// Paired Bluetooth devices that aren't connected, as (MAC address, name)
+async fn paired_devices(adapter: &Adapter, bt_manager: &BluetoothManager) -> bluer::Result<Vec<(String, String)>> {
+    let mut paired = Vec::new();
+    for addr in adapter.device_addresses().await? {
+        let device = adapter.device(addr)?;
+        if device.is_paired().await? && !device.is_connected().await? {
+            let name = match bt_manager.aliases.get(&addr.to_string().to_uppercase()) {
+                Some(alias) => alias.clone(),
+                None => device.alias().await?,
+            };
+            paired.push((addr.to_string(), name));
+        }
+    }
+    paired.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(paired)
+}
+
+// Refresh the tray's Connect menu
+async fn update_paired_menu(adapter: Option<&Adapter>, bt_manager: &BluetoothManager, sinks: &StatusSinks) {
+    let Some(tray) = &sinks.tray else {
+        return;
+    };
+    let Some(adapter) = adapter else {
+        BatteryTray::set_paired_devices(tray, Vec::new()).await;
+        return;
+    };
+    match paired_devices(adapter, bt_manager).await {
+        Ok(paired) => BatteryTray::set_paired_devices(tray, paired).await,
+        Err(e) => warn!("Failed to list paired Bluetooth devices: {}", e),
+    }
+}
+
+async fn refresh_bluetooth(adapter: &Adapter, bt_manager: &mut BluetoothManager) -> bluer::Result<bool> {
+    let mut bt_updated = false;
+    let addresses: Vec<_> = bt_manager.connected_devices.keys().cloned().collect();
+    for addr in addresses {
+        let device = adapter.device(addr)?;
+        if let Ok(Some(updated_device)) = BluetoothDevice::from_device(device, addr).await {
+            if bt_manager.update_device(addr, updated_device) {
+                bt_updated = true;
+            }
+        }
+    }
+    Ok(bt_updated)
+}
+
+// Re-read battery levels for known devices, returning whether anything changed
+async fn refresh_devices(
+    adapter: Option<&Adapter>,
+    bt_manager: &mut BluetoothManager,
+    kb_manager: &mut KeyboardManager,
+    scanners: &mut ScannerRegistry,
+) -> bluer::Result<bool> {
+    // Bluetooth and the other scanners run side by side, and a stuck BlueZ
+    // call is given up on after the scan timeout instead of stalling the loop
+    let timeout = scanners.timeout();
+    let bt_span = info_span!(
+        "scan.bluetooth",
+        devices = bt_manager.connected_devices.len(),
+        elapsed_ms = field::Empty
+    );
+    let bt_refresh = async {
+        match adapter {
+            Some(adapter) => refresh_bluetooth(adapter, bt_manager).await,
+            None => Ok(false),
+        }
+    };
+    // Keyboard batteries, read on a blocking thread alongside the others
+    let kb_count_before = kb_manager.connected_keyboards.len();
+    let usb_span = info_span!("scan.usb", devices = kb_count_before, elapsed_ms = field::Empty);
+    let kb_refresh = async {
+        if let Err(e) = kb_manager.update_battery_levels_off_thread().await {
+            warn!("Failed to update keyboard batteries: {}", e);
+        }
+
+        // Rescan for new keyboards occasionally
+        if kb_count_before == 0 {
+            if let Err(e) = kb_manager.scan_for_keyboards_off_thread().await {
+                warn!("Failed to rescan keyboards: {}", e);
+            }
+        }
+    };
+    let (bt_result, scanners_updated, ()) = tokio::join!(
+        timed(bt_span, tokio::time::timeout(timeout, bt_refresh)),
+        scanners.scan_all(),
+        timed(usb_span, kb_refresh),
+    );
+    // A Bluetooth error is only returned once the others are refreshed
+    let bt_result = bt_result.unwrap_or_else(|_| {
+        warn!("Bluetooth refresh timed out after {}s", timeout.as_secs());
+        Ok(false)
+    });
+
+    Ok(bt_result? || scanners_updated || kb_count_before != kb_manager.connected_keyboards.len())
+}
+
+// Scan Bluetooth, keyboards and other scanners once without starting the monitoring loop
+async fn scan_once(config: &Config) -> Result<(BluetoothManager, KeyboardManager, ScannerRegistry), Box<dyn std::error::Error>> {
+    let mut bt_manager = BluetoothManager::new();
+    let mut kb_manager = KeyboardManager::new()?;
+    let mut scanners = ScannerRegistry::from_config(&config.scanners);
+    bt_manager.aliases = config.aliases();
+    kb_manager.aliases = config.aliases();
+
+    scanners.scan_all().await;
+
+    if let Err(e) = kb_manager.scan_for_keyboards() {
+        warn!("Failed to scan keyboards: {}", e);
+    }
+
+    let session = bluer::Session::new().await?;
+    let adapter = session.default_adapter().await?;
+    for addr in adapter.device_addresses().await? {
+        let device = adapter.device(addr)?;
+        if let Ok(Some(bt_device)) = BluetoothDevice::from_device(device, addr).await {
+            bt_manager.add_device(bt_device);
+        }
+    }
+
+    Ok((bt_manager, kb_manager, scanners))
+}
+
+async fn current_devices(config: &Config) -> Result<Vec<DeviceStatus>, Box<dyn std::error::Error>> {
+    // Prefer the running monitor's view and fall back to scanning ourselves
+    match control::send_command("list").await {
+        Ok(response) => Ok(serde_json::from_str(&response)?),
+        Err(_) => {
+            let (bt_manager, kb_manager, scanners) = scan_once(config).await?;
+            Ok(collect_device_status(&bt_manager, &kb_manager, &scanners))
+        }
+    }
+}
+
+// Render the device list for `status`; in follow mode everything goes on one
+// line so status bar script modules can read it line by line
+fn format_status(
+    devices: &[DeviceStatus],
+    low_threshold: u8,
+    json: bool,
+    format: Option<&str>,
+    follow: bool,
+) -> Result<String, serde_json::Error> {
+    if json {
+        return if follow { serde_json::to_string(devices) } else { serde_json::to_string_pretty(devices) };
+    }
+
+    if let Some(template) = format {
+        return Ok(devices
+            .iter()
+            .map(|d| output::format_template(template, d, low_threshold))
+            .collect::<Vec<_>>()
+            .join("  "));
+    }
+
+    if devices.is_empty() {
+        return Ok("No devices connected".to_string());
+    }
+    Ok(devices
+        .iter()
+        .map(|d| d.format_line())
+        .collect::<Vec<_>>()
+        .join(if follow { " | " } else { "\n" }))
+}
+
+async fn print_status(config: &Config, json: bool, format: Option<&str>, follow: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if !follow {
+        let devices = current_devices(config).await?;
+        println!("{}", format_status(&devices, config.notifications.low_battery_threshold, json, format, false)?);
+        return Ok(());
+    }
+
+    // Only print when something changed, so bars don't redraw needlessly
+    let mut last_line = None;
+    let mut ticker = tokio::time::interval(FOLLOW_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let devices = match current_devices(config).await {
+            Ok(devices) => devices,
+            Err(e) => {
+                eprintln!("Failed to read device status: {}", e);
+                continue;
+            }
+        };
+
+        let line = format_status(&devices, config.notifications.low_battery_threshold, json, format, true)?;
+        if last_line.as_ref() != Some(&line) {
+            let mut stdout = std::io::stdout().lock();
+            writeln!(stdout, "{}", line)?;
+            stdout.flush()?;
+            last_line = Some(line);
+        }
+    }
+}
+
+async fn run_list(config: &Config, json: bool, quiet_exit_code: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let (bt_manager, kb_manager, scanners) = scan_once(config).await?;
+    let devices = collect_device_status(&bt_manager, &kb_manager, &scanners);
+
+    if quiet_exit_code {
+        let threshold = config.notifications.low_battery_threshold;
+        if devices.iter().any(|d| d.battery_percentage.is_some_and(|battery| battery <= threshold)) {
+            std::process::exit(LOW_BATTERY_EXIT_CODE);
+        }
+    } else if json {
+        println!("{}", serde_json::to_string_pretty(&devices)?);
+    } else {
+        print!("{}", output::device_table(&devices));
+    }
+    Ok(())
+}
+
+// The running monitor's scanners and devices, or a scan of our own without one
+async fn diagnostics_scan(config: &Config) -> Result<ScanResults, String> {
+    if let (Ok(stats), Ok(devices)) = (control::send_command("stats").await, control::send_command("list").await) {
+        return Ok(ScanResults {
+            source: "running monitor",
+            scanners: serde_json::from_str(&stats).map_err(|e| e.to_string())?,
+            devices: serde_json::from_str(&devices).map_err(|e| e.to_string())?,
+        });
+    }
+    let (bt_manager, kb_manager, scanners) = scan_once(config).await.map_err(|e| e.to_string())?;
+    Ok(ScanResults {
+        source: "one-off scan",
+        scanners: serde_json::to_value(scanners.stats()).map_err(|e| e.to_string())?,
+        devices: collect_device_status(&bt_manager, &kb_manager, &scanners),
+    })
+}
+
+#[cfg(feature = "tui")]
+async fn run_watch(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let low_threshold = config.notifications.low_battery_threshold;
+    battery_percentage::tui::run(low_threshold, FOLLOW_INTERVAL, async || current_devices(config).await).await
+}
 
-    println!("Status: {}", combined_status);
+#[cfg(not(feature = "tui"))]
+async fn run_watch(_config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    Err("this build has no terminal UI; rebuild with --features tui".into())
+}
+
+fn print_notification_log(since: Option<Duration>, device: Option<&str>, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let since = since.map_or(0, |since| {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().saturating_sub(since).as_secs()
+    });
+    let records = NotificationLog::read(since, device)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&records)?);
+    } else if records.is_empty() {
+        println!("No notifications");
+    } else {
+        for record in &records {
+            println!("{}", record.format_line());
+        }
+    }
+
+    Ok(())
+}
+
+fn print_stats(device: &str, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let samples = BatteryHistory::read(0, Some(device))?;
+    let Some(first) = samples.first() else {
+        return Err(format!("No battery history for '{}'", device).into());
+    };
+    let alerts = NotificationLog::read(history::month_start(), Some(&first.device_id))?
+        .iter()
+        .filter(|record| {
+            record.event == NotificationType::LowBattery.key() || record.event == NotificationType::CriticalBattery.key()
+        })
+        .count();
+    let stats = history::device_stats(&samples, alerts).ok_or("No battery history")?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+
+    let unknown = || "not enough history yet".to_string();
+    println!("{} ({})", stats.device_name, stats.device_id);
+    println!(
+        "  Average drain:       {}",
+        stats.drain_per_hour.map_or_else(unknown, |rate| format!("{:.1}% per hour", rate))
+    );
+    println!("  Typical charge:      {}", stats.typical_charge_hours.map_or_else(unknown, format_hours));
+    println!("  Last full charge:    {}", stats.last_full_charge.map_or("never seen".to_string(), local_time));
+    println!("  Alerts this month:   {}", stats.alerts_this_month);
+    println!("  Based on {} readings since {}", stats.samples, local_time(stats.since));
+
+    let most = stats.hours_by_level.iter().copied().fold(0.0, f64::max);
+    if most > 0.0 {
+        println!();
+        println!("  Time spent at each level:");
+        for (band, hours) in stats.hours_by_level.iter().enumerate().rev() {
+            let low = band as u8 * history::LEVEL_BAND;
+            let high = if band == stats.hours_by_level.len() - 1 { 100 } else { low + history::LEVEL_BAND - 1 };
+            let bar = "█".repeat((hours / most * HISTOGRAM_WIDTH as f64).round() as usize);
+            let range = format!("{}-{}%", low, high);
+            println!("  {:>7} {:<width$} {}", range, bar, format_hours(*hours), width = HISTOGRAM_WIDTH);
+        }
+    }
+    Ok(())
+}
+
+fn format_hours(hours: f64) -> String {
+    if hours < 1.0 {
+        format!("{} minutes", (hours * 60.0).round())
+    } else if hours < 48.0 {
+        format!("{:.1} hours", hours)
+    } else {
+        format!("{} days {} hours", (hours / 24.0).floor(), (hours % 24.0).round())
+    }
+}
+
+fn local_time(timestamp: u64) -> String {
+    chrono::Local
+        .timestamp_opt(timestamp as i64, 0)
+        .single()
+        .map_or_else(|| timestamp.to_string(), |time| time.format("%Y-%m-%d %H:%M").to_string())
+}
+
+fn run_export(
+    format: ExportFormat,
+    device: Option<&str>,
+    since: Option<Duration>,
+    output: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let since = since.map_or(0, |since| {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().saturating_sub(since).as_secs()
+    });
+    let samples = BatteryHistory::read(since, device)?;
+    let exported = history::export(&samples, format)?;
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, exported)?;
+            eprintln!("Exported {} samples to {}", samples.len(), path.display());
+        }
+        None => print!("{}", exported),
+    }
+
+    Ok(())
+}
+
+// How long to keep the runtime alive for deliveries spawned by the sinks
+const DELIVERY_WAIT: Duration = Duration::from_secs(5);
+
+async fn run_test_notification(
+    config: &Config,
+    notification_type: NotificationType,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut sinks = config.notifications.clone();
+    // Keep test alerts out of `battery_percentage log`
+    sinks.history = false;
+    // and show them right away, not after a digest window
+    sinks.digest_secs = 0;
+    let mut notifier = NotificationManager::new(NotificationConfig {
+        low_battery_threshold: config.notifications.low_battery_threshold,
+        critical_battery_threshold: config.notifications.critical_battery_threshold,
+        // Nothing would be around to handle a button click
+        actions: false,
+        sinks,
+        ..Default::default()
+    });
+
+    let sink_names = notifier.sink_names();
+    if sink_names.is_empty() {
+        return Err("no notification channels are enabled under [notifications]".into());
+    }
+
+    if dry_run {
+        println!("{}", notification_type.title());
+        println!("Would be sent to: {}", sink_names.join(", "));
+        return Ok(());
+    }
+
+    match notifier.send_test(notification_type) {
+        Some(sent) => println!("Sent a test {} alert to: {}", sent.key(), sink_names.join(", ")),
+        None => return Err("the test device is muted".into()),
+    }
+    tokio::time::sleep(DELIVERY_WAIT).await;
+
+    Ok(())
+}
+
+// One pass of the monitor for cron jobs and scripts: scan, alert through the
+// configured channels as the monitor would and print the devices. Returns the
+// exit code, LOW_BATTERY_EXIT_CODE when any device is low.
+async fn run_oneshot(config: &Config) -> Result<i32, Box<dyn std::error::Error>> {
+    let (bt_manager, kb_manager, scanners) = scan_once(config).await?;
+    let devices = collect_device_status(&bt_manager, &kb_manager, &scanners);
+
+    let mut sinks = config.notifications.clone();
+    // Nothing stays around to collect a digest
+    sinks.digest_secs = 0;
+    let mut notifier = NotificationManager::new(NotificationConfig {
+        low_battery_threshold: config.notifications.low_battery_threshold,
+        critical_battery_threshold: config.notifications.critical_battery_threshold,
+        // or to handle a button click
+        actions: false,
+        sinks,
+        ..Default::default()
+    });
+    notifier.restore(SavedAlerts::load());
+
+    // Every device counts as coming from full, so each one that's low alerts
+    // unless the last run already did within the suppression window
+    let mut thresholds = ThresholdTracker::default();
+    let mut sent = false;
+    for crossing in thresholds.update(&devices, &notifier.config.thresholds()) {
+        sent |= notifier.handle_crossing(&crossing).is_some();
+    }
+    // Nothing stays around to send what the rate limit held back later
+    notifier.send_held();
+    SavedAlerts::save(notifier.snapshot());
+
+    print!("{}", output::device_table(&devices));
+    if sent {
+        // Desktop and webhook alerts go out in the background
+        tokio::time::sleep(DELIVERY_WAIT).await;
+    }
+
+    let threshold = config.notifications.low_battery_threshold;
+    if devices.iter().any(|d| d.battery_percentage.is_some_and(|battery| battery <= threshold)) {
+        return Ok(LOW_BATTERY_EXIT_CODE);
+    }
+    Ok(0)
+}
+
+async fn run_ctl(command: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    // The monitor may run in another directory, so hand it an absolute path
+    let mut command = command.to_vec();
+    if let [name, path] = command.as_mut_slice()
+        && name == "snapshot"
+    {
+        *path = std::path::absolute(&*path)?.display().to_string();
+    }
+
+    let response = control::send_command(&command.join(" "))
+        .await
+        .map_err(|e| format!("Failed to reach the battery monitor at {}: {}", control::socket_path().display(), e))?;
+
+    println!("{}", response);
+    if response.starts_with("error:") {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn run_config(config: &Config, action: ConfigAction) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        ConfigAction::Get { key: Some(key) } => match config.get(&key)? {
+            toml::Value::String(value) => println!("{}", value),
+            toml::Value::Table(table) => print!("{}", toml::to_string(&table)?),
+            value => println!("{}", value),
+        },
+        ConfigAction::Get { key: None } => print!("{}", toml::to_string(config)?),
+        ConfigAction::Set { key, value } => {
+            Config::set(&key, &value)?;
+            println!("Set {} in {}", key, config_path().display());
+
+            // Not running is fine; the new value is picked up on the next start
+            if let Ok(response) = control::send_command("reload").await {
+                println!("Running monitor: {}", response);
+            }
+        }
+        ConfigAction::Path => println!("{}", config_path().display()),
+    }
+
+    Ok(())
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!("Starting device battery monitor...");
-    println!("Monitoring Bluetooth devices and keyboards for battery status");
+    // The shell's completion script calls back in here; answers and exits
+    CompleteEnv::with_factory(Cli::command).var(COMPLETE_VAR).complete();
+    let cli = Cli::parse();
+    let _log_guard = logging::init(cli.log_file.as_deref(), cli.log_format)?;
+    let mut config = Config::load_or_default();
+    cli.overrides.apply(&mut config);
+    // Held to the same rules as a reload; the commands that inspect or fix
+    // the config still run
+    let fixes_config =
+        matches!(cli.command, Some(Commands::Config { .. } | Commands::Setup | Commands::Doctor | Commands::Diagnostics));
+    if let Err(e) = config.validate()
+        && !fixes_config
+    {
+        eprintln!("Invalid configuration: {}", e);
+        std::process::exit(1);
+    }
+
+    match cli.command {
+        Some(Commands::Status { json, format, follow }) => print_status(&config, json, format.as_deref(), follow).await,
+        Some(Commands::List { json, quiet_exit_code }) => run_list(&config, json, quiet_exit_code).await,
+        Some(Commands::Watch) => run_watch(&config).await,
+        Some(Commands::Ctl { command }) => run_ctl(&command).await,
+        Some(Commands::InstallService { enable, no_enable }) => service::install(enable || (config.auto_start && !no_enable)),
+        Some(Commands::UninstallService) => service::uninstall(),
+        Some(Commands::Log { since, device, json }) => print_notification_log(since, device.as_deref(), json),
+        Some(Commands::Export { format, device, since, output }) => {
+            run_export(format, device.as_deref(), since, output.as_deref())
+        }
+        Some(Commands::Stats { device, json }) => print_stats(&device, json),
+        Some(Commands::TestNotification { notification_type, dry_run }) => {
+            run_test_notification(&config, notification_type, dry_run).await
+        }
+        Some(Commands::Doctor) => {
+            if !doctor::run().await {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(Commands::Config { action }) => run_config(&config, action).await,
+        Some(Commands::Diagnostics) => {
+            print!("{}", diagnostics::report(&config, diagnostics_scan(&config).await, cli.log_file.as_deref()));
+            Ok(())
+        }
+        Some(Commands::Completions { shell }) => Ok(completions::write_registration(shell, &mut std::io::stdout())?),
+        Some(Commands::GenerateMan { output_dir: Some(dir) }) => {
+            for path in man::write_pages(&dir)? {
+                println!("Wrote {}", path.display());
+            }
+            Ok(())
+        }
+        Some(Commands::GenerateMan { output_dir: None }) => man::write_main_page(&mut std::io::stdout()),
+        Some(Commands::Setup) => setup::run(&config, async || current_devices(&config).await).await,
+        // Scans on its own alongside a running monitor, so it takes no lock
+        None if cli.oneshot => {
+            let code = run_oneshot(&config).await?;
+            if code != 0 {
+                drop(_log_guard);
+                std::process::exit(code);
+            }
+            Ok(())
+        }
+        None => {
+            // Only one monitor may scan at a time; hold the lock until it exits
+            let _lock = match InstanceLock::acquire() {
+                Ok(lock) => lock,
+                Err(LockError::AlreadyRunning(_)) if cli.show_details => {
+                    return run_ctl(&["show-details".to_string()]).await;
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    if let LockError::AlreadyRunning(_) = e {
+                        eprintln!("Use `battery_percentage ctl` to talk to the running monitor.");
+                    }
+                    std::process::exit(1);
+                }
+            };
+            // First launch from a terminal: point at the walkthrough
+            if !config_path().exists() && std::io::stdin().is_terminal() {
+                eprintln!("No config file yet; `battery_percentage setup` walks through one. Using defaults.");
+            }
+            let restore = match cli.restore.as_deref().map(Snapshot::load).transpose() {
+                Ok(restore) => restore,
+                Err(e) => {
+                    eprintln!("Failed to load snapshot: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let result = run_monitor(
+                config,
+                cli.overrides.clone(),
+                cli.status_outputs(),
+                !cli.no_tray,
+                cli.show_details,
+                restore,
+            )
+            .await;
+            systemd::notify_stopping();
+            result
+        }
+    }
+}
+
+async fn run_monitor(
+    mut config: Config,
+    overrides: ConfigOverrides,
+    mut outputs: Vec<StatusOutput>,
+    show_tray: bool,
+    show_details_on_start: bool,
+    restore: Option<Snapshot>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Starting device battery monitor...");
+    info!("Monitoring Bluetooth devices and keyboards for battery status");
 
     // Initialize managers
     let mut bt_manager = BluetoothManager::new();
+    let mut notifier = NotificationManager::new(NotificationConfig {
+        low_battery_threshold: config.notifications.low_battery_threshold,
+        critical_battery_threshold: config.notifications.critical_battery_threshold,
+        sinks: config.notifications.clone(),
+        ..Default::default()
+    });
     let mut kb_manager = match KeyboardManager::new() {
         Ok(manager) => manager,
         Err(e) => {
-            eprintln!("Failed to initialize keyboard manager: {}", e);
-            eprintln!("Continuing with Bluetooth-only monitoring...");
+            warn!("Failed to initialize keyboard manager: {}", e);
+            info!("Continuing with Bluetooth-only monitoring...");
             // Create a fallback that will have no keyboards
             KeyboardManager::new().unwrap_or_else(|_| panic!("Failed to create fallback keyboard manager"))
         }
     };
 
+    bt_manager.aliases = config.aliases();
+    kb_manager.aliases = config.aliases();
+    bt_manager.battery_filter = BatteryFilter::new(config.smoothing.clone());
+    kb_manager.battery_filter = BatteryFilter::new(config.smoothing.clone());
+
     // Initial keyboard scan
-    println!("Scanning for keyboards...");
-    if let Err(e) = kb_manager.scan_for_keyboards() {
-        eprintln!("Warning: Failed to scan keyboards: {}", e);
+    info!("Scanning for keyboards...");
+    if let Err(e) = kb_manager.scan_for_keyboards_off_thread().await {
+        warn!("Failed to scan keyboards: {}", e);
     }
 
-    // Setup Bluetooth monitoring
-    let session = bluer::Session::new().await?;
-    let adapter = session.default_adapter().await?;
-    adapter.set_powered(true).await?;
+    // Optional device sources beyond Bluetooth and keyboards
+    let mut scanners = ScannerRegistry::from_config(&config.scanners);
+    scanners.battery_filter = BatteryFilter::new(config.smoothing.clone());
+    if let Some(snapshot) = restore {
+        info!("Restoring snapshot from {} with {} devices", snapshot.timestamp, snapshot.devices.len());
+        scanners.register(Box::new(SnapshotScanner::new(snapshot.devices)));
+        notifier.restore(snapshot.notifications);
+    }
+    scanners.scan_all().await;
 
-    let filter = DiscoveryFilter {
-        transport: DiscoveryTransport::Auto,
-        ..Default::default()
+    // Setup the tray icon, if a StatusNotifierItem host is available
+    let (tray, mut tray_actions) = if show_tray {
+        let thresholds = (config.notifications.low_battery_threshold, config.notifications.critical_battery_threshold);
+        match BatteryTray::start(config.tray_pins(), config.icon_map(), thresholds).await {
+            Ok((handle, actions)) => (Some(handle), Some(actions)),
+            Err(e) => {
+                warn!("Failed to create tray icon: {}", e);
+                (None, None)
+            }
+        }
+    } else {
+        (None, None)
     };
 
-    adapter.set_discovery_filter(filter).await?;
+    let mut notification_actions = notifier.take_action_receiver();
 
-    let device_events = adapter.discover_devices().await?;
-    pin_mut!(device_events);
+    let mqtt = config.mqtt.enabled.then(|| MqttPublisher::start(config.mqtt.clone()));
+    if let Some(command) = &config.hooks.on_low_battery {
+        notifier.add_sink(Box::new(LowBatteryHook {
+            command: command.clone(),
+            timeout: Duration::from_secs(config.hooks.timeout_secs),
+        }));
+    }
+    let hooks = (config.hooks.on_connect.is_some() || config.hooks.on_disconnect.is_some())
+        .then(|| ConnectionHooks::new(config.hooks.clone()));
 
-    let mut all_change_events = SelectAll::new();
+    // Publish status on the session bus for shell extensions and widgets
+    let dbus = match DbusService::start().await {
+        Ok(service) => Some(service),
+        Err(e) => {
+            warn!("Failed to register on D-Bus: {}", e);
+            None
+        }
+    };
+
+    // Listen for commands from `ctl`, the HTTP API and other clients
+    let (control_sender, control_receiver) = mpsc::unbounded_channel();
+    if let Err(e) = control::start_server(control_sender.clone()) {
+        warn!("Failed to create control socket: {}", e);
+    }
+    let mut control_requests = Some(control_receiver);
+
+    let api = if config.api.enabled {
+        match ApiServer::start(&config.api, control_sender.clone()).await {
+            Ok(server) => Some(server),
+            Err(e) => {
+                warn!("Failed to start the HTTP API on {}: {}", config.api.listen, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let history = config.history.enabled.then(|| BatteryHistory::new(&config.history));
+    outputs.extend(config.status_files.iter().cloned().map(|file| StatusOutput::Template(TemplateFile::new(file))));
+    let mut sinks = StatusSinks {
+        outputs,
+        tray,
+        mqtt,
+        dbus,
+        hooks,
+        history,
+        api,
+        thresholds: ThresholdTracker::default(),
+        screen_lock: LockedChanges::default(),
+        last_known: LastKnown::load(),
+        health: MonitorHealth::default(),
+        hid_access_reported: false,
+        battery_provider: None,
+    };
+
+    // Setup Bluetooth monitoring. Without an adapter everything else still
+    // runs, and Bluetooth is picked up once one appears.
+    let mut bluetooth = connect_bluetooth().await;
+    let (_adapter_watch_session, mut adapter_watch) = match adapter::watch_adapters().await {
+        Ok((session, watch)) => (Some(session), Some(watch)),
+        Err(e) => {
+            warn!("Failed to watch for Bluetooth adapters: {}", e);
+            (None, None)
+        }
+    };
+
+    if config.bluez_battery_provider {
+        sinks.battery_provider = start_battery_provider(adapter_of(&bluetooth)).await;
+    }
+
+    // Stop scanning over suspend and rescan as soon as the system wakes
+    let mut sleep_watch = match SleepWatch::start().await {
+        Ok(watch) => Some(watch),
+        Err(e) => {
+            warn!("Failed to watch for suspend and resume: {}", e);
+            None
+        }
+    };
+    let mut suspended = false;
+
+    // Keep connect/disconnect popups off the lock screen
+    let mut lock_watch = None;
+    if notifier.config.sinks.quiet_when_locked {
+        lock_watch = start_lock_watch(&mut sinks, &bt_manager, &kb_manager, &scanners).await;
+    }
 
     // Initial status update
-    update_status_display(&bt_manager, &kb_manager);
+    update_status_display(&bt_manager, &kb_manager, &scanners, &mut notifier, &mut sinks).await;
+    if show_details_on_start {
+        show_details(&bt_manager, &kb_manager, &scanners, &sinks.last_known);
+    }
+    if bluetooth.is_none() {
+        report_adapter_state(&sinks, false).await;
+    }
+    update_paired_menu(adapter_of(&bluetooth), &bt_manager, &sinks).await;
+
+    // Under systemd, report readiness and keep the watchdog fed from the
+    // main loop so a hung loop gets the service restarted
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    // SIGHUP reloads the config, SIGUSR1 rescans and SIGUSR2 logs the current state
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut rescan_signal = signal(SignalKind::user_defined1())?;
+    let mut dump_signal = signal(SignalKind::user_defined2())?;
+    let mut watchdog = systemd::watchdog_interval().map(tokio::time::interval);
+    systemd::notify_ready();
+
+    // Deadlines rather than per-iteration sleeps, so frequent events and
+    // watchdog pings can't keep pushing the periodic refreshes back
+    let mut poll_scheduler = PollScheduler::new(config.polling.clone());
+    let mut next_refresh = Instant::now() + poll_scheduler.next_interval(
+        &collect_device_status(&bt_manager, &kb_manager, &scanners),
+        notifier.config.low_battery_threshold,
+    );
+    let mut keyboard_rescan_timer = interval_at(Instant::now() + KEYBOARD_RESCAN_INTERVAL, KEYBOARD_RESCAN_INTERVAL);
+    // Bluetooth devices that dropped, kept as connected until their deadline
+    // in case they come right back
+    let mut pending_disconnects: HashMap<Address, Instant> = HashMap::new();
+    let mut disconnect_debounce = Duration::from_secs(config.polling.disconnect_debounce_secs);
+    // Devices disconnected from the tray, which go away without the debounce
+    let mut user_disconnects: HashSet<Address> = HashSet::new();
 
     loop {
+        // Set by the branches below, handled once the select is done with `bluetooth`
+        let mut bluetooth_lost = false;
+        let mut reconnect_bluetooth = false;
+
         tokio::select! {
-            Some(device_event) = device_events.next() => {
-                match device_event {
-                    AdapterEvent::DeviceAdded(addr) => {
-                        let device = adapter.device(addr)?;
+            event = next_link_event(&mut bluetooth) => {
+                let Some(link) = &mut bluetooth else { continue };
+                match event {
+                    LinkEvent::Adapter(AdapterEvent::DeviceAdded(addr)) => {
+                        let device = link.adapter.device(addr)?;
 
                         if let Ok(Some(bt_device)) = BluetoothDevice::from_device(device.clone(), addr).await {
+                            pending_disconnects.remove(&addr);
                             bt_manager.add_device(bt_device);
-                            update_status_display(&bt_manager, &kb_manager);
+                            update_status_display(&bt_manager, &kb_manager, &scanners, &mut notifier, &mut sinks).await;
+                            update_paired_menu(Some(&link.adapter), &bt_manager, &sinks).await;
 
-                            let change_events = device.events().await?.map(move |evt| (addr, evt));
-                            all_change_events.push(change_events);
+                            if let Err(e) = link.watch(&device, addr).await {
+                                warn!("Failed to watch {}: {}", addr, e);
+                            }
                         }
                     }
-                    AdapterEvent::DeviceRemoved(addr) => {
+                    LinkEvent::Adapter(AdapterEvent::DeviceRemoved(addr)) => {
+                        pending_disconnects.remove(&addr);
                         if bt_manager.remove_device(addr) {
-                            update_status_display(&bt_manager, &kb_manager);
+                            update_status_display(&bt_manager, &kb_manager, &scanners, &mut notifier, &mut sinks).await;
+                        }
+                    }
+                    LinkEvent::Device(addr, DeviceEvent::PropertyChanged(property)) => {
+                        if bt_manager.connected_devices.contains_key(&addr) {
+                            let device = link.adapter.device(addr)?;
+
+                            if matches!(property, DeviceProperty::ServicesResolved(true)) {
+                                link.subscribe_gatt_battery(&device, addr).await;
+                            }
+
+                            if device.is_connected().await.unwrap_or(false) {
+                                if pending_disconnects.remove(&addr).is_some() {
+                                    debug!("{} reconnected within the debounce window", addr);
+                                }
+                                user_disconnects.remove(&addr);
+                                if let Ok(Some(updated_device)) = BluetoothDevice::from_device(device, addr).await {
+                                    if bt_manager.update_device(addr, updated_device) {
+                                        poll_scheduler.record_change();
+                                        update_status_display(&bt_manager, &kb_manager, &scanners, &mut notifier, &mut sinks).await;
+                                    }
+                                }
+                            } else if user_disconnects.remove(&addr) || disconnect_debounce.is_zero() {
+                                pending_disconnects.remove(&addr);
+                                if bt_manager.remove_device(addr) {
+                                    update_status_display(&bt_manager, &kb_manager, &scanners, &mut notifier, &mut sinks).await;
+                                    update_paired_menu(Some(&link.adapter), &bt_manager, &sinks).await;
+                                }
+                            } else if !pending_disconnects.contains_key(&addr) {
+                                debug!("{} dropped, waiting {:?} before treating it as disconnected", addr, disconnect_debounce);
+                                pending_disconnects.insert(addr, Instant::now() + disconnect_debounce);
+                            }
+                        }
+                    }
+                    LinkEvent::Battery(addr, level) => {
+                        if bt_manager.update_battery(addr, level) {
+                            update_status_display(&bt_manager, &kb_manager, &scanners, &mut notifier, &mut sinks).await;
                         }
                     }
+                    LinkEvent::Lost => bluetooth_lost = true,
                     _ => (),
                 }
             }
-            Some((addr, DeviceEvent::PropertyChanged(_))) = all_change_events.next() => {
-                if bt_manager.connected_devices.contains_key(&addr) {
-                    let device = adapter.device(addr)?;
-
-                    if device.is_connected().await.unwrap_or(false) {
-                        if let Ok(Some(updated_device)) = BluetoothDevice::from_device(device, addr).await {
-                            if bt_manager.update_device(addr, updated_device) {
-                                update_status_display(&bt_manager, &kb_manager);
+            _ = next_debounced_disconnect(&pending_disconnects) => {
+                let now = Instant::now();
+                let due: Vec<Address> =
+                    pending_disconnects.iter().filter(|(_, deadline)| **deadline <= now).map(|(addr, _)| *addr).collect();
+                let mut removed = false;
+                for addr in due {
+                    pending_disconnects.remove(&addr);
+                    removed |= bt_manager.remove_device(addr);
+                }
+                if removed {
+                    update_status_display(&bt_manager, &kb_manager, &scanners, &mut notifier, &mut sinks).await;
+                    update_paired_menu(adapter_of(&bluetooth), &bt_manager, &sinks).await;
+                }
+            }
+            _ = next_held_flush(&notifier) => notifier.flush_held(),
+            Some(change) = next_adapter_change(&mut adapter_watch) => {
+                match change {
+                    SessionEvent::AdapterAdded(name) if bluetooth.is_none() => {
+                        info!("Bluetooth adapter {} appeared", name);
+                        reconnect_bluetooth = true;
+                    }
+                    SessionEvent::AdapterRemoved(name) => {
+                        bluetooth_lost = adapter_of(&bluetooth).is_some_and(|adapter| adapter.name() == name);
+                    }
+                    _ => (),
+                }
+            }
+            Some(action) = recv_or_pending(&mut tray_actions) => {
+                match action {
+                    TrayAction::ShowDetails => show_details(&bt_manager, &kb_manager, &scanners, &sinks.last_known),
+                    TrayAction::SnoozeDevice(device_id) => notifier.snooze_device(&device_id, SNOOZE_DURATION),
+                    TrayAction::MuteDevice(device_id) => notifier.mute_device(&device_id),
+                    TrayAction::UnmuteDevice(device_id) => notifier.unmute_device(&device_id),
+                    TrayAction::ConnectDevice(device_id) => {
+                        let Some(link) = &mut bluetooth else {
+                            warn!("Can't connect to {}: Bluetooth is unavailable", device_id);
+                            continue;
+                        };
+                        match connect_device(&link.adapter, &device_id).await {
+                            Ok((addr, device)) => {
+                                if let Ok(Some(bt_device)) = BluetoothDevice::from_device(device.clone(), addr).await
+                                    && !bt_manager.connected_devices.contains_key(&addr)
+                                {
+                                    bt_manager.add_device(bt_device);
+                                    if let Err(e) = link.watch(&device, addr).await {
+                                        warn!("Failed to watch {}: {}", addr, e);
+                                    }
+                                    update_status_display(&bt_manager, &kb_manager, &scanners, &mut notifier, &mut sinks).await;
+                                }
                             }
+                            Err(e) => warn!("Failed to connect to {}: {}", device_id, e),
                         }
-                    } else {
-                        if bt_manager.remove_device(addr) {
-                            update_status_display(&bt_manager, &kb_manager);
+                        update_paired_menu(Some(&link.adapter), &bt_manager, &sinks).await;
+                    }
+                    TrayAction::DisconnectDevice(device_id) => {
+                        // The Connected property change takes it off the device list,
+                        // right away since the user asked for it
+                        let addr = device_id.parse::<Address>().ok();
+                        user_disconnects.extend(addr);
+                        let result = match adapter_of(&bluetooth) {
+                            Some(adapter) => disconnect_device(adapter, &device_id).await,
+                            None => Err("Bluetooth is unavailable".into()),
+                        };
+                        if let Err(e) = result {
+                            warn!("Failed to disconnect {}: {}", device_id, e);
+                            if let Some(addr) = addr {
+                                user_disconnects.remove(&addr);
+                            }
                         }
                     }
+                    TrayAction::ForgetDevice(device_id) => {
+                        // BlueZ reports the removal as DeviceRemoved
+                        let result = match adapter_of(&bluetooth) {
+                            Some(adapter) => forget_device(adapter, &device_id).await,
+                            None => Err("Bluetooth is unavailable".into()),
+                        };
+                        if let Err(e) = result {
+                            warn!("Failed to remove pairing for {}: {}", device_id, e);
+                        }
+                    }
+                    TrayAction::Quit => {
+                        info!("Quit requested from tray");
+                        break;
+                    }
                 }
             }
-            _ = sleep(Duration::from_secs(30)) => {
-                println!("Periodic update check...");
-
-                // Update Bluetooth devices
-                let mut bt_updated = false;
-                let addresses: Vec<_> = bt_manager.connected_devices.keys().cloned().collect();
-                for addr in addresses {
-                    let device = adapter.device(addr)?;
-                    if let Ok(Some(updated_device)) = BluetoothDevice::from_device(device, addr).await {
-                        if bt_manager.update_device(addr, updated_device) {
-                            bt_updated = true;
+            Some(NotificationActionEvent { device_id, action }) = recv_or_pending(&mut notification_actions) => {
+                match action {
+                    NotificationAction::OpenDetails => show_details(&bt_manager, &kb_manager, &scanners, &sinks.last_known),
+                    NotificationAction::Snooze => notifier.snooze_device(&device_id, SNOOZE_DURATION),
+                    NotificationAction::Dismiss => notifier.mute_device(&device_id),
+                }
+            }
+            Some(ControlRequest { command, reply }) = recv_or_pending(&mut control_requests) => {
+                let response = match command {
+                    ControlCommand::List => {
+                        let devices = collect_device_status(&bt_manager, &kb_manager, &scanners);
+                        json_reply(&devices)
+                    }
+                    ControlCommand::Refresh => {
+                        let adapter = adapter_of(&bluetooth);
+                        if let Err(e) = refresh_devices(adapter, &mut bt_manager, &mut kb_manager, &mut scanners).await {
+                            warn!("Failed to refresh Bluetooth devices: {}", e);
+                        }
+                        report_health(&mut sinks, scanners.health()).await;
+                        update_status_display(&bt_manager, &kb_manager, &scanners, &mut notifier, &mut sinks).await;
+                        "ok".to_string()
+                    }
+                    ControlCommand::Stats => json_reply(&scanners.stats()),
+                    ControlCommand::Health => json_reply(&scanners.health()),
+                    ControlCommand::ShowDetails => {
+                        show_details(&bt_manager, &kb_manager, &scanners, &sinks.last_known);
+                        "ok".to_string()
+                    }
+                    ControlCommand::Mute { device: query, duration } => {
+                        let devices = collect_device_status(&bt_manager, &kb_manager, &scanners);
+                        match (find_device(&devices, &query), duration) {
+                            (Some(device), Some(duration)) => {
+                                notifier.snooze_device(&device.id, duration);
+                                format!("ok: muted {} for {} minutes", device.name, duration.as_secs() / 60)
+                            }
+                            (Some(device), None) => {
+                                notifier.mute_device(&device.id);
+                                format!("ok: muted {}", device.name)
+                            }
+                            (None, _) => format!("error: no connected device matches '{}'", query),
+                        }
+                    }
+                    ControlCommand::Unmute(query) => {
+                        let devices = collect_device_status(&bt_manager, &kb_manager, &scanners);
+                        // Allow unmuting by raw id even when the device isn't connected
+                        let device_id = find_device(&devices, &query).map(|d| d.id.clone()).unwrap_or(query);
+                        notifier.unmute_device(&device_id);
+                        format!("ok: unmuted {}", device_id)
+                    }
+                    ControlCommand::SetThreshold { tier, value } => {
+                        // Held to the config's rules, so critical can't end up above low
+                        let mut updated = config.clone();
+                        match tier {
+                            ThresholdTier::Low => updated.notifications.low_battery_threshold = value,
+                            ThresholdTier::Critical => updated.notifications.critical_battery_threshold = value,
+                        }
+                        match updated.validate() {
+                            Ok(()) => {
+                                config = updated;
+                                let (low, critical) =
+                                    (config.notifications.low_battery_threshold, config.notifications.critical_battery_threshold);
+                                notifier.config.low_battery_threshold = low;
+                                notifier.config.critical_battery_threshold = critical;
+                                if let Some(tray) = &sinks.tray {
+                                    BatteryTray::set_thresholds(tray, low, critical).await;
+                                }
+                                format!("ok: {:?} threshold set to {}%", tier, value)
+                            }
+                            Err(e) => format!("error: {}", e),
+                        }
+                    }
+                    ControlCommand::Snapshot(path) => {
+                        let devices = collect_device_status(&bt_manager, &kb_manager, &scanners);
+                        let snapshot = Snapshot::new(devices, notifier.snapshot());
+                        let path = path.unwrap_or_else(|| snapshot.default_path());
+                        match snapshot.save(&path) {
+                            Ok(()) => format!("ok: wrote {}", path.display()),
+                            Err(e) => format!("error: failed to write {}: {}", path.display(), e),
+                        }
+                    }
+                    ControlCommand::Reload => {
+                        let mut reloaded = Config::load_or_default();
+                        overrides.apply(&mut reloaded);
+                        match reloaded.validate() {
+                            Ok(()) => {
+                                // MQTT and hooks are set up once at startup and keep
+                                // their settings until the monitor restarts
+                                bt_manager.aliases = reloaded.aliases();
+                                kb_manager.aliases = reloaded.aliases();
+                                notifier.config.low_battery_threshold = reloaded.notifications.low_battery_threshold;
+                                notifier.config.critical_battery_threshold = reloaded.notifications.critical_battery_threshold;
+                                notifier.set_sinks(reloaded.notifications.clone());
+                                disconnect_debounce = Duration::from_secs(reloaded.polling.disconnect_debounce_secs);
+                                lock_watch = match (lock_watch.take(), reloaded.notifications.quiet_when_locked) {
+                                    (None, true) => start_lock_watch(&mut sinks, &bt_manager, &kb_manager, &scanners).await,
+                                    (_, false) => {
+                                        // Let popups through again, without a summary
+                                        sinks.screen_lock.unlock(&[]);
+                                        None
+                                    }
+                                    (watch, true) => watch,
+                                };
+                                poll_scheduler = PollScheduler::new(reloaded.polling.clone());
+                                // Otherwise a new interval only applies after the old one runs out
+                                let devices = collect_device_status(&bt_manager, &kb_manager, &scanners);
+                                next_refresh = Instant::now()
+                                    + poll_scheduler.next_interval(&devices, notifier.config.low_battery_threshold);
+                                if let Some(tray) = &sinks.tray {
+                                    BatteryTray::set_pins(tray, reloaded.tray_pins()).await;
+                                    BatteryTray::set_icons(tray, reloaded.icon_map()).await;
+                                    BatteryTray::set_thresholds(
+                                        tray,
+                                        reloaded.notifications.low_battery_threshold,
+                                        reloaded.notifications.critical_battery_threshold,
+                                    )
+                                    .await;
+                                }
+                                config = reloaded;
+                                update_status_display(&bt_manager, &kb_manager, &scanners, &mut notifier, &mut sinks).await;
+                                info!("Reloaded config from {}", config_path().display());
+                                "ok: reloaded config".to_string()
+                            }
+                            Err(e) => format!("error: {}", e),
+                        }
+                    }
+                    ControlCommand::Shutdown => {
+                        info!("Shutdown requested over control socket");
+                        let _ = reply.send("ok".to_string());
+                        break;
+                    }
+                };
+                let _ = reply.send(response);
+            }
+            _ = tick_or_pending(&mut watchdog) => systemd::notify_watchdog(),
+            _ = terminate.recv() => {
+                info!("Received SIGTERM, shutting down");
+                break;
+            }
+            _ = interrupt.recv() => {
+                info!("Received SIGINT, shutting down");
+                break;
+            }
+            _ = hangup.recv() => {
+                info!("Received SIGHUP, reloading config");
+                run_for_signal(&control_sender, ControlCommand::Reload);
+            }
+            _ = rescan_signal.recv() => {
+                info!("Received SIGUSR1, rescanning devices");
+                run_for_signal(&control_sender, ControlCommand::Refresh);
+            }
+            _ = dump_signal.recv() => {
+                let devices = collect_device_status(&bt_manager, &kb_manager, &scanners);
+                info!(
+                    "Received SIGUSR2: {} devices, Bluetooth {}",
+                    devices.len(),
+                    if bluetooth.is_some() { "available" } else { "unavailable" }
+                );
+                for device in &devices {
+                    info!("  {} [{}]", device.format_line(), device.id);
+                }
+                match serde_json::to_string(&notifier.snapshot()) {
+                    Ok(state) => info!("Alert state: {}", state),
+                    Err(e) => warn!("Failed to serialize the alert state: {}", e),
+                }
+            }
+            Some(event) = next_sleep_event(&mut sleep_watch) => {
+                match event {
+                    SleepEvent::Suspending => {
+                        info!("System is suspending, pausing scans");
+                        suspended = true;
+                    }
+                    SleepEvent::Resumed => {
+                        // The timers don't count time asleep, so the next refresh
+                        // would otherwise be a full interval away
+                        info!("System resumed, rescanning devices");
+                        suspended = false;
+                        if let Err(e) = kb_manager.scan_for_keyboards_off_thread().await {
+                            warn!("Failed to rescan keyboards: {}", e);
                         }
+                        next_refresh = Instant::now();
+                    }
+                }
+            }
+            Some(locked) = next_lock_change(&mut lock_watch) => {
+                let devices = collect_device_status(&bt_manager, &kb_manager, &scanners);
+                if locked {
+                    debug!("Screen locked, holding back connection popups");
+                    sinks.screen_lock.lock(devices);
+                } else if let Some((connected, disconnected)) = sinks.screen_lock.unlock(&devices) {
+                    debug!("Screen unlocked");
+                    if notifier.config.sinks.unlock_summary {
+                        show_unlock_summary(&connected, &disconnected);
                     }
                 }
+            }
+            _ = sleep_until(next_refresh), if !suspended => {
+                debug!("Periodic update check...");
 
-                // Update keyboard batteries
-                let kb_count_before = kb_manager.connected_keyboards.len();
-                if let Err(e) = kb_manager.update_battery_levels() {
-                    eprintln!("Warning: Failed to update keyboard batteries: {}", e);
+                // Try for an adapter again, or notice BlueZ went away without saying so
+                match &bluetooth {
+                    Some(link) => bluetooth_lost = !link.is_alive().await,
+                    None => reconnect_bluetooth = true,
                 }
+                let adapter = adapter_of(&bluetooth).filter(|_| !bluetooth_lost);
 
-                // Rescan for new keyboards occasionally
-                if kb_count_before == 0 {
-                    if let Err(e) = kb_manager.scan_for_keyboards() {
-                        eprintln!("Warning: Failed to rescan keyboards: {}", e);
+                match refresh_devices(adapter, &mut bt_manager, &mut kb_manager, &mut scanners).await {
+                    Ok(true) => {
+                        poll_scheduler.record_change();
+                        update_status_display(&bt_manager, &kb_manager, &scanners, &mut notifier, &mut sinks).await;
                     }
+                    Ok(false) => {}
+                    Err(e) => warn!("Failed to refresh Bluetooth devices: {}", e),
                 }
+                report_health(&mut sinks, scanners.health()).await;
+
+                update_paired_menu(adapter, &bt_manager, &sinks).await;
 
-                if bt_updated || kb_count_before != kb_manager.connected_keyboards.len() {
-                    update_status_display(&bt_manager, &kb_manager);
+                let devices = collect_device_status(&bt_manager, &kb_manager, &scanners);
+                next_refresh = Instant::now() + poll_scheduler.next_interval(&devices, notifier.config.low_battery_threshold);
+
+                // Whatever from the last run hasn't shown up by now isn't around
+                if !sinks.last_known.is_empty() {
+                    sinks.last_known.clear();
+                    if let Some(tray) = &sinks.tray {
+                        BatteryTray::update_devices(tray, devices).await;
+                    }
                 }
             }
-            _ = sleep(Duration::from_secs(120)) => {
+            _ = keyboard_rescan_timer.tick(), if !suspended => {
                 // Rescan for keyboards every 2 minutes
-                println!("Rescanning for keyboards...");
-                if let Err(e) = kb_manager.scan_for_keyboards() {
-                    eprintln!("Warning: Failed to rescan keyboards: {}", e);
+                info!("Rescanning for keyboards...");
+                if let Err(e) = kb_manager.scan_for_keyboards_off_thread().await {
+                    warn!("Failed to rescan keyboards: {}", e);
+                }
+                update_status_display(&bt_manager, &kb_manager, &scanners, &mut notifier, &mut sinks).await;
+            }
+        }
+
+        if bluetooth_lost {
+            if let Some(link) = bluetooth.take() {
+                warn!("Bluetooth adapter {} is gone, waiting for it to come back", link.adapter.name());
+            }
+            bt_manager.connected_devices.clear();
+            pending_disconnects.clear();
+            user_disconnects.clear();
+            sinks.battery_provider = None;
+            report_adapter_state(&sinks, false).await;
+            update_status_display(&bt_manager, &kb_manager, &scanners, &mut notifier, &mut sinks).await;
+            update_paired_menu(None, &bt_manager, &sinks).await;
+        } else if reconnect_bluetooth && bluetooth.is_none() {
+            match BluetoothLink::connect().await {
+                Ok(link) => {
+                    info!("Bluetooth is available again");
+                    // Discovery reports the connected devices as DeviceAdded
+                    bluetooth = Some(link);
+                    if config.bluez_battery_provider {
+                        sinks.battery_provider = start_battery_provider(adapter_of(&bluetooth)).await;
+                    }
+                    report_adapter_state(&sinks, true).await;
+                    update_paired_menu(adapter_of(&bluetooth), &bt_manager, &sinks).await;
                 }
-                update_status_display(&bt_manager, &kb_manager);
+                Err(e) => debug!("Bluetooth still unavailable: {}", e),
             }
         }
     }
+
+    // Remember the devices for the next start, including the last run's if
+    // this one ended before they could turn up
+    LastKnown::save(with_last_known(
+        collect_device_status(&bt_manager, &kb_manager, &scanners),
+        &sinks.last_known,
+    ));
+    Ok(())
 }
@@ -0,0 +1,66 @@
+use crate::cli::Cli;
+use crate::config::Config;
+use clap::CommandFactory;
+use clap_mangen::Man;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Write battery_percentage.1 and a page per subcommand into `dir`, returning their paths
+pub fn write_pages(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(dir)?;
+    let command = Cli::command();
+    let mut written = Vec::new();
+
+    let path = dir.join(format!("{}.1", command.get_name()));
+    let mut file = std::fs::File::create(&path)?;
+    write_main_page(&mut file)?;
+    written.push(path);
+
+    for subcommand in command.get_subcommands().filter(|subcommand| !subcommand.is_hide_set()) {
+        // Subcommand pages are named like git's: battery_percentage-status.1
+        let name = format!("{}-{}", command.get_name(), subcommand.get_name());
+        let subcommand = subcommand.clone().name(&name);
+        let path = dir.join(format!("{}.1", name));
+        Man::new(subcommand).render(&mut std::fs::File::create(&path)?)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+// The top-level page, with the config file's keys and defaults after the options
+pub fn write_main_page(out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    let man = Man::new(Cli::command());
+    man.render_title(out)?;
+    man.render_name_section(out)?;
+    man.render_synopsis_section(out)?;
+    man.render_description_section(out)?;
+    man.render_options_section(out)?;
+    man.render_subcommands_section(out)?;
+    write_config_section(out)?;
+    man.render_version_section(out)?;
+    Ok(())
+}
+
+// The defaults come from Config::default, so the page can't drift from the code
+fn write_config_section(out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    writeln!(out, ".SH CONFIGURATION")?;
+    writeln!(
+        out,
+        "Settings are read from \\fI$XDG_CONFIG_HOME/battery\\-monitor/config.toml\\fR \
+         (\\fI~/.config/battery\\-monitor/config.toml\\fR by default). Every section is optional. \
+         Any key can also be set with a \\fBBATTERY_MONITOR_<SECTION>__<KEY>\\fR environment variable, \
+         and changed from the command line with \\fBconfig set\\fR. These are the keys and their defaults:"
+    )?;
+    writeln!(out, ".PP\n.nf\n.RS 4")?;
+    for line in toml::to_string(&Config::default())?.lines() {
+        writeln!(out, "{}", roff_line(line))?;
+    }
+    writeln!(out, ".RE\n.fi")?;
+    Ok(())
+}
+
+// Escape backslashes, and keep lines starting with . or ' from being read as requests
+fn roff_line(line: &str) -> String {
+    let line = line.replace('\\', "\\e");
+    if line.starts_with('.') || line.starts_with('\'') { format!("\\&{}", line) } else { line }
+}
@@ -0,0 +1,118 @@
+use crate::icons::emoji_for_type;
+use crate::scanner::DeviceScanner;
+use crate::status::{DeviceStatus, Transport};
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+// A made-up device from a [[scanners.mock]] config entry
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MockDeviceConfig {
+    pub name: String,
+    // Picks the icon, e.g. Mouse, Headphones, Mechanical, Gamepad
+    pub device_type: String,
+    // (seconds since startup, level) points the battery follows, with
+    // straight lines in between
+    pub curve: Vec<(u64, u8)>,
+    // Start the curve over once it ends instead of staying at the last level
+    pub repeat: bool,
+}
+
+impl Default for MockDeviceConfig {
+    fn default() -> Self {
+        Self {
+            name: "Mock device".to_string(),
+            device_type: "Mouse".to_string(),
+            curve: vec![(0, 100)],
+            repeat: true,
+        }
+    }
+}
+
+impl MockDeviceConfig {
+    fn level_at(&self, elapsed: u64) -> Option<u8> {
+        let (first, last) = (self.curve.first()?, self.curve.last()?);
+        let elapsed = if self.repeat && last.0 > 0 { elapsed % last.0 } else { elapsed };
+        if elapsed <= first.0 {
+            return Some(first.1);
+        }
+
+        let Some(pair) = self.curve.windows(2).find(|pair| elapsed <= pair[1].0) else {
+            return Some(last.1);
+        };
+        let ((start, from), (end, to)) = (pair[0], pair[1]);
+        let progress = (elapsed - start) as f64 / (end - start).max(1) as f64;
+        Some((f64::from(from) + (f64::from(to) - f64::from(from)) * progress).round() as u8)
+    }
+}
+
+// What --mock-devices shows when no [[scanners.mock]] entries are configured:
+// a mouse that crosses both thresholds every 10 minutes, earbuds that charge
+// back up, and a keyboard that sits at a steady level
+pub fn demo_devices() -> Vec<MockDeviceConfig> {
+    vec![
+        MockDeviceConfig {
+            name: "Mock Mouse".to_string(),
+            device_type: "Mouse".to_string(),
+            curve: vec![(0, 100), (540, 5), (600, 100)],
+            repeat: true,
+        },
+        MockDeviceConfig {
+            name: "Mock Buds".to_string(),
+            device_type: "Buds".to_string(),
+            curve: vec![(0, 40), (300, 15), (900, 90)],
+            repeat: true,
+        },
+        MockDeviceConfig {
+            name: "Mock Keyboard".to_string(),
+            device_type: "Mechanical".to_string(),
+            curve: vec![(0, 65)],
+            repeat: false,
+        },
+    ]
+}
+
+// Fake devices with scripted battery curves, for working on notifications
+// and the tray without Bluetooth hardware, and for reproducible screenshots
+pub struct MockScanner {
+    devices: Vec<MockDeviceConfig>,
+    started: Instant,
+}
+
+impl MockScanner {
+    pub fn new(devices: Vec<MockDeviceConfig>) -> Self {
+        let devices = if devices.is_empty() { demo_devices() } else { devices };
+        Self {
+            devices,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl DeviceScanner for MockScanner {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn scan(&mut self) -> LocalBoxFuture<'_, Result<Vec<DeviceStatus>, Box<dyn std::error::Error>>> {
+        let elapsed = self.started.elapsed().as_secs();
+        let devices = self
+            .devices
+            .iter()
+            .map(|device| DeviceStatus {
+                id: format!("mock:{}", device.name.to_lowercase().replace(' ', "_")),
+                name: device.name.clone(),
+                device_type: device.device_type.clone(),
+                transport: Transport::Usb,
+                extra_transports: Vec::new(),
+                battery_percentage: device.level_at(elapsed).map(|level| level.min(100)),
+                batteries: Vec::new(),
+                rssi: None,
+                icon: emoji_for_type(&device.device_type).to_string(),
+                stale: false,
+            })
+            .collect();
+        Box::pin(async move { Ok(devices) })
+    }
+}
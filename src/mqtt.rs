@@ -0,0 +1,179 @@
+use crate::status::DeviceStatus;
+use rumqttc::{AsyncClient, LastWill, MqttOptions, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::{info, warn};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MqttConfig {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub client_id: String,
+    // Topic prefix for availability and device state
+    pub base_topic: String,
+    // Home Assistant MQTT discovery prefix
+    pub discovery_prefix: String,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "localhost".to_string(),
+            port: 1883,
+            username: None,
+            password: None,
+            client_id: "battery-monitor".to_string(),
+            base_topic: "battery-monitor".to_string(),
+            discovery_prefix: "homeassistant".to_string(),
+        }
+    }
+}
+
+pub struct MqttPublisher {
+    client: AsyncClient,
+    config: MqttConfig,
+    // Last state published per device, keyed by object id
+    published: HashMap<String, DeviceStatus>,
+}
+
+impl MqttPublisher {
+    pub fn start(config: MqttConfig) -> Self {
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        options.set_last_will(LastWill::new(availability_topic(&config), "offline", QoS::AtLeastOnce, true));
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            options.set_credentials(username, password);
+        }
+
+        let (client, mut event_loop) = AsyncClient::new(options, 32);
+
+        // The event loop has to be polled for anything to be sent; it
+        // reconnects on its own after errors
+        let online_client = client.clone();
+        let online_topic = availability_topic(&config);
+        tokio::spawn(async move {
+            loop {
+                match event_loop.poll().await {
+                    Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_))) => {
+                        let _ = online_client.publish(&online_topic, QoS::AtLeastOnce, true, "online").await;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("MQTT connection error: {}", e);
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            }
+        });
+
+        info!("Publishing battery levels to MQTT broker {}:{}", config.host, config.port);
+
+        Self {
+            client,
+            config,
+            published: HashMap::new(),
+        }
+    }
+
+    pub async fn publish_devices(&mut self, devices: &[DeviceStatus]) {
+        let mut current = HashMap::new();
+
+        for device in devices {
+            let object_id = object_id(&device.id);
+            match self.published.get(&object_id) {
+                None => {
+                    self.publish_discovery(&object_id, device).await;
+                    self.publish_state(&object_id, device.battery_percentage, true).await;
+                }
+                Some(previous) if previous.battery_percentage != device.battery_percentage => {
+                    self.publish_state(&object_id, device.battery_percentage, true).await;
+                }
+                Some(_) => {}
+            }
+            current.insert(object_id, device.clone());
+        }
+
+        // Devices that went away keep their last battery level but show as disconnected
+        for (object_id, device) in &self.published {
+            if !current.contains_key(object_id) {
+                self.publish_state(object_id, device.battery_percentage, false).await;
+            }
+        }
+
+        self.published = current;
+    }
+
+    async fn publish_discovery(&self, object_id: &str, device: &DeviceStatus) {
+        let state_topic = self.state_topic(object_id);
+        let availability = availability_topic(&self.config);
+        let ha_device = json!({
+            "identifiers": [format!("battery_monitor_{}", object_id)],
+            "name": device.name,
+            "model": device.device_type,
+        });
+
+        let battery = json!({
+            "name": "Battery",
+            "unique_id": format!("battery_monitor_{}_battery", object_id),
+            "state_topic": state_topic,
+            "value_template": "{{ value_json.battery }}",
+            "device_class": "battery",
+            "unit_of_measurement": "%",
+            "state_class": "measurement",
+            "availability_topic": availability,
+            "device": ha_device,
+        });
+        let connected = json!({
+            "name": "Connected",
+            "unique_id": format!("battery_monitor_{}_connected", object_id),
+            "state_topic": state_topic,
+            "value_template": "{{ value_json.connected }}",
+            "device_class": "connectivity",
+            "payload_on": "ON",
+            "payload_off": "OFF",
+            "availability_topic": availability,
+            "device": ha_device,
+        });
+
+        let prefix = &self.config.discovery_prefix;
+        self.publish(format!("{}/sensor/{}/battery/config", prefix, object_id), battery.to_string(), true).await;
+        self.publish(format!("{}/binary_sensor/{}/connected/config", prefix, object_id), connected.to_string(), true).await;
+    }
+
+    async fn publish_state(&self, object_id: &str, battery: Option<u8>, connected: bool) {
+        let state = json!({
+            "battery": battery,
+            "connected": if connected { "ON" } else { "OFF" },
+        });
+        self.publish(self.state_topic(object_id), state.to_string(), true).await;
+    }
+
+    async fn publish(&self, topic: String, payload: String, retain: bool) {
+        if let Err(e) = self.client.publish(&topic, QoS::AtLeastOnce, retain, payload).await {
+            warn!("Failed to publish MQTT message to {}: {}", topic, e);
+        }
+    }
+
+    fn state_topic(&self, object_id: &str) -> String {
+        format!("{}/{}/state", self.config.base_topic, object_id)
+    }
+}
+
+fn availability_topic(config: &MqttConfig) -> String {
+    format!("{}/status", config.base_topic)
+}
+
+// MQTT topics and Home Assistant ids only allow a limited character set
+fn object_id(device_id: &str) -> String {
+    device_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
@@ -0,0 +1,38 @@
+use super::{BatteryEvent, NotificationSink};
+use tokio::process::Command;
+use tracing::warn;
+
+// Run a shell command for every alert, with the event in environment variables
+pub struct CommandSink {
+    pub command: String,
+}
+
+impl NotificationSink for CommandSink {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    fn send(&self, event: &BatteryEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(&self.command)
+            .env("BATTERY_DEVICE_ID", &event.device_id)
+            .env("BATTERY_DEVICE_NAME", &event.device_name)
+            .env("BATTERY_LEVEL", event.battery.to_string())
+            .env("BATTERY_EVENT", event.notification_type.key());
+
+        // Spawning fails right away if sh is missing; the exit status is only known later
+        let mut child = command.spawn()?;
+        let command = self.command.clone();
+        tokio::spawn(async move {
+            match child.wait().await {
+                Ok(status) if !status.success() => warn!("Notification command '{}' exited with {}", command, status),
+                Ok(_) => {}
+                Err(e) => warn!("Failed to wait for notification command '{}': {}", command, e),
+            }
+        });
+
+        Ok(())
+    }
+}
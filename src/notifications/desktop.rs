@@ -0,0 +1,220 @@
+use super::{BatteryEvent, NotificationAction, NotificationActionEvent, NotificationSink, NotificationType, Urgency};
+use crate::i18n::tr_args;
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::warn;
+
+// Desktop notifications through notify-send
+#[derive(Clone)]
+pub struct DesktopSink {
+    // Keep critical notifications on screen until the user dismisses them
+    pub persistent_critical: bool,
+    // Attach Open details/Snooze/Dismiss buttons, reporting clicks on this channel
+    pub actions: Option<UnboundedSender<NotificationActionEvent>>,
+    // Each device's notification on screen, so the next one for the device
+    // updates it in place instead of stacking up
+    handles: Arc<Mutex<HashMap<String, NotificationHandle>>>,
+    // With a digest window, alerts are held this long and the ones that
+    // came in meanwhile go out as a single summary
+    digest: Option<Duration>,
+    pending: Arc<Mutex<Vec<BatteryEvent>>>,
+}
+
+impl DesktopSink {
+    pub fn new(
+        persistent_critical: bool,
+        actions: Option<UnboundedSender<NotificationActionEvent>>,
+        digest: Option<Duration>,
+    ) -> Self {
+        Self {
+            persistent_critical,
+            actions,
+            handles: Arc::default(),
+            digest,
+            pending: Arc::default(),
+        }
+    }
+
+    // A timeout of 0 asks the notification server to keep it until dismissed
+    fn timeout(&self, critical: bool) -> &'static str {
+        if critical && self.persistent_critical { "0" } else { "10000" }
+    }
+
+    // Hold an alert for the digest; the first one of a batch starts the timer
+    fn queue(&self, event: &BatteryEvent, window: Duration) {
+        let mut pending = self.pending.lock().unwrap();
+        // A device alerting twice within the window only needs its latest level
+        pending.retain(|queued| queued.device_id != event.device_id);
+        pending.push(event.clone());
+        if pending.len() > 1 {
+            return;
+        }
+
+        let sink = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(window).await;
+            let events = std::mem::take(&mut *sink.pending.lock().unwrap());
+            match events.as_slice() {
+                [] => {}
+                [event] => sink.show(event),
+                events => sink.show_summary(events),
+            }
+        });
+    }
+
+    fn show_summary(&self, events: &[BatteryEvent]) {
+        let critical = events.iter().any(|event| event.notification_type == NotificationType::CriticalBattery);
+        let urgency = if critical { Urgency::Critical } else { Urgency::Normal };
+        let title = tr_args("digest-title", &[("count", events.len().into())]);
+        let body = events
+            .iter()
+            .map(|event| format!("{} {}%", event.device_name, event.battery))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let args = vec![
+            title,
+            body,
+            "-t".to_string(),
+            self.timeout(critical).to_string(),
+            "-u".to_string(),
+            urgency.as_str().to_string(),
+        ];
+        tokio::spawn(async move {
+            if let Err(e) = Command::new("notify-send").args(&args).output().await {
+                warn!("Failed to run notify-send: {}", e);
+            }
+        });
+    }
+
+    fn show(&self, event: &BatteryEvent) {
+        let critical = event.notification_type == NotificationType::CriticalBattery;
+        let args = vec![
+            event.notification_type.title(),
+            event.body(),
+            "-t".to_string(),
+            self.timeout(critical).to_string(),
+            "-u".to_string(),
+            event.notification_type.urgency().as_str().to_string(),
+        ];
+
+        // Print the id and replace the device's previous notification, so a
+        // further drop updates the alert and a recovered notification clears
+        // a critical one left on screen
+        let mut full_args = args.clone();
+        full_args.push("-p".to_string());
+        if let Some(handle) = self.handles.lock().unwrap().get(&event.device_id) {
+            full_args.push("-r".to_string());
+            full_args.push(handle.id.to_string());
+        }
+        // Nothing to act on once the battery is fine again
+        let sender = self.actions.clone().filter(|_| event.notification_type.is_alert());
+        if sender.is_some() {
+            for action in NotificationAction::ALL {
+                full_args.push("-A".to_string());
+                full_args.push(format!("{}={}", action.key(), action.label()));
+            }
+        }
+
+        let device_id = event.device_id.clone();
+        let handles = self.handles.clone();
+        tokio::spawn(async move {
+            match notify(&full_args, &device_id, &handles, sender).await {
+                Ok(true) => {}
+                // Older notify-send versions know neither -p nor -A; show the plain notification instead
+                _ => {
+                    if let Err(e) = Command::new("notify-send").args(&args).output().await {
+                        warn!("Failed to run notify-send: {}", e);
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl NotificationSink for DesktopSink {
+    fn name(&self) -> &'static str {
+        "desktop"
+    }
+
+    fn send(&self, event: &BatteryEvent) -> Result<(), Box<dyn std::error::Error>> {
+        match self.digest {
+            Some(window) if event.notification_type.is_alert() => self.queue(event, window),
+            _ => self.show(event),
+        }
+        Ok(())
+    }
+
+    fn send_digest(&self, events: &[BatteryEvent]) -> Result<(), Box<dyn std::error::Error>> {
+        match events {
+            [] => {}
+            [event] => self.show(event),
+            events => self.show_summary(events),
+        }
+        Ok(())
+    }
+
+    fn withdraw(&self, device_id: &str) {
+        self.pending.lock().unwrap().retain(|queued| queued.device_id != device_id);
+        if let Some(handle) = self.handles.lock().unwrap().remove(device_id) {
+            tokio::spawn(async move {
+                if let Err(e) = handle.close().await {
+                    warn!("Failed to close notification {}: {}", handle.id, e);
+                }
+            });
+        }
+    }
+}
+
+// A notification the server is showing, by the id it gave it
+#[derive(Clone, Copy, Debug)]
+struct NotificationHandle {
+    id: u32,
+}
+
+impl NotificationHandle {
+    async fn close(&self) -> zbus::Result<()> {
+        let connection = zbus::Connection::session().await?;
+        connection
+            .call_method(
+                Some("org.freedesktop.Notifications"),
+                "/org/freedesktop/Notifications",
+                Some("org.freedesktop.Notifications"),
+                "CloseNotification",
+                &(self.id,),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+// Run notify-send, recording the notification id it prints first. With
+// actions it then waits until the notification is closed and prints the
+// chosen action. Returns whether notify-send succeeded.
+async fn notify(
+    args: &[String],
+    device_id: &str,
+    handles: &Mutex<HashMap<String, NotificationHandle>>,
+    sender: Option<UnboundedSender<NotificationActionEvent>>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut child = Command::new("notify-send").args(args).stdout(Stdio::piped()).spawn()?;
+    let mut lines = BufReader::new(child.stdout.take().ok_or("no stdout")?).lines();
+
+    if let Some(id) = lines.next_line().await?.and_then(|line| line.trim().parse().ok()) {
+        handles.lock().unwrap().insert(device_id.to_string(), NotificationHandle { id });
+    }
+    while let Some(line) = lines.next_line().await? {
+        if let (Some(sender), Some(action)) = (&sender, NotificationAction::from_key(line.trim())) {
+            let _ = sender.send(NotificationActionEvent {
+                device_id: device_id.to_string(),
+                action,
+            });
+        }
+    }
+
+    Ok(child.wait().await?.success())
+}
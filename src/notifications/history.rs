@@ -0,0 +1,88 @@
+use super::{BatteryEvent, NotificationSink};
+use crate::config::data_dir;
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+// One line of the notification log
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NotificationRecord {
+    // Unix timestamp (seconds)
+    pub timestamp: u64,
+    pub device_id: String,
+    pub device_name: String,
+    pub level: u8,
+    pub event: String,
+}
+
+impl NotificationRecord {
+    pub fn format_line(&self) -> String {
+        let time = Local
+            .timestamp_opt(self.timestamp as i64, 0)
+            .single()
+            .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|| self.timestamp.to_string());
+        format!("{}  {:<16}  {:>3}%  {} ({})", time, self.event, self.level, self.device_name, self.device_id)
+    }
+}
+
+// Every alert that went out, one JSON object per line, kept in the data dir
+// so `battery_percentage log` can show them after the fact
+pub struct NotificationLog;
+
+impl NotificationLog {
+    pub fn path() -> PathBuf {
+        data_dir().join("notifications.jsonl")
+    }
+
+    pub fn append(record: &NotificationRecord) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(data_dir())?;
+        let mut file = OpenOptions::new().create(true).append(true).open(Self::path())?;
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+        Ok(())
+    }
+
+    // Records at or after `since` (a Unix timestamp), optionally only for the
+    // device with this id or name. Lines that don't parse are skipped.
+    pub fn read(since: u64, device: Option<&str>) -> Result<Vec<NotificationRecord>, Box<dyn std::error::Error>> {
+        let contents = match std::fs::read_to_string(Self::path()) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str::<NotificationRecord>(line).ok())
+            .filter(|record| record.timestamp >= since)
+            .filter(|record| {
+                device.is_none_or(|query| record.device_id == query || record.device_name.eq_ignore_ascii_case(query))
+            })
+            .collect())
+    }
+}
+
+// Appends every alert to the notification log
+pub struct HistorySink;
+
+impl NotificationSink for HistorySink {
+    fn name(&self) -> &'static str {
+        "history"
+    }
+
+    fn keeps_record(&self) -> bool {
+        true
+    }
+
+    fn send(&self, event: &BatteryEvent) -> Result<(), Box<dyn std::error::Error>> {
+        NotificationLog::append(&NotificationRecord {
+            timestamp: event.timestamp,
+            device_id: event.device_id.clone(),
+            device_name: event.device_name.clone(),
+            level: event.battery,
+            event: event.notification_type.key().to_string(),
+        })
+    }
+}
@@ -0,0 +1,20 @@
+use super::{BatteryEvent, NotificationSink};
+use tracing::warn;
+
+// Write alerts to stderr, which ends up in the journal when run as a service
+pub struct LogSink;
+
+impl NotificationSink for LogSink {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    fn keeps_record(&self) -> bool {
+        true
+    }
+
+    fn send(&self, event: &BatteryEvent) -> Result<(), Box<dyn std::error::Error>> {
+        warn!("{}: {}", event.notification_type.title(), event.body());
+        Ok(())
+    }
+}
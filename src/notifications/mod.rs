@@ -0,0 +1,738 @@
+pub mod command;
+pub mod desktop;
+pub mod history;
+pub mod log;
+#[cfg(feature = "osd")]
+pub mod osd;
+pub mod portal;
+pub mod webhook;
+
+use crate::config::data_dir;
+use crate::i18n::{tr, tr_args};
+use crate::status::DeviceStatus;
+use crate::thresholds::{Direction, ThresholdCrossed};
+use command::CommandSink;
+use desktop::DesktopSink;
+use history::HistorySink;
+use log::LogSink;
+use portal::{DesktopBackend, PortalSink};
+use tracing::{debug, info, warn};
+use webhook::{WebhookConfig, WebhookSink};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+// How long the "Snooze" notification action silences a device
+pub const SNOOZE_DURATION: Duration = Duration::from_secs(60 * 60);
+
+// Device id of the made-up device `test-notification` alerts for
+pub const TEST_DEVICE_ID: &str = "test-device";
+
+// How many alerts the manager remembers for snapshots
+const RECENT_EVENTS: usize = 50;
+
+// Points a device has to gain over the level it alerted at to count as
+// charging when it doesn't report charging itself
+const CHARGE_RISE: u8 = 3;
+// Window max_per_minute counts alerts over
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationType {
+    LowBattery,
+    CriticalBattery,
+    // A device that alerted charged back above the low threshold
+    BatteryRecovered,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NotificationAction {
+    OpenDetails,
+    Snooze,
+    Dismiss,
+}
+
+// An action button the user clicked on a battery notification
+#[derive(Clone, Debug, PartialEq)]
+pub struct NotificationActionEvent {
+    pub device_id: String,
+    pub action: NotificationAction,
+}
+
+impl NotificationAction {
+    pub(crate) const ALL: [NotificationAction; 3] = [
+        NotificationAction::OpenDetails,
+        NotificationAction::Snooze,
+        NotificationAction::Dismiss,
+    ];
+
+    pub(crate) fn key(&self) -> &'static str {
+        match self {
+            NotificationAction::OpenDetails => "details",
+            NotificationAction::Snooze => "snooze",
+            NotificationAction::Dismiss => "dismiss",
+        }
+    }
+
+    pub(crate) fn label(&self) -> String {
+        tr(match self {
+            NotificationAction::OpenDetails => "action-open-details",
+            NotificationAction::Snooze => "action-snooze",
+            NotificationAction::Dismiss => "action-dismiss",
+        })
+    }
+
+    pub(crate) fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.key() == key)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+impl Urgency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Urgency::Low => "low",
+            Urgency::Normal => "normal",
+            Urgency::Critical => "critical",
+        }
+    }
+}
+
+impl NotificationType {
+    // Stable name for scripts and other machine consumers
+    pub fn key(&self) -> &'static str {
+        match self {
+            NotificationType::LowBattery => "low_battery",
+            NotificationType::CriticalBattery => "critical_battery",
+            NotificationType::BatteryRecovered => "battery_recovered",
+        }
+    }
+
+    pub fn urgency(&self) -> Urgency {
+        match self {
+            NotificationType::LowBattery => Urgency::Normal,
+            NotificationType::CriticalBattery => Urgency::Critical,
+            NotificationType::BatteryRecovered => Urgency::Low,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        tr(match self {
+            NotificationType::LowBattery => "low-battery-title",
+            NotificationType::CriticalBattery => "critical-battery-title",
+            NotificationType::BatteryRecovered => "battery-recovered-title",
+        })
+    }
+
+    // Whether this is a warning rather than the all-clear
+    pub fn is_alert(&self) -> bool {
+        *self != NotificationType::BatteryRecovered
+    }
+
+    pub fn get_icon(&self) -> &'static str {
+        match self {
+            NotificationType::LowBattery => "🪫",
+            NotificationType::CriticalBattery => "⚠️",
+            NotificationType::BatteryRecovered => "🔋",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NotificationConfig {
+    pub low_battery_threshold: u8,
+    pub critical_battery_threshold: u8,
+    pub low_battery_suppression: Duration,
+    pub critical_battery_suppression: Duration,
+    // Keep critical notifications on screen until the user dismisses them
+    pub persistent_critical: bool,
+    // Attach Open details/Snooze/Dismiss buttons to battery notifications
+    pub actions: bool,
+    pub sinks: SinksConfig,
+}
+
+// Which channels battery alerts go out on and when they repeat, from the
+// [notifications] config section
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SinksConfig {
+    pub low_battery_threshold: u8,
+    pub critical_battery_threshold: u8,
+    pub desktop: bool,
+    // notify-send or the desktop portal; auto picks the portal in a Flatpak or Snap
+    pub desktop_backend: DesktopBackend,
+    pub log: bool,
+    // Keep every alert in the notification log for `battery_percentage log`
+    pub history: bool,
+    // Shell command to run for every alert
+    pub command: Option<String>,
+    pub webhook: Option<WebhookConfig>,
+    // Flash an on-screen overlay for critical alerts (needs the `osd` feature)
+    pub osd: bool,
+    // Levels below the low threshold that alert again as soon as the
+    // battery drops to them, even inside the suppression window
+    pub escalation_levels: Vec<u8>,
+    // Collect desktop alerts for this many seconds and show one summary
+    // for all of them; 0 shows each right away
+    pub digest_secs: u64,
+    // Hold back connect/disconnect popups while the screen is locked;
+    // battery alerts still go out
+    pub quiet_when_locked: bool,
+    // On unlock, show one popup listing what connected and disconnected meanwhile
+    pub unlock_summary: bool,
+    // Alerts that pop up or go out per minute across all devices before the
+    // rest are held and sent as one digest; 0 is no limit. The log and
+    // history always get every alert.
+    pub max_per_minute: u32,
+}
+
+impl Default for SinksConfig {
+    fn default() -> Self {
+        Self {
+            low_battery_threshold: 20,
+            critical_battery_threshold: 10,
+            desktop: true,
+            desktop_backend: DesktopBackend::Auto,
+            log: true,
+            history: true,
+            command: None,
+            webhook: None,
+            osd: false,
+            escalation_levels: vec![15, 10, 5],
+            digest_secs: 0,
+            quiet_when_locked: true,
+            unlock_summary: true,
+            max_per_minute: 10,
+        }
+    }
+}
+
+// A battery alert as handed to every notification sink
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BatteryEvent {
+    pub device_id: String,
+    pub device_name: String,
+    pub battery: u8,
+    pub notification_type: NotificationType,
+    // Unix timestamp (seconds)
+    pub timestamp: u64,
+}
+
+impl BatteryEvent {
+    pub fn body(&self) -> String {
+        tr_args(
+            "battery-alert-body",
+            &[
+                ("icon", self.notification_type.get_icon().into()),
+                ("device", self.device_name.as_str().into()),
+                ("level", self.battery.into()),
+            ],
+        )
+    }
+}
+
+// A channel battery alerts can be delivered on. Sinks must not block the
+// monitoring loop, so anything slow is spawned onto the runtime.
+pub trait NotificationSink {
+    fn name(&self) -> &'static str;
+    fn send(&self, event: &BatteryEvent) -> Result<(), Box<dyn std::error::Error>>;
+
+    // Sinks that only keep a record, like the log, get every alert right
+    // away; the rate limit is for the ones that reach the user
+    fn keeps_record(&self) -> bool {
+        false
+    }
+
+    // Several alerts held back together by the rate limit. Sinks that pop up
+    // show them as one; the rest send them one by one.
+    fn send_digest(&self, events: &[BatteryEvent]) -> Result<(), Box<dyn std::error::Error>> {
+        for event in events {
+            self.send(event)?;
+        }
+        Ok(())
+    }
+
+    // Take back whatever is still showing for a device whose alert no longer
+    // applies. Most sinks have nothing to take back.
+    fn withdraw(&self, _device_id: &str) {}
+}
+
+impl NotificationConfig {
+    // Every level whose crossing matters for alerts
+    pub fn thresholds(&self) -> Vec<u8> {
+        let mut thresholds = vec![self.low_battery_threshold, self.critical_battery_threshold];
+        thresholds.extend(&self.sinks.escalation_levels);
+        thresholds.sort_unstable();
+        thresholds.dedup();
+        thresholds
+    }
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            low_battery_threshold: SinksConfig::default().low_battery_threshold,
+            critical_battery_threshold: SinksConfig::default().critical_battery_threshold,
+            low_battery_suppression: Duration::from_secs(30 * 60),
+            critical_battery_suppression: Duration::from_secs(10 * 60),
+            persistent_critical: true,
+            actions: true,
+            sinks: SinksConfig::default(),
+        }
+    }
+}
+
+// Muted and snoozed devices, kept on disk so they survive restarts
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MuteList {
+    pub muted: HashSet<String>,
+    // Unix timestamp (seconds) until which each device is snoozed
+    pub snoozed_until: HashMap<String, u64>,
+}
+
+impl MuteList {
+    pub fn path() -> PathBuf {
+        data_dir().join("muted.json")
+    }
+
+    pub fn load() -> Self {
+        std::fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        let path = Self::path();
+        let result = std::fs::create_dir_all(data_dir())
+            .and_then(|_| std::fs::write(&path, serde_json::to_string_pretty(self).unwrap_or_default()));
+        if let Err(e) = result {
+            warn!("Failed to save mute list to {}: {}", path.display(), e);
+        }
+    }
+
+    fn is_snoozed(&self, device_id: &str) -> bool {
+        self.snoozed_until.get(device_id).is_some_and(|until| unix_now() < *until)
+    }
+}
+
+// When a device last alerted, for snapshots
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SentAlert {
+    pub device_id: String,
+    pub notification_type: NotificationType,
+    pub seconds_ago: u64,
+}
+
+// The manager's repeat suppression and mute state, for `ctl snapshot`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationState {
+    pub last_sent: Vec<SentAlert>,
+    pub escalated: HashMap<String, u8>,
+    pub low_devices: HashSet<String>,
+    pub mutes: MuteList,
+    // Latest alerts, oldest first
+    pub recent_events: Vec<BatteryEvent>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+pub struct NotificationManager {
+    pub config: NotificationConfig,
+    last_sent: HashMap<(String, NotificationType), Instant>,
+    // Lowest escalation level each device has already been alerted at
+    escalated: HashMap<String, u8>,
+    // Devices with a low or critical alert out, which get a recovered
+    // notification when they charge back up
+    low_devices: HashSet<String>,
+    // Level each device's latest alert went out at, until it's withdrawn
+    open_alerts: HashMap<String, u8>,
+    mutes: MuteList,
+    recent_events: VecDeque<BatteryEvent>,
+    // When alerts went out within the last RATE_WINDOW, oldest first
+    delivered: VecDeque<Instant>,
+    // Alerts held back by the rate limit, the latest per device
+    held: Vec<BatteryEvent>,
+    sinks: Vec<Box<dyn NotificationSink>>,
+    // Kept to hand to the desktop sink again when the sinks are rebuilt
+    action_sender: UnboundedSender<NotificationActionEvent>,
+    action_receiver: Option<UnboundedReceiver<NotificationActionEvent>>,
+}
+
+impl NotificationManager {
+    pub fn new(config: NotificationConfig) -> Self {
+        let (action_sender, action_receiver) = unbounded_channel();
+        let sinks = Self::build_sinks(&config, action_sender.clone());
+        Self {
+            config,
+            last_sent: HashMap::new(),
+            escalated: HashMap::new(),
+            low_devices: HashSet::new(),
+            open_alerts: HashMap::new(),
+            mutes: MuteList::load(),
+            recent_events: VecDeque::new(),
+            delivered: VecDeque::new(),
+            held: Vec::new(),
+            sinks,
+            action_sender,
+            action_receiver: Some(action_receiver),
+        }
+    }
+
+    // Switch to new channel settings from a config reload. Alert state carries
+    // over; popups already on screen stay until they time out or are dismissed,
+    // since the rebuilt desktop sink doesn't know about them.
+    pub fn set_sinks(&mut self, sinks: SinksConfig) {
+        self.config.sinks = sinks;
+        self.sinks = Self::build_sinks(&self.config, self.action_sender.clone());
+    }
+
+    fn build_sinks(
+        config: &NotificationConfig,
+        action_sender: UnboundedSender<NotificationActionEvent>,
+    ) -> Vec<Box<dyn NotificationSink>> {
+        let mut sinks: Vec<Box<dyn NotificationSink>> = Vec::new();
+
+        if config.sinks.log {
+            sinks.push(Box::new(LogSink));
+        }
+        if config.sinks.history {
+            sinks.push(Box::new(HistorySink));
+        }
+        if config.sinks.desktop {
+            match config.sinks.desktop_backend.resolve() {
+                DesktopBackend::Portal => {
+                    if config.sinks.desktop_backend == DesktopBackend::Auto {
+                        info!("Running sandboxed, sending desktop alerts through the notification portal");
+                    }
+                    sinks.push(Box::new(PortalSink));
+                }
+                _ => sinks.push(Box::new(DesktopSink::new(
+                    config.persistent_critical,
+                    config.actions.then_some(action_sender),
+                    (config.sinks.digest_secs > 0).then(|| Duration::from_secs(config.sinks.digest_secs)),
+                ))),
+            }
+        }
+        if let Some(command) = &config.sinks.command {
+            sinks.push(Box::new(CommandSink { command: command.clone() }));
+        }
+        if let Some(webhook) = &config.sinks.webhook {
+            match WebhookSink::new(webhook.clone()) {
+                Ok(sink) => sinks.push(Box::new(sink)),
+                Err(e) => warn!("Failed to set up webhook notifications: {}", e),
+            }
+        }
+        #[cfg(feature = "osd")]
+        if config.sinks.osd {
+            sinks.push(Box::new(osd::OsdSink));
+        }
+        #[cfg(not(feature = "osd"))]
+        if config.sinks.osd {
+            warn!("osd is enabled but this build has no OSD support; rebuild with --features osd");
+        }
+
+        sinks
+    }
+
+    // Add another delivery channel on top of the configured ones
+    pub fn add_sink(&mut self, sink: Box<dyn NotificationSink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn snapshot(&self) -> NotificationState {
+        NotificationState {
+            last_sent: self
+                .last_sent
+                .iter()
+                .map(|((device_id, notification_type), sent)| SentAlert {
+                    device_id: device_id.clone(),
+                    notification_type: *notification_type,
+                    seconds_ago: sent.elapsed().as_secs(),
+                })
+                .collect(),
+            escalated: self.escalated.clone(),
+            low_devices: self.low_devices.clone(),
+            mutes: self.mutes.clone(),
+            recent_events: self.recent_events.iter().cloned().collect(),
+        }
+    }
+
+    // Pick up repeat suppression and open alerts from a snapshot. Its mutes
+    // are left out: the next mute change saves self.mutes over muted.json, so
+    // restoring them would bring back or drop the user's current mutes.
+    pub fn restore(&mut self, state: NotificationState) {
+        let now = Instant::now();
+        self.last_sent = state
+            .last_sent
+            .into_iter()
+            .filter_map(|alert| {
+                let sent = now.checked_sub(Duration::from_secs(alert.seconds_ago))?;
+                Some(((alert.device_id, alert.notification_type), sent))
+            })
+            .collect();
+        self.escalated = state.escalated;
+        self.low_devices = state.low_devices;
+    }
+
+    pub fn sink_names(&self) -> Vec<&'static str> {
+        self.sinks.iter().map(|sink| sink.name()).collect()
+    }
+
+    // Run a made-up device dropping to this alert's threshold through the
+    // normal checks, so the test exercises the same path as a real alert
+    pub fn send_test(&mut self, notification_type: NotificationType) -> Option<NotificationType> {
+        let threshold = match notification_type {
+            NotificationType::LowBattery | NotificationType::BatteryRecovered => self.config.low_battery_threshold,
+            NotificationType::CriticalBattery => self.config.critical_battery_threshold,
+        };
+        let (direction, battery) = if notification_type == NotificationType::BatteryRecovered {
+            // As if it had alerted before and just charged past the threshold
+            self.low_devices.insert(TEST_DEVICE_ID.to_string());
+            (Direction::Up, threshold + 1)
+        } else {
+            (Direction::Down, threshold)
+        };
+        self.handle_crossing(&ThresholdCrossed {
+            device_id: TEST_DEVICE_ID.to_string(),
+            device_name: tr("test-device-name"),
+            threshold,
+            direction,
+            battery,
+        })
+    }
+
+    // Hand out the channel that receives notification button clicks; only the first caller gets it
+    pub fn take_action_receiver(&mut self) -> Option<UnboundedReceiver<NotificationActionEvent>> {
+        self.action_receiver.take()
+    }
+
+    pub fn snooze_device(&mut self, device_id: &str, duration: Duration) {
+        info!("Snoozed battery notifications for {} ({} minutes)", device_id, duration.as_secs() / 60);
+        self.mutes.snoozed_until.insert(device_id.to_string(), unix_now() + duration.as_secs());
+        self.mutes.snoozed_until.retain(|_, until| *until > unix_now());
+        self.mutes.save();
+    }
+
+    pub fn mute_device(&mut self, device_id: &str) {
+        info!("Muted battery notifications for {}", device_id);
+        self.mutes.muted.insert(device_id.to_string());
+        self.mutes.save();
+    }
+
+    pub fn unmute_device(&mut self, device_id: &str) {
+        info!("Unmuted battery notifications for {}", device_id);
+        self.mutes.muted.remove(device_id);
+        self.mutes.snoozed_until.remove(device_id);
+        self.mutes.save();
+    }
+
+    pub fn is_muted(&self, device_id: &str) -> bool {
+        self.mutes.muted.contains(device_id) || self.mutes.is_snoozed(device_id)
+    }
+
+    // Alert for a device dropping through a threshold, or give the all-clear
+    // for one charging back above the low threshold. Returns the notification
+    // sent, if any.
+    pub fn handle_crossing(&mut self, crossing: &ThresholdCrossed) -> Option<NotificationType> {
+        let ThresholdCrossed { device_id, device_name, battery, .. } = crossing;
+        let (device_id, battery) = (device_id.as_str(), *battery);
+
+        if crossing.direction == Direction::Up {
+            if battery <= self.config.low_battery_threshold {
+                return None;
+            }
+            // Charged back up, so the next drain escalates from the top again
+            self.escalated.remove(device_id);
+            if !self.low_devices.remove(device_id) || self.is_muted(device_id) {
+                return None;
+            }
+            // Suppressed like the alerts, so a level bouncing around the
+            // threshold doesn't keep saying it's fine
+            let key = (device_id.to_string(), NotificationType::BatteryRecovered);
+            if self.last_sent.get(&key).is_some_and(|last| last.elapsed() < self.config.low_battery_suppression) {
+                return None;
+            }
+            // The recovered notification takes the alert's place
+            self.open_alerts.remove(device_id);
+            self.send(NotificationType::BatteryRecovered, device_id, device_name, battery);
+            self.last_sent.insert(key, Instant::now());
+            return Some(NotificationType::BatteryRecovered);
+        }
+
+        if self.is_muted(device_id) {
+            return None;
+        }
+
+        let notification_type = if battery <= self.config.critical_battery_threshold {
+            NotificationType::CriticalBattery
+        } else if battery <= self.config.low_battery_threshold {
+            NotificationType::LowBattery
+        } else {
+            return None;
+        };
+
+        // Reaching a new escalation level always alerts
+        let level = self.escalation_level(battery);
+        let escalating = level.is_some_and(|level| self.escalated.get(device_id).is_none_or(|reached| level < *reached));
+
+        // Otherwise each tier has its own suppression timer, so a level
+        // flapping around a threshold doesn't alert on every dip, while dropping
+        // from low to critical still alerts even if a low warning was just shown
+        let key = (device_id.to_string(), notification_type);
+        let suppression = self.suppression_for(notification_type);
+        if !escalating && self.last_sent.get(&key).is_some_and(|last| last.elapsed() < suppression) {
+            return None;
+        }
+
+        self.send(notification_type, device_id, device_name, battery);
+        self.last_sent.insert(key, Instant::now());
+        self.low_devices.insert(device_id.to_string());
+        self.open_alerts.insert(device_id.to_string(), battery);
+        if let Some(level) = level {
+            self.escalated.insert(device_id.to_string(), level);
+        }
+        Some(notification_type)
+    }
+
+    // Close alerts still showing for devices that went away or started
+    // charging, since there's nothing left to warn about
+    pub fn withdraw_resolved(&mut self, devices: &[DeviceStatus]) {
+        let resolved: Vec<String> = self
+            .open_alerts
+            .iter()
+            .filter(|(device_id, alerted_at)| {
+                devices.iter().find(|device| &device.id == *device_id).is_none_or(|device| {
+                    device.is_charging()
+                        || device.battery_percentage.is_some_and(|battery| battery >= **alerted_at + CHARGE_RISE)
+                })
+            })
+            .map(|(device_id, _)| device_id.clone())
+            .collect();
+
+        for device_id in resolved {
+            self.open_alerts.remove(&device_id);
+            self.held.retain(|held| held.device_id != device_id);
+            for sink in &self.sinks {
+                sink.withdraw(&device_id);
+            }
+        }
+    }
+
+    // The lowest configured escalation level the battery is at or below
+    fn escalation_level(&self, battery: u8) -> Option<u8> {
+        self.config
+            .sinks
+            .escalation_levels
+            .iter()
+            .copied()
+            .filter(|level| battery <= *level)
+            .min()
+    }
+
+    fn suppression_for(&self, notification_type: NotificationType) -> Duration {
+        match notification_type {
+            NotificationType::LowBattery => self.config.low_battery_suppression,
+            NotificationType::CriticalBattery => self.config.critical_battery_suppression,
+            NotificationType::BatteryRecovered => self.config.low_battery_suppression,
+        }
+    }
+
+    fn send(&mut self, notification_type: NotificationType, device_id: &str, device_name: &str, battery: u8) {
+        let event = BatteryEvent {
+            device_id: device_id.to_string(),
+            device_name: device_name.to_string(),
+            battery,
+            notification_type,
+            timestamp: unix_now(),
+        };
+
+        if self.recent_events.len() == RECENT_EVENTS {
+            self.recent_events.pop_front();
+        }
+        self.recent_events.push_back(event.clone());
+
+        // Every sink gets the event, so one failing channel doesn't lose the alert
+        for sink in self.sinks.iter().filter(|sink| sink.keeps_record()) {
+            if let Err(e) = sink.send(&event) {
+                warn!("Failed to deliver {} notification: {}", sink.name(), e);
+            }
+        }
+
+        // A flapping scanner can produce a burst of alerts; past the limit they
+        // wait, behind any already waiting so none overtakes them
+        self.flush_held();
+        if !self.held.is_empty() || self.rate_limited() {
+            debug!("Holding back {} alert for {}: rate limit reached", notification_type.key(), device_id);
+            self.held.retain(|held| held.device_id != event.device_id);
+            self.held.push(event);
+            return;
+        }
+
+        for sink in self.sinks.iter().filter(|sink| !sink.keeps_record()) {
+            if let Err(e) = sink.send(&event) {
+                warn!("Failed to deliver {} notification: {}", sink.name(), e);
+            }
+        }
+        self.delivered.push_back(Instant::now());
+    }
+
+    // Send the alerts the rate limit held back as one digest, once it allows another
+    pub fn flush_held(&mut self) {
+        if !self.rate_limited() {
+            self.send_held();
+        }
+    }
+
+    // Send whatever is held as one digest now, limit or not, e.g. before exiting
+    pub fn send_held(&mut self) {
+        if self.held.is_empty() {
+            return;
+        }
+        let events = std::mem::take(&mut self.held);
+        info!("Sending {} rate-limited alerts as one digest", events.len());
+        for sink in self.sinks.iter().filter(|sink| !sink.keeps_record()) {
+            if let Err(e) = sink.send_digest(&events) {
+                warn!("Failed to deliver {} notification: {}", sink.name(), e);
+            }
+        }
+        self.delivered.push_back(Instant::now());
+    }
+
+    // When the rate limit next lets held alerts out; None when nothing is held
+    pub fn held_flush_at(&self) -> Option<Instant> {
+        if self.held.is_empty() {
+            return None;
+        }
+        let limit = self.config.sinks.max_per_minute as usize;
+        if limit == 0 || self.delivered.len() < limit {
+            return Some(Instant::now());
+        }
+        // The oldest delivery leaving the window frees a slot
+        self.delivered.front().map(|sent| *sent + RATE_WINDOW)
+    }
+
+    fn rate_limited(&mut self) -> bool {
+        let limit = self.config.sinks.max_per_minute;
+        if limit == 0 {
+            return false;
+        }
+        while self.delivered.front().is_some_and(|sent| sent.elapsed() >= RATE_WINDOW) {
+            self.delivered.pop_front();
+        }
+        self.delivered.len() >= limit as usize
+    }
+}
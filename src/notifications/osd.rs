@@ -0,0 +1,109 @@
+use super::{BatteryEvent, NotificationSink, NotificationType};
+use gtk4::glib;
+use gtk4::prelude::*;
+use gtk4_layer_shell::{Edge, KeyboardMode, Layer, LayerShell};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+// How long the overlay stays up unless it's clicked away
+const OSD_TIMEOUT: Duration = Duration::from_secs(8);
+const FLASH_INTERVAL: Duration = Duration::from_millis(400);
+const FLASH_COUNT: u32 = 6;
+
+// GTK lives on its own thread with its own main loop; overlays are handed to
+// it through its main context. Set up on first use, once per process.
+static GTK_CONTEXT: OnceLock<Result<glib::MainContext, String>> = OnceLock::new();
+
+fn gtk_context() -> Result<&'static glib::MainContext, String> {
+    GTK_CONTEXT
+        .get_or_init(|| {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            std::thread::Builder::new()
+                .name("osd".to_string())
+                .spawn(move || {
+                    if let Err(e) = gtk4::init() {
+                        let _ = sender.send(Err(e.to_string()));
+                        return;
+                    }
+                    if !gtk4_layer_shell::is_supported() {
+                        let _ = sender.send(Err("compositor does not support wlr-layer-shell".to_string()));
+                        return;
+                    }
+                    let _ = sender.send(Ok(glib::MainContext::default()));
+                    glib::MainLoop::new(None, false).run();
+                })
+                .map_err(|e| e.to_string())?;
+            receiver.recv().map_err(|e| e.to_string())?
+        })
+        .as_ref()
+        .map_err(Clone::clone)
+}
+
+// A full-width overlay above fullscreen windows for critical alerts, via
+// gtk4-layer-shell on Wayland compositors that support it
+pub struct OsdSink;
+
+impl NotificationSink for OsdSink {
+    fn name(&self) -> &'static str {
+        "osd"
+    }
+
+    fn send(&self, event: &BatteryEvent) -> Result<(), Box<dyn std::error::Error>> {
+        if event.notification_type != NotificationType::CriticalBattery {
+            return Ok(());
+        }
+
+        let title = event.notification_type.title();
+        let body = event.body();
+        gtk_context()?.invoke(move || show_overlay(&title, &body));
+        Ok(())
+    }
+}
+
+fn show_overlay(title: &str, body: &str) {
+    let window = gtk4::Window::new();
+    window.init_layer_shell();
+    window.set_namespace("battery-monitor-osd");
+    window.set_layer(Layer::Overlay);
+    window.set_keyboard_mode(KeyboardMode::None);
+    window.set_anchor(Edge::Top, true);
+    window.set_margin(Edge::Top, 48);
+
+    let label = gtk4::Label::new(None);
+    label.set_markup(&format!(
+        "<span size=\"xx-large\" weight=\"bold\">{}</span>\n<span size=\"x-large\">{}</span>",
+        glib::markup_escape_text(title),
+        glib::markup_escape_text(body),
+    ));
+    label.set_justify(gtk4::Justification::Center);
+    label.set_margin_top(24);
+    label.set_margin_bottom(24);
+    label.set_margin_start(48);
+    label.set_margin_end(48);
+    window.set_child(Some(&label));
+
+    // Click anywhere on it to dismiss
+    let click = gtk4::GestureClick::new();
+    let clicked_window = window.clone();
+    click.connect_pressed(move |_, _, _, _| clicked_window.close());
+    window.add_controller(click);
+
+    // Blink a few times so it catches the eye mid-game
+    let flashing_window = window.clone();
+    let mut flashes = 0;
+    glib::timeout_add_local(FLASH_INTERVAL, move || {
+        flashes += 1;
+        flashing_window.set_opacity(if flashes % 2 == 0 { 1.0 } else { 0.4 });
+        if flashes < FLASH_COUNT {
+            glib::ControlFlow::Continue
+        } else {
+            flashing_window.set_opacity(1.0);
+            glib::ControlFlow::Break
+        }
+    });
+
+    let timeout_window = window.clone();
+    glib::timeout_add_local_once(OSD_TIMEOUT, move || timeout_window.close());
+
+    window.present();
+}
@@ -0,0 +1,88 @@
+use super::{BatteryEvent, NotificationSink, Urgency};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::warn;
+use zbus::zvariant::Value;
+
+pub const PORTAL_NAME: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const PORTAL_INTERFACE: &str = "org.freedesktop.portal.Notification";
+
+// How desktop alerts reach the screen
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DesktopBackend {
+    // The portal inside a Flatpak or Snap, notify-send everywhere else
+    #[default]
+    Auto,
+    NotifySend,
+    Portal,
+}
+
+impl DesktopBackend {
+    pub fn resolve(self) -> Self {
+        match self {
+            DesktopBackend::Auto if is_sandboxed() => DesktopBackend::Portal,
+            DesktopBackend::Auto => DesktopBackend::NotifySend,
+            backend => backend,
+        }
+    }
+}
+
+// Flatpak and Snap confine what the monitor can reach on the session bus
+pub fn is_sandboxed() -> bool {
+    Path::new("/.flatpak-info").exists() || std::env::var_os("SNAP").is_some()
+}
+
+// Alerts through the XDG desktop portal's Notification interface. Each
+// device's notification uses the device id as its portal id, so a newer one
+// replaces it in place.
+pub struct PortalSink;
+
+impl NotificationSink for PortalSink {
+    fn name(&self) -> &'static str {
+        "portal"
+    }
+
+    fn send(&self, event: &BatteryEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let id = event.device_id.clone();
+        let title = event.notification_type.title();
+        let body = event.body();
+        let priority = match event.notification_type.urgency() {
+            Urgency::Low => "low",
+            Urgency::Normal => "normal",
+            Urgency::Critical => "urgent",
+        };
+
+        tokio::spawn(async move {
+            let notification = HashMap::from([
+                ("title", Value::from(title.as_str())),
+                ("body", Value::from(body.as_str())),
+                ("priority", Value::from(priority)),
+            ]);
+            if let Err(e) = call_portal("AddNotification", &(id.as_str(), notification)).await {
+                warn!("Failed to send portal notification: {}", e);
+            }
+        });
+        Ok(())
+    }
+
+    fn withdraw(&self, device_id: &str) {
+        let id = device_id.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = call_portal("RemoveNotification", &(id.as_str(),)).await {
+                warn!("Failed to remove portal notification: {}", e);
+            }
+        });
+    }
+}
+
+async fn call_portal<B>(method: &str, body: &B) -> zbus::Result<()>
+where
+    B: Serialize + zbus::zvariant::DynamicType,
+{
+    let connection = zbus::Connection::session().await?;
+    connection.call_method(Some(PORTAL_NAME), PORTAL_PATH, Some(PORTAL_INTERFACE), method, body).await?;
+    Ok(())
+}
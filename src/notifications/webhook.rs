@@ -0,0 +1,85 @@
+use super::{BatteryEvent, NotificationSink};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+use tracing::warn;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub url: String,
+    // Attempts after the first failed delivery, doubling the delay each time
+    pub retries: u32,
+    pub timeout_secs: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            retries: 3,
+            timeout_secs: 10,
+        }
+    }
+}
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+
+// POST each alert as JSON to a URL, e.g. for ntfy or a Slack/Discord relay
+pub struct WebhookSink {
+    config: WebhookConfig,
+    client: reqwest::Client,
+}
+
+impl WebhookSink {
+    pub fn new(config: WebhookConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()?;
+        Ok(Self { config, client })
+    }
+}
+
+impl NotificationSink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn send(&self, event: &BatteryEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let payload = json!({
+            "device_id": event.device_id,
+            "device": event.device_name,
+            "level": event.battery,
+            "event": event.notification_type.key(),
+            "timestamp": event.timestamp,
+            "message": event.body(),
+        });
+
+        let client = self.client.clone();
+        let url = self.config.url.clone();
+        let retries = self.config.retries;
+        tokio::spawn(async move {
+            let mut backoff = INITIAL_BACKOFF;
+            for attempt in 0..=retries {
+                let result = client
+                    .post(&url)
+                    .json(&payload)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+
+                match result {
+                    Ok(_) => return,
+                    Err(e) if attempt < retries => {
+                        warn!("Webhook delivery to {} failed, retrying in {}s: {}", url, backoff.as_secs(), e);
+                        tokio::time::sleep(backoff).await;
+                        backoff *= 2;
+                    }
+                    Err(e) => warn!("Giving up on webhook delivery to {}: {}", url, e),
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+use crate::status::DeviceStatus;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+pub const DEFAULT_STATUS_FILE: &str = "/tmp/bluetooth-battery-status";
+
+// Battery levels above this are shown as "high" in Waybar
+const MEDIUM_BATTERY_THRESHOLD: u8 = 50;
+
+#[derive(Clone, Debug)]
+pub enum StatusOutput {
+    // Plain status text for the GNOME/Generic Monitor integration
+    PlainFile(PathBuf),
+    // Waybar custom module JSON, rewritten on every update
+    WaybarFile(PathBuf),
+    // Waybar custom module JSON, one line per update on stdout
+    WaybarStdout,
+    // A `[[status_files]]` entry from the config
+    Template(TemplateFile),
+}
+
+// A status file laid out by a template, for Conky, SwayNC and anything else
+// that reads a text file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatusFileConfig {
+    pub path: PathBuf,
+    // {status}, {devices}, {lowest}, {count} and {time} are filled in
+    pub template: String,
+    // How each device in {devices} looks, with the same placeholders as `status --format`
+    pub device_template: String,
+    // Put between devices in {devices}
+    pub separator: String,
+    // Write at most this often, with the latest status once the interval is
+    // up; 0 writes on every update
+    pub interval_secs: u64,
+}
+
+impl Default for StatusFileConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::new(),
+            template: "{devices}\n".to_string(),
+            device_template: "{icon} {name}: {level}%".to_string(),
+            separator: "\n".to_string(),
+            interval_secs: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TemplateFile {
+    config: StatusFileConfig,
+    state: Arc<Mutex<ThrottleState>>,
+}
+
+#[derive(Debug, Default)]
+struct ThrottleState {
+    last_write: Option<Instant>,
+    // Content held back until the interval is up
+    pending: Option<String>,
+}
+
+impl TemplateFile {
+    pub fn new(config: StatusFileConfig) -> Self {
+        Self {
+            config,
+            state: Arc::default(),
+        }
+    }
+
+    fn render(&self, status_text: &str, devices: &[DeviceStatus], low_threshold: u8) -> String {
+        let lowest = devices.iter().filter_map(|d| d.battery_percentage).min();
+        let device_lines = devices
+            .iter()
+            .map(|device| format_template(&self.config.device_template, device, low_threshold))
+            .collect::<Vec<_>>()
+            .join(&self.config.separator);
+
+        self.config
+            .template
+            .replace("{status}", status_text)
+            .replace("{devices}", &device_lines)
+            .replace("{lowest}", &lowest.map_or("?".to_string(), |b| b.to_string()))
+            .replace("{count}", &devices.len().to_string())
+            .replace("{time}", &chrono::Local::now().format("%H:%M").to_string())
+    }
+
+    fn write(&self, contents: String) -> std::io::Result<()> {
+        let interval = Duration::from_secs(self.config.interval_secs);
+        let mut state = self.state.lock().unwrap();
+        let wait = state.last_write.map(|last| interval.saturating_sub(last.elapsed())).unwrap_or_default();
+        if wait.is_zero() {
+            // Anything held back is older than this
+            state.pending = None;
+            state.last_write = Some(Instant::now());
+            return write_atomic(&self.config.path, &contents);
+        }
+
+        // Only the first held-back update needs a timer; later ones just
+        // replace what it will write
+        if state.pending.replace(contents).is_none() {
+            let file = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(wait).await;
+                let mut state = file.state.lock().unwrap();
+                if let Some(contents) = state.pending.take() {
+                    state.last_write = Some(Instant::now());
+                    if let Err(e) = write_atomic(&file.config.path, &contents) {
+                        warn!("Failed to write status file {}: {}", file.config.path.display(), e);
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct WaybarStatus {
+    text: String,
+    tooltip: String,
+    class: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    percentage: Option<u8>,
+}
+
+impl StatusOutput {
+    pub fn write(&self, status_text: &str, devices: &[DeviceStatus], low_threshold: u8) -> std::io::Result<()> {
+        match self {
+            StatusOutput::PlainFile(path) => write_atomic(path, status_text),
+            StatusOutput::WaybarFile(path) => write_atomic(path, &waybar_json(status_text, devices, low_threshold)),
+            StatusOutput::WaybarStdout => {
+                let mut stdout = std::io::stdout().lock();
+                writeln!(stdout, "{}", waybar_json(status_text, devices, low_threshold))?;
+                stdout.flush()
+            }
+            StatusOutput::Template(file) => file.write(file.render(status_text, devices, low_threshold)),
+        }
+    }
+}
+
+// Write next to the file and rename over it, so a status bar reading it
+// never sees a half-written file. The temp file gets an unguessable name and
+// must not exist yet, so nobody can plant a symlink there in a shared /tmp.
+pub fn write_atomic(path: &Path, contents: &str) -> std::io::Result<()> {
+    let (temp_path, mut temp_file) = create_temp_file(path)?;
+    let written = temp_file
+        .write_all(contents.as_bytes())
+        .and_then(|_| std::fs::rename(&temp_path, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    written
+}
+
+// A new file named after path with a random suffix, in the same directory
+fn create_temp_file(path: &Path) -> std::io::Result<(PathBuf, std::fs::File)> {
+    let mut attempts = 0;
+    loop {
+        let candidate = temp_path(path);
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&candidate) {
+            Ok(file) => return Ok((candidate, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempts < 8 => attempts += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(format!(".{:016x}.tmp", random_suffix()));
+    path.with_file_name(temp_name)
+}
+
+// std seeds every RandomState from the OS, which is random enough for a file name
+fn random_suffix() -> u64 {
+    use std::hash::{BuildHasher, RandomState};
+    RandomState::new().hash_one((std::process::id(), Instant::now()))
+}
+
+pub fn battery_class(battery: u8, low_threshold: u8) -> &'static str {
+    if battery <= low_threshold {
+        "low"
+    } else if battery <= MEDIUM_BATTERY_THRESHOLD {
+        "medium"
+    } else {
+        "high"
+    }
+}
+
+pub fn waybar_json(status_text: &str, devices: &[DeviceStatus], low_threshold: u8) -> String {
+    let lowest = devices.iter().filter_map(|d| d.battery_percentage).min();
+
+    let tooltip = devices.iter().map(|d| d.format_line()).collect::<Vec<_>>().join("\n");
+
+    let status = WaybarStatus {
+        text: status_text.to_string(),
+        tooltip,
+        class: lowest
+            .map(|battery| battery_class(battery, low_threshold))
+            .unwrap_or("unknown")
+            .to_string(),
+        percentage: lowest,
+    };
+
+    serde_json::to_string(&status).unwrap_or_default()
+}
+
+// Fill in {name}, {level}, {icon} and {state} for one device, for polybar
+// and i3blocks script modules
+pub fn format_template(template: &str, device: &DeviceStatus, low_threshold: u8) -> String {
+    let level = device.battery_percentage.map(|b| b.to_string()).unwrap_or_else(|| "?".to_string());
+    let state = if device.batteries.iter().any(|b| b.charging) {
+        "charging"
+    } else {
+        device
+            .battery_percentage
+            .map(|battery| battery_class(battery, low_threshold))
+            .unwrap_or("unknown")
+    };
+
+    template
+        .replace("{name}", &device.name)
+        .replace("{level}", &level)
+        .replace("{icon}", &device.icon)
+        .replace("{state}", state)
+}
+
+// Aligned columns for `list`
+pub fn device_table(devices: &[DeviceStatus]) -> String {
+    let header = ["NAME", "TYPE", "TRANSPORT", "BATTERY", "CHARGING"].map(String::from);
+    let rows: Vec<[String; 5]> = devices
+        .iter()
+        .map(|device| {
+            [
+                device.name.clone(),
+                device.device_type.clone(),
+                device.transports().map(|t| t.label()).collect::<Vec<_>>().join("+"),
+                device.battery_percentage.map_or("-".to_string(), |b| format!("{}%", b)),
+                if device.is_charging() { "yes" } else { "no" }.to_string(),
+            ]
+        })
+        .collect();
+
+    let mut widths = [0; 5];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    std::iter::once(&header)
+        .chain(&rows)
+        .map(|row| {
+            let cells: Vec<String> = row.iter().zip(widths).map(|(cell, width)| format!("{:width$}", cell)).collect();
+            format!("{}\n", cells.join("  ").trim_end())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_atomic_replaces_the_file_and_leaves_no_temp_files() {
+        let dir = std::env::temp_dir().join(format!("battery-output-test-{:x}", random_suffix()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("status");
+
+        write_atomic(&path, "first").unwrap();
+        write_atomic(&path, "second").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn temp_files_get_fresh_names() {
+        let path = Path::new("/tmp/status");
+        let first = temp_path(path);
+        assert_ne!(first, temp_path(path));
+        assert_eq!(first.parent(), path.parent());
+    }
+}
@@ -0,0 +1,79 @@
+use crate::status::DeviceStatus;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+// Devices at or above this level count as full for backing off
+const FULL_BATTERY_LEVEL: u8 = 80;
+// A battery change keeps polling fast for this long
+const RECENT_CHANGE_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+// How often battery levels are re-read, from the [polling] config section
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PollingConfig {
+    pub interval_secs: u64,
+    // Poll faster for low or changing devices and back off when everything is full and stable
+    pub adaptive: bool,
+    pub fast_interval_secs: u64,
+    pub idle_interval_secs: u64,
+    // Seconds a Bluetooth device that dropped may take to come back before it
+    // counts as disconnected, so a brief dropout isn't reported at all; 0 is off
+    pub disconnect_debounce_secs: u64,
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 30,
+            adaptive: false,
+            fast_interval_secs: 10,
+            idle_interval_secs: 300,
+            disconnect_debounce_secs: 10,
+        }
+    }
+}
+
+pub struct PollScheduler {
+    config: PollingConfig,
+    last_change: Option<Instant>,
+}
+
+impl PollScheduler {
+    pub fn new(config: PollingConfig) -> Self {
+        Self {
+            config,
+            last_change: None,
+        }
+    }
+
+    // Note that a battery level or the device list changed
+    pub fn record_change(&mut self) {
+        self.last_change = Some(Instant::now());
+    }
+
+    pub fn next_interval(&self, devices: &[DeviceStatus], low_threshold: u8) -> Duration {
+        let interval = if !self.config.adaptive {
+            self.config.interval_secs
+        } else if self.needs_attention(devices, low_threshold) {
+            self.config.fast_interval_secs
+        } else if self.is_idle(devices) {
+            self.config.idle_interval_secs
+        } else {
+            self.config.interval_secs
+        };
+
+        Duration::from_secs(interval.max(1))
+    }
+
+    fn recently_changed(&self) -> bool {
+        self.last_change.is_some_and(|at| at.elapsed() < RECENT_CHANGE_WINDOW)
+    }
+
+    fn needs_attention(&self, devices: &[DeviceStatus], low_threshold: u8) -> bool {
+        self.recently_changed() || devices.iter().any(|d| d.battery_percentage.is_some_and(|b| b <= low_threshold))
+    }
+
+    fn is_idle(&self, devices: &[DeviceStatus]) -> bool {
+        devices.iter().all(|d| d.battery_percentage.is_none_or(|b| b >= FULL_BATTERY_LEVEL))
+    }
+}
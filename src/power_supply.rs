@@ -0,0 +1,139 @@
+use crate::gamepad::is_controller_supply;
+use crate::identity::IdentityMap;
+use crate::scanner::DeviceScanner;
+use crate::status::{DeviceStatus, Transport};
+use futures::future::LocalBoxFuture;
+use std::fs;
+use std::path::Path;
+
+pub(crate) const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+// Reads peripheral batteries from the kernel's power_supply class: HID
+// devices with battery reports, game controllers, Logitech receivers, styluses
+pub struct PowerSupplyScanner {
+    identities: IdentityMap,
+}
+
+impl PowerSupplyScanner {
+    pub fn new() -> Self {
+        Self {
+            identities: IdentityMap::load(),
+        }
+    }
+}
+
+impl Default for PowerSupplyScanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceScanner for PowerSupplyScanner {
+    fn name(&self) -> &'static str {
+        "power_supply"
+    }
+
+    fn scan(&mut self) -> LocalBoxFuture<'_, Result<Vec<DeviceStatus>, Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            // A capacity read can wait on the device itself
+            let mut identities = std::mem::take(&mut self.identities);
+            let (identities, devices) = tokio::task::spawn_blocking(move || {
+                let devices = scan_power_supplies(Path::new(POWER_SUPPLY_DIR), &mut identities);
+                (identities, devices.map_err(|e| e.to_string()))
+            })
+            .await?;
+            self.identities = identities;
+            Ok(devices?)
+        })
+    }
+}
+
+pub(crate) fn read_attribute(path: &Path, attribute: &str) -> Option<String> {
+    fs::read_to_string(path.join(attribute))
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn scan_power_supplies(dir: &Path, identities: &mut IdentityMap) -> Result<Vec<DeviceStatus>, Box<dyn std::error::Error>> {
+    let mut devices = Vec::new();
+
+    for entry in fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+
+        // Laptop batteries and AC adapters have System scope; only peripherals are wanted
+        if read_attribute(&path, "scope").as_deref() != Some("Device") {
+            continue;
+        }
+        let Some(capacity) = read_attribute(&path, "capacity").and_then(|c| c.parse::<u8>().ok()) else {
+            continue;
+        };
+
+        let supply_name = entry.file_name().to_string_lossy().to_string();
+        // Game controllers are the gamepad scanner's
+        if is_controller_supply(&supply_name) {
+            continue;
+        }
+        let name = read_attribute(&path, "model_name").unwrap_or_else(|| supply_name.clone());
+
+        // HID over Bluetooth shows up as hid-<mac>-battery; use the MAC as the
+        // id so it lines up with the same device seen through BlueZ. Other
+        // supply names like hidpp_battery_0 are numbered in plug-in order, so
+        // use the serial number or a remembered id instead.
+        let (id, transport) = match bluetooth_address(&supply_name) {
+            Some(address) => (address, Transport::Bluetooth),
+            None => {
+                let id = match read_attribute(&path, "serial_number") {
+                    Some(serial) => format!("power_supply:{}", serial),
+                    None => {
+                        let manufacturer = read_attribute(&path, "manufacturer").unwrap_or_default();
+                        identities.id_for(
+                            &format!("power_supply:{}:{}", manufacturer, name),
+                            &format!("power_supply:{}", name.to_lowercase().replace(' ', "_")),
+                        )
+                    }
+                };
+                (id, Transport::Usb)
+            }
+        };
+
+        let (device_type, icon) = if is_stylus(&supply_name, &name) {
+            ("Stylus", "🖊️")
+        } else {
+            ("PowerSupply", "🔋")
+        };
+
+        devices.push(DeviceStatus {
+            id,
+            name,
+            device_type: device_type.to_string(),
+            transport,
+            extra_transports: Vec::new(),
+            battery_percentage: Some(capacity.min(100)),
+            batteries: Vec::new(),
+            rssi: None,
+            icon: icon.to_string(),
+            stale: false,
+        });
+    }
+
+    devices.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(devices)
+}
+
+// The wacom driver names its supplies wacom_battery_<n>; active pens on
+// other digitizers (XP-Pen, Surface and USI pens) show up as hid-*-battery
+// with the pen's model name
+fn is_stylus(supply_name: &str, model_name: &str) -> bool {
+    let model_name = model_name.to_lowercase();
+    supply_name.starts_with("wacom_battery") || model_name.contains("stylus") || model_name.contains(" pen")
+}
+
+fn bluetooth_address(supply_name: &str) -> Option<String> {
+    let address = supply_name.strip_prefix("hid-")?.strip_suffix("-battery")?;
+    is_mac_address(address).then(|| address.to_uppercase())
+}
+
+pub(crate) fn is_mac_address(address: &str) -> bool {
+    address.len() == 17 && address.split(':').all(|part| part.len() == 2 && part.chars().all(|c| c.is_ascii_hexdigit()))
+}
@@ -0,0 +1,164 @@
+use crate::config::config_dir;
+use crate::hid::probe::HidProbe;
+use crate::hid::DongleProtocol;
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::OnceLock;
+use tracing::{info, warn};
+
+// Shipped quirks; see the comment at the top of the file for the format
+const BUILTIN_QUIRKS: &str = include_str!("../quirks/builtin.toml");
+
+// How to read a device's battery
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatteryMethod {
+    Ajazz,
+    Razer,
+    #[serde(rename = "steelseries")]
+    SteelSeries,
+    Corsair,
+    // One byte of a feature report, at report_id and offset
+    FeatureReport,
+    // The quirk's own probes, tried in order
+    Probe,
+    // The device has no readable battery; don't probe it
+    None,
+}
+
+impl BatteryMethod {
+    // The gaming dongle protocol this method stands for, if it is one
+    pub fn dongle_protocol(self) -> Option<DongleProtocol> {
+        match self {
+            BatteryMethod::Razer => Some(DongleProtocol::Razer),
+            BatteryMethod::SteelSeries => Some(DongleProtocol::SteelSeries),
+            BatteryMethod::Corsair => Some(DongleProtocol::Corsair),
+            _ => None,
+        }
+    }
+}
+
+// One entry of a quirks file
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Quirk {
+    // "usb:vvvv:pppp", with "*" for any product, or "bt:XX:XX:XX" for a
+    // Bluetooth vendor's address prefix (OUI)
+    #[serde(rename = "match")]
+    pub matches: String,
+    // What the entry is for; only shown in logs
+    pub name: Option<String>,
+    // How the device is listed, e.g. "Mechanical" or "Headphones"
+    pub device_type: Option<String>,
+    pub battery: Option<BatteryMethod>,
+    pub report_id: Option<u8>,
+    // Byte of the report holding the level; 1, right after the report id, by default
+    pub offset: Option<usize>,
+    // Scale raw readings, for devices with a coarser gauge than percent
+    pub multiply: Option<f32>,
+    // For battery = "probe"
+    pub probe: Vec<HidProbe>,
+}
+
+impl Quirk {
+    fn matches_usb(&self, vendor_id: u16, product_id: u16) -> bool {
+        let Some(rest) = self.matches.strip_prefix("usb:") else { return false };
+        let Some((vendor, product)) = rest.split_once(':') else { return false };
+        u16::from_str_radix(vendor, 16).is_ok_and(|vendor| vendor == vendor_id)
+            && (product == "*" || u16::from_str_radix(product, 16).is_ok_and(|product| product == product_id))
+    }
+
+    fn matches_bluetooth(&self, address: &str) -> bool {
+        self.matches
+            .strip_prefix("bt:")
+            .is_some_and(|oui| address.len() >= oui.len() && address[..oui.len()].eq_ignore_ascii_case(oui))
+    }
+
+    // The HID queries for a feature_report or probe quirk
+    pub fn probes(&self) -> Vec<HidProbe> {
+        match self.battery {
+            Some(BatteryMethod::FeatureReport) => vec![HidProbe {
+                report_id: self.report_id.unwrap_or(0),
+                offset: self.offset.unwrap_or(1),
+                scale: self.multiply,
+                ..Default::default()
+            }],
+            Some(BatteryMethod::Probe) => self.probe.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    // A raw reading as a percentage, None if it's out of range, so a
+    // miscalibrated factor doesn't pass for a full battery
+    pub fn transform(&self, raw: u8) -> Option<u8> {
+        let level = match self.multiply {
+            Some(factor) => (raw as f32 * factor).round(),
+            None => raw as f32,
+        };
+        (0.0..=100.0).contains(&level).then_some(level as u8)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct QuirksFile {
+    #[serde(default)]
+    quirk: Vec<Quirk>,
+}
+
+// Built-in quirks plus the user's, with the user's winning
+#[derive(Debug, Default)]
+pub struct QuirksDb {
+    // In priority order, the user's first
+    quirks: Vec<Quirk>,
+}
+
+impl QuirksDb {
+    // The built-in table and every *.toml in the config dir's quirks/
+    pub fn load() -> Self {
+        let mut db = Self::default();
+        let user_dir = config_dir().join("quirks");
+        if let Ok(entries) = std::fs::read_dir(&user_dir) {
+            let mut paths: Vec<_> = entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+                .collect();
+            paths.sort();
+            for path in paths {
+                db.add_file(&path);
+            }
+        }
+        match toml::from_str::<QuirksFile>(BUILTIN_QUIRKS) {
+            Ok(file) => db.quirks.extend(file.quirk),
+            Err(e) => warn!("Built-in quirks are invalid: {}", e),
+        }
+        db
+    }
+
+    fn add_file(&mut self, path: &Path) {
+        let file = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| toml::from_str::<QuirksFile>(&text).map_err(|e| e.to_string()));
+        match file {
+            Ok(file) => {
+                info!("Loaded {} quirk(s) from {}", file.quirk.len(), path.display());
+                self.quirks.extend(file.quirk);
+            }
+            Err(e) => warn!("Ignoring quirks file {}: {}", path.display(), e),
+        }
+    }
+
+    pub fn for_usb(&self, vendor_id: u16, product_id: u16) -> Option<&Quirk> {
+        self.quirks.iter().find(|quirk| quirk.matches_usb(vendor_id, product_id))
+    }
+
+    pub fn for_bluetooth(&self, address: &str) -> Option<&Quirk> {
+        self.quirks.iter().find(|quirk| quirk.matches_bluetooth(address))
+    }
+}
+
+// The quirks database, loaded on first use
+pub fn quirks() -> &'static QuirksDb {
+    static QUIRKS: OnceLock<QuirksDb> = OnceLock::new();
+    QUIRKS.get_or_init(QuirksDb::load)
+}
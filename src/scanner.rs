@@ -0,0 +1,276 @@
+use crate::logging::timed;
+use crate::mock::{MockDeviceConfig, MockScanner};
+use crate::smoothing::BatteryFilter;
+use crate::status::DeviceStatus;
+use futures::future::{join_all, LocalBoxFuture};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, field, info, info_span, warn};
+
+// A failing scanner waits this long before its next try, doubling with each
+// failure in a row up to BACKOFF_MAX
+const BACKOFF_BASE: Duration = Duration::from_secs(30);
+const BACKOFF_MAX: Duration = Duration::from_secs(30 * 60);
+// Failures in a row before a scanner counts as unhealthy
+const UNHEALTHY_AFTER: u32 = 3;
+
+// A polled source of devices. Bluetooth and keyboards are handled by their
+// own managers; scanners are for everything else that can report a battery.
+// Scans run on the event loop and the timeout only fires at an await, so a
+// scan must not block: file and device I/O goes through spawn_blocking.
+pub trait DeviceScanner {
+    fn name(&self) -> &'static str;
+    fn scan(&mut self) -> LocalBoxFuture<'_, Result<Vec<DeviceStatus>, Box<dyn std::error::Error>>>;
+}
+
+// Which optional scanners run, from the [scanners] config section
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScannersConfig {
+    // Peripheral batteries the kernel exposes in /sys/class/power_supply
+    pub power_supply: bool,
+    // Game controller batteries from their kernel power_supply entries
+    pub gamepad: bool,
+    // Battery levels broadcast in BLE advertisements by devices that never
+    // connect. Off by default since it walks every device BlueZ has seen
+    // nearby on each poll, which in busy places is a lot of D-Bus calls.
+    pub ble_adverts: bool,
+    // Fake devices for development and demos
+    pub mock_devices: bool,
+    // The fake devices to show; a built-in demo set when empty
+    pub mock: Vec<MockDeviceConfig>,
+    // A scan taking longer than this is abandoned, so one stuck source
+    // can't hold up the others
+    pub timeout_secs: u64,
+}
+
+impl Default for ScannersConfig {
+    fn default() -> Self {
+        Self {
+            power_supply: false,
+            gamepad: true,
+            ble_adverts: false,
+            mock_devices: false,
+            mock: Vec::new(),
+            timeout_secs: 10,
+        }
+    }
+}
+
+// How a scanner has been doing, for `ctl stats`
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ScannerStats {
+    pub name: &'static str,
+    pub scans: u64,
+    pub failures: u64,
+    pub last_duration_ms: Option<u64>,
+    // Unix timestamp (seconds) of the last successful scan
+    pub last_success: Option<u64>,
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+}
+
+// Whether the device sources are working, for `ctl health`, D-Bus and the tray
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MonitorHealth {
+    // Unix timestamp (seconds) of the last successful scan by any scanner
+    pub last_success: Option<u64>,
+    // The most failures in a row of any scanner
+    pub consecutive_failures: u32,
+    // Scanners that have failed UNHEALTHY_AFTER times or more in a row
+    pub failing: Vec<FailingScanner>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FailingScanner {
+    pub name: &'static str,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+}
+
+impl MonitorHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.failing.is_empty()
+    }
+}
+
+struct RegisteredScanner {
+    scanner: Box<dyn DeviceScanner>,
+    // Devices from the last successful scan
+    devices: Vec<DeviceStatus>,
+    stats: ScannerStats,
+    // Set after a failure; scans before then are skipped
+    retry_at: Option<Instant>,
+}
+
+pub struct ScannerRegistry {
+    scanners: Vec<RegisteredScanner>,
+    timeout: Duration,
+    pub battery_filter: BatteryFilter,
+}
+
+impl ScannerRegistry {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            scanners: Vec::new(),
+            timeout,
+            battery_filter: BatteryFilter::default(),
+        }
+    }
+
+    // Build the registry with every scanner enabled in the config
+    pub fn from_config(config: &ScannersConfig) -> Self {
+        let mut registry = Self::new(Duration::from_secs(config.timeout_secs));
+        if config.power_supply {
+            registry.register(Box::new(crate::power_supply::PowerSupplyScanner::new()));
+        }
+        if config.gamepad {
+            registry.register(Box::new(crate::gamepad::GamepadScanner::default()));
+        }
+        if config.ble_adverts {
+            registry.register(Box::new(crate::advertisement::AdvertisementScanner::default()));
+        }
+        if config.mock_devices {
+            registry.register(Box::new(MockScanner::new(config.mock.clone())));
+        }
+        registry
+    }
+
+    pub fn register(&mut self, scanner: Box<dyn DeviceScanner>) {
+        info!("Registered {} scanner", scanner.name());
+        let stats = ScannerStats {
+            name: scanner.name(),
+            ..Default::default()
+        };
+        self.scanners.push(RegisteredScanner {
+            scanner,
+            devices: Vec::new(),
+            stats,
+            retry_at: None,
+        });
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    // Run every scanner concurrently, each under the scan timeout, returning
+    // whether any device list changed. A failing scanner keeps its previous devices.
+    pub async fn scan_all(&mut self) -> bool {
+        let timeout = self.timeout;
+        let scans = self.scanners.iter_mut().map(|registered| {
+            let span = info_span!("scan.scanner", scanner = registered.stats.name, elapsed_ms = field::Empty);
+            timed(span, Self::scan_one(registered, timeout))
+        });
+        let results = join_all(scans).await;
+
+        // Smooth the new readings before comparing, so a flapping level
+        // doesn't count as a change
+        let mut changed = false;
+        for (registered, devices) in self.scanners.iter_mut().zip(results) {
+            let Some(mut devices) = devices else { continue };
+            for device in &mut devices {
+                device.battery_percentage = self.battery_filter.apply(&device.id, device.battery_percentage);
+            }
+            changed |= !same_devices(&registered.devices, &devices);
+            registered.devices = devices;
+        }
+        changed
+    }
+
+    // One scanner's scan under the timeout, updating its stats; None if it
+    // failed or is backing off from earlier failures
+    async fn scan_one(registered: &mut RegisteredScanner, timeout: Duration) -> Option<Vec<DeviceStatus>> {
+        if registered.retry_at.is_some_and(|retry_at| Instant::now() < retry_at) {
+            debug!("Skipping {} scan while it backs off", registered.stats.name);
+            return None;
+        }
+
+        let started = Instant::now();
+        let result = match tokio::time::timeout(timeout, registered.scanner.scan()).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("timed out after {}s", timeout.as_secs()).into()),
+        };
+
+        let stats = &mut registered.stats;
+        stats.scans += 1;
+        stats.last_duration_ms = Some(started.elapsed().as_millis() as u64);
+
+        match result {
+            Ok(devices) => {
+                if stats.consecutive_failures > 0 {
+                    info!("{} scan works again after {} failures", stats.name, stats.consecutive_failures);
+                }
+                stats.last_success = SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs());
+                stats.last_error = None;
+                stats.consecutive_failures = 0;
+                registered.retry_at = None;
+                Some(devices)
+            }
+            Err(e) => {
+                stats.failures += 1;
+                stats.consecutive_failures += 1;
+                stats.last_error = Some(e.to_string());
+                let backoff = backoff(stats.consecutive_failures);
+                warn!("{} scan failed: {}, trying again in {}s", stats.name, e, backoff.as_secs());
+                registered.retry_at = Some(Instant::now() + backoff);
+                None
+            }
+        }
+    }
+
+    pub fn stats(&self) -> Vec<ScannerStats> {
+        self.scanners.iter().map(|registered| registered.stats.clone()).collect()
+    }
+
+    pub fn health(&self) -> MonitorHealth {
+        let stats = self.scanners.iter().map(|registered| &registered.stats);
+        MonitorHealth {
+            last_success: stats.clone().filter_map(|stats| stats.last_success).max(),
+            consecutive_failures: stats.clone().map(|stats| stats.consecutive_failures).max().unwrap_or(0),
+            failing: stats
+                .filter(|stats| stats.consecutive_failures >= UNHEALTHY_AFTER)
+                .map(|stats| FailingScanner {
+                    name: stats.name,
+                    consecutive_failures: stats.consecutive_failures,
+                    last_error: stats.last_error.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = &DeviceStatus> {
+        self.scanners.iter().flat_map(|registered| registered.devices.iter())
+    }
+
+    // Status text for the scanned devices, leaving out those `skip` matches
+    pub fn get_status_text(&self, skip: impl Fn(&DeviceStatus) -> bool) -> Option<String> {
+        let parts: Vec<String> = self
+            .devices()
+            .filter(|device| !skip(device))
+            .filter_map(|device| {
+                let battery = device.battery_percentage?;
+                let short_name = if device.name.chars().count() > 12 {
+                    format!("{}...", device.name.chars().take(9).collect::<String>())
+                } else {
+                    device.name.clone()
+                };
+                Some(format!("{} {}: {}%", device.icon, short_name, battery))
+            })
+            .collect();
+
+        (!parts.is_empty()).then(|| parts.join(" | "))
+    }
+}
+
+fn backoff(consecutive_failures: u32) -> Duration {
+    BACKOFF_BASE.saturating_mul(1 << consecutive_failures.saturating_sub(1).min(16)).min(BACKOFF_MAX)
+}
+
+fn same_devices(old: &[DeviceStatus], new: &[DeviceStatus]) -> bool {
+    old.len() == new.len()
+        && old
+            .iter()
+            .zip(new)
+            .all(|(a, b)| a.id == b.id && a.battery_percentage == b.battery_percentage && a.batteries == b.batteries)
+}
@@ -0,0 +1,79 @@
+use crate::config::xdg_config_home;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const SERVICE_NAME: &str = "battery-monitor.service";
+
+pub fn unit_path() -> PathBuf {
+    xdg_config_home().join("systemd/user").join(SERVICE_NAME)
+}
+
+fn unit_contents(executable: &Path) -> String {
+    format!(
+        "[Unit]
+Description=Battery Monitor
+After=graphical-session.target bluetooth.target
+
+[Service]
+Type=notify
+ExecStart={}
+ExecReload=kill -HUP $MAINPID
+WatchdogSec=60
+Restart=on-failure
+RestartSec=5
+
+[Install]
+WantedBy=default.target
+",
+        executable.display()
+    )
+}
+
+fn systemctl(args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+    let status = Command::new("systemctl").arg("--user").args(args).status()?;
+    if !status.success() {
+        return Err(format!("systemctl --user {} failed ({})", args.join(" "), status).into());
+    }
+    Ok(())
+}
+
+// Write a user unit pointing at the current executable, optionally enabling it
+pub fn install(enable: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let executable = std::env::current_exe()?;
+    let path = unit_path();
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, unit_contents(&executable))?;
+    println!("Wrote {}", path.display());
+
+    systemctl(&["daemon-reload"])?;
+
+    if enable {
+        systemctl(&["enable", "--now", SERVICE_NAME])?;
+        println!("Enabled and started {}", SERVICE_NAME);
+    } else {
+        println!("Start it with: systemctl --user enable --now {}", SERVICE_NAME);
+    }
+
+    Ok(())
+}
+
+pub fn uninstall() -> Result<(), Box<dyn std::error::Error>> {
+    let path = unit_path();
+    if !path.exists() {
+        println!("{} is not installed", SERVICE_NAME);
+        return Ok(());
+    }
+
+    // Stopping fails harmlessly if the service was never enabled
+    if let Err(e) = systemctl(&["disable", "--now", SERVICE_NAME]) {
+        eprintln!("Warning: {}", e);
+    }
+
+    std::fs::remove_file(&path)?;
+    println!("Removed {}", path.display());
+
+    systemctl(&["daemon-reload"])
+}
@@ -0,0 +1,131 @@
+use crate::config::{config_path, Config};
+use crate::control;
+use crate::output;
+use crate::service;
+use crate::status::DeviceStatus;
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+// How often the first scan prints a progress dot
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+// Walk through a first configuration on the terminal: check Bluetooth, ask
+// for the alert thresholds and autostart, scan once so the user sees what
+// will be monitored, then write the answers to the config file
+pub async fn run<F>(config: &Config, scan: F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: AsyncFnOnce() -> Result<Vec<DeviceStatus>, Box<dyn std::error::Error>>,
+{
+    let path = config_path();
+    println!("Setting up the battery monitor; answers are saved to {}", path.display());
+    println!("Press Enter to keep the value in brackets.");
+    println!();
+
+    println!("Checking Bluetooth...");
+    match check_bluetooth().await {
+        Ok(detail) => println!("  ✓ {}", detail),
+        Err(e) => {
+            println!("  ✗ {}", e);
+            println!("    Keyboards and other sources still work; `battery_percentage doctor` shows how to fix this");
+        }
+    }
+    println!();
+
+    let low = ask_percent("Warn when a battery drops to (%)", config.notifications.low_battery_threshold)?;
+    let critical = loop {
+        let default = config.notifications.critical_battery_threshold.min(low);
+        let critical = ask_percent("Critical alert at (%)", default)?;
+        if critical <= low {
+            break critical;
+        }
+        println!("    The critical level can't be above the warning level ({}%)", low);
+    };
+    let auto_start = ask_yes_no("Start the monitor when you log in?", config.auto_start)?;
+    println!();
+
+    print!("Scanning for devices");
+    io::stdout().flush()?;
+    let mut scan = std::pin::pin!(scan());
+    let mut progress = tokio::time::interval(PROGRESS_INTERVAL);
+    // The first tick fires right away
+    progress.tick().await;
+    let devices = loop {
+        tokio::select! {
+            devices = &mut scan => break devices,
+            _ = progress.tick() => {
+                print!(".");
+                io::stdout().flush()?;
+            }
+        }
+    };
+    println!();
+    match devices {
+        Ok(devices) if devices.is_empty() => println!("No devices found yet; paired devices show up once they connect"),
+        Ok(devices) => print!("{}", output::device_table(&devices)),
+        Err(e) => println!("The scan failed ({}); the monitor keeps retrying once it runs", e),
+    }
+    println!();
+
+    Config::set_all(&[
+        ("notifications.low_battery_threshold", &low.to_string()),
+        ("notifications.critical_battery_threshold", &critical.to_string()),
+        ("auto_start", &auto_start.to_string()),
+    ])?;
+    println!("Saved {}", path.display());
+    // Not running is fine; it reads the file when it starts
+    if let Ok(response) = control::send_command("reload").await {
+        println!("Running monitor: {}", response);
+    }
+
+    if auto_start {
+        service::install(true)?;
+    } else {
+        println!("Start it with `battery_percentage`, or run `battery_percentage install-service` later");
+    }
+    Ok(())
+}
+
+async fn check_bluetooth() -> Result<String, Box<dyn std::error::Error>> {
+    let session = bluer::Session::new().await.map_err(|e| format!("BlueZ isn't reachable: {}", e))?;
+    let adapter = session.default_adapter().await.map_err(|e| format!("no Bluetooth adapter: {}", e))?;
+    if adapter.is_powered().await? {
+        Ok(format!("{} is ready", adapter.name()))
+    } else {
+        Ok(format!("{} is powered off; the monitor powers it on at startup", adapter.name()))
+    }
+}
+
+// One trimmed line from stdin, after showing the question and its default
+fn ask(question: &str, default: &str) -> io::Result<String> {
+    print!("{} [{}]: ", question, default);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "setup needs answers on stdin"));
+    }
+    Ok(answer.trim().to_string())
+}
+
+fn ask_percent(question: &str, default: u8) -> io::Result<u8> {
+    loop {
+        let answer = ask(question, &default.to_string())?;
+        if answer.is_empty() {
+            return Ok(default);
+        }
+        match answer.trim_end_matches('%').parse::<u8>() {
+            Ok(percent) if percent <= 100 => return Ok(percent),
+            _ => println!("    Enter a number from 0 to 100"),
+        }
+    }
+}
+
+fn ask_yes_no(question: &str, default: bool) -> io::Result<bool> {
+    loop {
+        match ask(question, if default { "Y/n" } else { "y/N" })?.to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("    Answer y or n"),
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+// Damping for devices whose battery reading flaps between neighbouring
+// values, from the [smoothing] config section
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SmoothingConfig {
+    // Report the median of this many recent readings (1 turns it off)
+    pub median_window: usize,
+    // Ignore changes smaller than this many percent (0 turns it off)
+    pub min_delta: u8,
+}
+
+impl Default for SmoothingConfig {
+    fn default() -> Self {
+        Self {
+            median_window: 1,
+            min_delta: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct DeviceReadings {
+    samples: VecDeque<u8>,
+    reported: Option<u8>,
+}
+
+// Turns raw battery readings into the levels that get reported, per device
+#[derive(Default)]
+pub struct BatteryFilter {
+    config: SmoothingConfig,
+    devices: HashMap<String, DeviceReadings>,
+}
+
+impl BatteryFilter {
+    pub fn new(config: SmoothingConfig) -> Self {
+        Self {
+            config,
+            devices: HashMap::new(),
+        }
+    }
+
+    pub fn apply(&mut self, device_id: &str, raw: Option<u8>) -> Option<u8> {
+        let raw = raw?;
+        let readings = self.devices.entry(device_id.to_string()).or_default();
+
+        readings.samples.push_back(raw);
+        while readings.samples.len() > self.config.median_window.max(1) {
+            readings.samples.pop_front();
+        }
+
+        let mut sorted: Vec<u8> = readings.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let median = sorted[sorted.len() / 2];
+
+        // Hold the previous level until the reading has moved far enough,
+        // but always let a full or empty battery through
+        let level = match readings.reported {
+            Some(reported)
+                if reported.abs_diff(median) < self.config.min_delta && median != 0 && median != 100 =>
+            {
+                reported
+            }
+            _ => median,
+        };
+
+        readings.reported = Some(level);
+        Some(level)
+    }
+
+    // Start over for a device that went away, so a stale history doesn't
+    // hold back its first reading when it comes back
+    pub fn forget(&mut self, device_id: &str) {
+        self.devices.remove(device_id);
+    }
+}
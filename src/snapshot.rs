@@ -0,0 +1,169 @@
+use crate::config::data_dir;
+use crate::notifications::NotificationState;
+use crate::output::write_atomic;
+use crate::scanner::DeviceScanner;
+use crate::status::DeviceStatus;
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+// Everything the running monitor knows, dumped by `ctl snapshot` so a bug
+// report can come with the state it happened in
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: String,
+    // Unix timestamp (seconds)
+    pub timestamp: u64,
+    pub devices: Vec<DeviceStatus>,
+    pub notifications: NotificationState,
+}
+
+impl Snapshot {
+    pub fn new(devices: Vec<DeviceStatus>, notifications: NotificationState) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp: unix_now(),
+            devices,
+            notifications,
+        }
+    }
+
+    // Where `ctl snapshot` writes when not given a path
+    pub fn default_path(&self) -> PathBuf {
+        data_dir().join("snapshots").join(format!("snapshot-{}.json", self.timestamp))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let snapshot: Snapshot = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        if snapshot.version != env!("CARGO_PKG_VERSION") {
+            warn!(
+                "{} was taken with version {}, this is {}",
+                path.display(),
+                snapshot.version,
+                env!("CARGO_PKG_VERSION")
+            );
+        }
+        Ok(snapshot)
+    }
+}
+
+// The device list as the monitor last exited with it, so the next start has
+// something to show before the devices turn up again
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LastKnown {
+    // Unix timestamp (seconds)
+    pub timestamp: u64,
+    pub devices: Vec<DeviceStatus>,
+}
+
+impl LastKnown {
+    pub fn path() -> PathBuf {
+        data_dir().join("last-known-devices.json")
+    }
+
+    // The saved devices, all marked stale; none if nothing was saved
+    pub fn load() -> Vec<DeviceStatus> {
+        let last_known: LastKnown = std::fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        last_known
+            .devices
+            .into_iter()
+            .map(|device| DeviceStatus { stale: true, ..device })
+            .collect()
+    }
+
+    pub fn save(devices: Vec<DeviceStatus>) {
+        let last_known = LastKnown {
+            timestamp: unix_now(),
+            devices,
+        };
+        let path = Self::path();
+        let result = std::fs::create_dir_all(data_dir())
+            .and_then(|_| write_atomic(&path, &serde_json::to_string_pretty(&last_known).unwrap_or_default()));
+        if let Err(e) = result {
+            warn!("Failed to save the device list to {}: {}", path.display(), e);
+        }
+    }
+}
+
+// Replays the devices from a restored snapshot, frozen at their snapshot
+// levels, next to whatever this machine finds itself
+pub struct SnapshotScanner {
+    devices: Vec<DeviceStatus>,
+}
+
+impl SnapshotScanner {
+    pub fn new(devices: Vec<DeviceStatus>) -> Self {
+        Self { devices }
+    }
+}
+
+impl DeviceScanner for SnapshotScanner {
+    fn name(&self) -> &'static str {
+        "snapshot"
+    }
+
+    fn scan(&mut self) -> LocalBoxFuture<'_, Result<Vec<DeviceStatus>, Box<dyn std::error::Error>>> {
+        Box::pin(async move { Ok(self.devices.clone()) })
+    }
+}
+
+// Alert suppression carried from one `--oneshot` run to the next, so a cron
+// job doesn't repeat an alert every time it runs
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedAlerts {
+    // Unix timestamp (seconds)
+    pub timestamp: u64,
+    pub state: NotificationState,
+}
+
+impl SavedAlerts {
+    pub fn path() -> PathBuf {
+        data_dir().join("oneshot-alerts.json")
+    }
+
+    // The saved state with its alert ages brought up to now, and the current
+    // mute list rather than the one saved with it
+    pub fn load() -> NotificationState {
+        let saved: SavedAlerts = std::fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        let elapsed = unix_now().saturating_sub(saved.timestamp);
+        let mut state = saved.state;
+        for alert in &mut state.last_sent {
+            alert.seconds_ago = alert.seconds_ago.saturating_add(elapsed);
+        }
+        state
+    }
+
+    pub fn save(state: NotificationState) {
+        let saved = SavedAlerts {
+            timestamp: unix_now(),
+            state,
+        };
+        let path = Self::path();
+        let result = std::fs::create_dir_all(data_dir())
+            .and_then(|_| write_atomic(&path, &serde_json::to_string_pretty(&saved).unwrap_or_default()));
+        if let Err(e) = result {
+            warn!("Failed to save the alert state to {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
@@ -0,0 +1,267 @@
+use crate::bluetooth::{BatteryComponent, BluetoothManager};
+use crate::history::{self, BatteryHistory, HealthEstimate};
+use crate::i18n::tr;
+use crate::keyboard::KeyboardManager;
+use crate::scanner::ScannerRegistry;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    Bluetooth,
+    Usb,
+}
+
+impl Transport {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Transport::Bluetooth => "bluetooth",
+            Transport::Usb => "usb",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeviceStatus {
+    pub id: String,
+    pub name: String,
+    pub device_type: String,
+    pub transport: Transport,
+    // Other transports the same device was seen through, e.g. a Bluetooth
+    // headset that is also plugged in over USB to charge
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_transports: Vec<Transport>,
+    pub battery_percentage: Option<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub batteries: Vec<BatteryComponent>,
+    // Received signal strength in dBm, for Bluetooth devices BlueZ reports it for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rssi: Option<i16>,
+    pub icon: String,
+    // Remembered from the last run and not seen since
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+}
+
+impl DeviceStatus {
+    pub fn transports(&self) -> impl Iterator<Item = Transport> + '_ {
+        std::iter::once(self.transport).chain(self.extra_transports.iter().copied())
+    }
+
+    // Keep this device's own readings and fill any gaps from the duplicate
+    fn absorb(&mut self, other: DeviceStatus) {
+        for transport in other.transports() {
+            if !self.transports().any(|t| t == transport) {
+                self.extra_transports.push(transport);
+            }
+        }
+        if self.battery_percentage.is_none() {
+            self.battery_percentage = other.battery_percentage;
+        }
+        if self.batteries.is_empty() {
+            self.batteries = other.batteries;
+        }
+        if self.rssi.is_none() {
+            self.rssi = other.rssi;
+        }
+    }
+
+    // Rough battery wear from the recorded history, if there's enough of it
+    pub fn health_estimate(&self) -> Option<HealthEstimate> {
+        let samples = BatteryHistory::read(0, Some(&self.id)).ok()?;
+        history::health_estimate(&samples, history::now())
+    }
+
+    // Whether any of its batteries reports charging
+    pub fn is_charging(&self) -> bool {
+        self.batteries.iter().any(|b| b.charging)
+    }
+
+    pub fn format_line(&self) -> String {
+        let mut line = match self.battery_percentage {
+            Some(battery) => format!("{} {}: {}%", self.icon, self.name, battery),
+            None => format!("{} {}", self.icon, self.name),
+        };
+        if let Some(rssi) = self.rssi {
+            line = format!("{} {}", line, signal_bars(rssi));
+        }
+        if self.stale {
+            line = format!("{} {}", line, tr("device-stale"));
+        }
+
+        if self.batteries.is_empty() {
+            return line;
+        }
+
+        let components = self.batteries
+            .iter()
+            .map(|b| format!("{} {}%{}", b.label, b.level, if b.charging { " ⚡" } else { "" }))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{} ({})", line, components)
+    }
+}
+
+// A four-step signal meter, so a weak link reads differently from a flat battery
+pub fn signal_bars(rssi: i16) -> &'static str {
+    match rssi {
+        -60.. => "▂▄▆█",
+        -70..=-61 => "▂▄▆_",
+        -80..=-71 => "▂▄__",
+        _ => "▂___",
+    }
+}
+
+// A battery gauge `width` cells wide, rounded up so any charge shows
+pub fn level_bar(battery: u8, width: usize) -> String {
+    let filled = (battery.min(100) as usize * width).div_ceil(100);
+    format!("{}{}", "█".repeat(filled), "░".repeat(width - filled))
+}
+
+// Match a device by its id or, case-insensitively, by its name
+pub fn find_device<'a>(devices: &'a [DeviceStatus], query: &str) -> Option<&'a DeviceStatus> {
+    devices
+        .iter()
+        .find(|d| d.id == query)
+        .or_else(|| devices.iter().find(|d| d.name.eq_ignore_ascii_case(query)))
+}
+
+pub fn collect_device_status(
+    bt_manager: &BluetoothManager,
+    kb_manager: &KeyboardManager,
+    scanners: &ScannerRegistry,
+) -> Vec<DeviceStatus> {
+    let mut devices = manager_devices(bt_manager, kb_manager);
+    devices.extend(scanners.devices().cloned());
+
+    let mut devices = merge_duplicates(devices);
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
+    devices
+}
+
+// Keyboards and Bluetooth devices, before anything from the scanners
+fn manager_devices(bt_manager: &BluetoothManager, kb_manager: &KeyboardManager) -> Vec<DeviceStatus> {
+    let mut devices = Vec::new();
+
+    for (key, keyboard) in &kb_manager.connected_keyboards {
+        devices.push(DeviceStatus {
+            id: key.clone(),
+            name: keyboard.display_name().to_string(),
+            device_type: format!("{:?}", keyboard.keyboard_type),
+            transport: Transport::Usb,
+            extra_transports: Vec::new(),
+            battery_percentage: keyboard.battery_percentage,
+            batteries: Vec::new(),
+            rssi: None,
+            icon: keyboard.get_icon().to_string(),
+            stale: false,
+        });
+    }
+
+    for device in bt_manager.connected_devices.values() {
+        devices.push(DeviceStatus {
+            id: device.address.to_string(),
+            name: device.display_name().to_string(),
+            device_type: format!("{:?}", device.device_type),
+            transport: Transport::Bluetooth,
+            extra_transports: Vec::new(),
+            battery_percentage: device.battery_percentage,
+            batteries: device.batteries.clone(),
+            rssi: device.rssi,
+            icon: device.get_icon().to_string(),
+            stale: false,
+        });
+    }
+
+    devices
+}
+
+// The same physical device can show up more than once, e.g. a Bluetooth mouse
+// that also has a kernel power_supply entry, or a headset connected over
+// Bluetooth while charging over USB. Fold those into the first one seen.
+pub fn merge_duplicates(devices: Vec<DeviceStatus>) -> Vec<DeviceStatus> {
+    let mut merged: Vec<DeviceStatus> = Vec::new();
+
+    for device in devices {
+        match merged.iter_mut().find(|existing| is_same_device(existing, &device)) {
+            Some(existing) => existing.absorb(device),
+            None => merged.push(device),
+        }
+    }
+
+    merged
+}
+
+// Same MAC or other id, or the same name seen through different transports
+fn is_same_device(a: &DeviceStatus, b: &DeviceStatus) -> bool {
+    if a.id.eq_ignore_ascii_case(&b.id) {
+        return true;
+    }
+
+    let name = normalized_name(&a.name);
+    !name.is_empty() && name == normalized_name(&b.name) && !a.transports().any(|t| t == b.transport)
+}
+
+// Lower-cased alphanumerics without the "LE-" prefix BlueZ gives some BLE
+// devices, so "LE-WH-1000XM4" and "WH-1000XM4" compare equal
+fn normalized_name(name: &str) -> String {
+    let name = name.strip_prefix("LE-").unwrap_or(name);
+    name.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+// The device list from when the screen locked, to tell what came and went
+// while nobody was looking
+#[derive(Debug, Default)]
+pub struct LockedChanges {
+    before: Option<Vec<DeviceStatus>>,
+}
+
+impl LockedChanges {
+    pub fn is_locked(&self) -> bool {
+        self.before.is_some()
+    }
+
+    pub fn lock(&mut self, devices: Vec<DeviceStatus>) {
+        self.before.get_or_insert(devices);
+    }
+
+    // Names of the devices that connected and disconnected since the lock,
+    // or None if it wasn't locked
+    pub fn unlock(&mut self, devices: &[DeviceStatus]) -> Option<(Vec<String>, Vec<String>)> {
+        let before = self.before.take()?;
+        let connected = devices
+            .iter()
+            .filter(|device| !before.iter().any(|b| b.id == device.id))
+            .map(|device| device.name.clone())
+            .collect();
+        let disconnected = before
+            .iter()
+            .filter(|b| !devices.iter().any(|device| device.id == b.id))
+            .map(|b| b.name.clone())
+            .collect();
+        Some((connected, disconnected))
+    }
+}
+
+pub fn combined_status_text(bt_manager: &BluetoothManager, kb_manager: &KeyboardManager, scanners: &ScannerRegistry) -> String {
+    let bt_status = bt_manager.get_status_text();
+    let kb_status = kb_manager.get_status_text();
+
+    let status = if bt_status.contains("No Bluetooth") && kb_status.contains("No keyboards") {
+        "No devices connected".to_string()
+    } else if bt_status.contains("No Bluetooth") {
+        kb_status
+    } else if kb_status.contains("No keyboards") {
+        bt_status
+    } else {
+        format!("{} | {}", kb_status, bt_status)
+    };
+
+    // Leave out scanner devices that are the same as one already listed
+    let tracked = manager_devices(bt_manager, kb_manager);
+    match scanners.get_status_text(|device| tracked.iter().any(|t| is_same_device(t, device))) {
+        Some(scanned) if status == "No devices connected" => scanned,
+        Some(scanned) => format!("{} | {}", status, scanned),
+        None => status,
+    }
+}
@@ -0,0 +1,52 @@
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+use tracing::warn;
+
+// Send a state change to systemd when running as a Type=notify service;
+// does nothing when NOTIFY_SOCKET isn't set
+pub fn notify(state: &str) {
+    let Some(socket_path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        let bytes = socket_path.as_encoded_bytes();
+        // A leading @ means an abstract socket, which std only reaches via the Linux address API
+        if let Some(name) = bytes.strip_prefix(b"@") {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)
+        } else {
+            socket.send_to(state.as_bytes(), &socket_path)
+        }
+    });
+
+    if let Err(e) = result {
+        warn!("Failed to notify systemd ({}): {}", state.trim(), e);
+    }
+}
+
+pub fn notify_ready() {
+    notify("READY=1");
+}
+
+pub fn notify_stopping() {
+    notify("STOPPING=1");
+}
+
+pub fn notify_watchdog() {
+    notify("WATCHDOG=1");
+}
+
+// How often to ping the watchdog: half of WatchdogSec, as systemd recommends
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+
+    // The watchdog settings are meant for the main service process only
+    let pid = std::env::var("WATCHDOG_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+    if pid.is_some_and(|pid| pid != std::process::id()) {
+        return None;
+    }
+
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
@@ -0,0 +1,74 @@
+use crate::status::DeviceStatus;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    // Dropped to or below the threshold
+    Down,
+    // Charged back above it
+    Up,
+}
+
+impl Direction {
+    pub fn key(&self) -> &'static str {
+        match self {
+            Direction::Down => "down",
+            Direction::Up => "up",
+        }
+    }
+}
+
+// A device's battery moving across one of the configured levels
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ThresholdCrossed {
+    pub device_id: String,
+    pub device_name: String,
+    pub threshold: u8,
+    pub direction: Direction,
+    // The level that crossed it
+    pub battery: u8,
+}
+
+// Turns successive device lists into threshold crossings, so alerts fire on
+// the way through a boundary rather than on every reading below it
+#[derive(Debug, Default)]
+pub struct ThresholdTracker {
+    // Last level seen per device. Kept while a device is away, so one that
+    // comes back charged is seen to have gone back up.
+    last_levels: HashMap<String, u8>,
+}
+
+impl ThresholdTracker {
+    // The crossings since the last update, at most one per device: the lowest
+    // threshold passed on the way down or the highest on the way up. A device
+    // seen for the first time counts as coming from full, so one that
+    // connects already low still alerts.
+    pub fn update(&mut self, devices: &[DeviceStatus], thresholds: &[u8]) -> Vec<ThresholdCrossed> {
+        let mut crossings = Vec::new();
+        for device in devices {
+            let Some(battery) = device.battery_percentage else { continue };
+            let previous = self.last_levels.insert(device.id.clone(), battery).unwrap_or(u8::MAX);
+
+            let crossing = if battery < previous {
+                let passed = thresholds.iter().copied().filter(|t| battery <= *t && *t < previous);
+                passed.min().map(|threshold| (threshold, Direction::Down))
+            } else {
+                let passed = thresholds.iter().copied().filter(|t| previous <= *t && *t < battery);
+                passed.max().map(|threshold| (threshold, Direction::Up))
+            };
+
+            if let Some((threshold, direction)) = crossing {
+                crossings.push(ThresholdCrossed {
+                    device_id: device.id.clone(),
+                    device_name: device.name.clone(),
+                    threshold,
+                    direction,
+                    battery,
+                });
+            }
+        }
+        crossings
+    }
+}
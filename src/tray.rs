@@ -0,0 +1,374 @@
+use crate::i18n::{tr, tr_args};
+use crate::icons::{self, IconMap};
+use crate::status::{level_bar, DeviceStatus, Transport};
+use ksni::menu::{StandardItem, SubMenu};
+use ksni::{MenuItem, TrayMethods};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+// Width of the battery bar after each device in the menu, in cells
+const MENU_BAR_WIDTH: usize = 5;
+// Levels the tray icon shows as an empty and a full battery
+const EMPTY_LEVEL: u8 = 2;
+const FULL_LEVEL: u8 = 90;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TrayAction {
+    ShowDetails,
+    SnoozeDevice(String),
+    MuteDevice(String),
+    UnmuteDevice(String),
+    // Bluetooth devices, by MAC address
+    ConnectDevice(String),
+    DisconnectDevice(String),
+    ForgetDevice(String),
+    Quit,
+}
+
+pub struct BatteryTray {
+    devices: Vec<DeviceStatus>,
+    // Upper-cased ids from Config::tray_pins; empty shows every device
+    pinned: Vec<String>,
+    // Configured icons over the built-in ones
+    icons: IconMap,
+    // Paired Bluetooth devices that aren't connected, as (MAC address, name)
+    paired: Vec<(String, String)>,
+    // False while there's no Bluetooth adapter to monitor through
+    bluetooth_available: bool,
+    // Scanners that keep failing, shown as a warning
+    failing_scanners: Vec<String>,
+    // Alert thresholds, which pick the icon's low and caution states
+    low_threshold: u8,
+    critical_threshold: u8,
+    actions: UnboundedSender<TrayAction>,
+}
+
+pub type TrayHandle = ksni::Handle<BatteryTray>;
+
+impl BatteryTray {
+    pub async fn start(
+        pinned: Vec<String>,
+        icons: IconMap,
+        (low_threshold, critical_threshold): (u8, u8),
+    ) -> Result<(TrayHandle, UnboundedReceiver<TrayAction>), ksni::Error> {
+        let (actions, receiver) = unbounded_channel();
+        let tray = BatteryTray {
+            devices: Vec::new(),
+            pinned,
+            icons,
+            paired: Vec::new(),
+            bluetooth_available: true,
+            failing_scanners: Vec::new(),
+            low_threshold,
+            critical_threshold,
+            actions,
+        };
+
+        let handle = tray.spawn().await?;
+        Ok((handle, receiver))
+    }
+
+    pub async fn update_devices(handle: &TrayHandle, devices: Vec<DeviceStatus>) {
+        handle.update(move |tray: &mut BatteryTray| tray.devices = tray.pinned_devices(devices)).await;
+    }
+
+    // Swap in a new pin list after a config reload; applies from the next update
+    pub async fn set_pins(handle: &TrayHandle, pinned: Vec<String>) {
+        handle.update(move |tray: &mut BatteryTray| tray.pinned = pinned).await;
+    }
+
+    pub async fn set_icons(handle: &TrayHandle, icons: IconMap) {
+        handle.update(move |tray: &mut BatteryTray| tray.icons = icons).await;
+    }
+
+    pub async fn set_paired_devices(handle: &TrayHandle, paired: Vec<(String, String)>) {
+        handle.update(move |tray: &mut BatteryTray| tray.paired = paired).await;
+    }
+
+    pub async fn set_bluetooth_available(handle: &TrayHandle, available: bool) {
+        handle.update(move |tray: &mut BatteryTray| tray.bluetooth_available = available).await;
+    }
+
+    pub async fn set_thresholds(handle: &TrayHandle, low_threshold: u8, critical_threshold: u8) {
+        handle
+            .update(move |tray: &mut BatteryTray| {
+                tray.low_threshold = low_threshold;
+                tray.critical_threshold = critical_threshold;
+            })
+            .await;
+    }
+
+    pub async fn set_failing_scanners(handle: &TrayHandle, failing: Vec<String>) {
+        handle.update(move |tray: &mut BatteryTray| tray.failing_scanners = failing).await;
+    }
+
+    fn health_warning(&self) -> Option<String> {
+        (!self.failing_scanners.is_empty())
+            .then(|| tr_args("scanners-failing", &[("scanners", self.failing_scanners.join(", ").into())]))
+    }
+
+    // Only the pinned devices, in the configured order
+    fn pinned_devices(&self, devices: Vec<DeviceStatus>) -> Vec<DeviceStatus> {
+        if self.pinned.is_empty() {
+            return devices;
+        }
+
+        let mut pinned: Vec<(usize, DeviceStatus)> = devices
+            .into_iter()
+            .filter_map(|device| self.pin_position(&device.id).map(|position| (position, device)))
+            .collect();
+        pinned.sort_by_key(|(position, _)| *position);
+        pinned.into_iter().map(|(_, device)| device).collect()
+    }
+
+    // Keyboards are configured by vendor:product id but their device id
+    // may carry a serial number or #n suffix after it, so a prefix match counts too
+    fn pin_position(&self, device_id: &str) -> Option<usize> {
+        let device_id = device_id.to_uppercase();
+        self.pinned.iter().position(|pin| {
+            device_id == *pin || device_id.starts_with(&format!("{}:", pin)) || device_id.starts_with(&format!("{}#", pin))
+        })
+    }
+
+    fn lowest_battery(&self) -> Option<u8> {
+        self.lowest_device().and_then(|d| d.battery_percentage)
+    }
+
+    // The device the tray icon represents
+    fn lowest_device(&self) -> Option<&DeviceStatus> {
+        self.devices
+            .iter()
+            .filter(|d| d.battery_percentage.is_some())
+            .min_by_key(|d| d.battery_percentage)
+    }
+
+    fn send_action(&self, action: TrayAction) {
+        let _ = self.actions.send(action);
+    }
+}
+
+impl ksni::Tray for BatteryTray {
+    fn id(&self) -> String {
+        env!("CARGO_PKG_NAME").into()
+    }
+
+    fn title(&self) -> String {
+        match self.lowest_battery() {
+            Some(battery) => tr_args("tray-title-level", &[("level", battery.into())]),
+            None => tr("app-name"),
+        }
+    }
+
+    // The lowest device's level as one of the icon theme's battery states,
+    // which every theme has, unlike the finer 10% gauges
+    fn icon_name(&self) -> String {
+        let Some(device) = self.lowest_device() else {
+            return if self.bluetooth_available {
+                "battery-missing-symbolic".into()
+            } else {
+                "bluetooth-disabled-symbolic".into()
+            };
+        };
+
+        let level = device.battery_percentage.unwrap_or(0);
+        let state = match level {
+            ..=EMPTY_LEVEL => "empty",
+            _ if level <= self.critical_threshold => "caution",
+            _ if level <= self.low_threshold => "low",
+            _ if level < FULL_LEVEL => "good",
+            _ => "full",
+        };
+        match (state, device.is_charging()) {
+            ("full", true) => "battery-full-charged-symbolic".into(),
+            (state, true) => format!("battery-{}-charging-symbolic", state),
+            (state, false) => format!("battery-{}-symbolic", state),
+        }
+    }
+
+    // Which kind of device the gauge is for, drawn over its corner
+    fn overlay_icon_name(&self) -> String {
+        let Some(icon) = self.lowest_device().map(|d| self.icons.icon_for(d)) else {
+            return String::new();
+        };
+        match icons::split_icon_path(&icon) {
+            Some((_, name)) => name,
+            None => icon,
+        }
+    }
+
+    // Where a configured icon file is, for the host to find it by name
+    fn icon_theme_path(&self) -> String {
+        self.lowest_device()
+            .and_then(|d| icons::split_icon_path(&self.icons.icon_for(d)))
+            .map(|(dir, _)| dir)
+            .unwrap_or_default()
+    }
+
+    fn tool_tip(&self) -> ksni::ToolTip {
+        let mut description = if self.devices.is_empty() {
+            tr("no-devices")
+        } else {
+            self.devices.iter().map(|d| d.format_line()).collect::<Vec<_>>().join("\n")
+        };
+        if !self.bluetooth_available {
+            description = format!("{}\n{}", tr("bluetooth-unavailable"), description);
+        }
+        if let Some(warning) = self.health_warning() {
+            description = format!("⚠ {}\n{}", warning, description);
+        }
+
+        ksni::ToolTip {
+            title: tr("app-name"),
+            description,
+            ..Default::default()
+        }
+    }
+
+    fn activate(&mut self, _x: i32, _y: i32) {
+        self.send_action(TrayAction::ShowDetails);
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        let mut items: Vec<MenuItem<Self>> = Vec::new();
+
+        if !self.bluetooth_available {
+            items.push(
+                StandardItem {
+                    label: tr("bluetooth-unavailable"),
+                    icon_name: "bluetooth-disabled-symbolic".into(),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+
+        if let Some(warning) = self.health_warning() {
+            items.push(
+                StandardItem {
+                    label: warning,
+                    icon_name: "dialog-warning-symbolic".into(),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+
+        if self.devices.is_empty() {
+            items.push(
+                StandardItem {
+                    label: tr("no-devices"),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+
+        for device in &self.devices {
+            let (label, icon_name) = match device.battery_percentage {
+                Some(level) => (
+                    format!("{}  {}", device.format_line(), level_bar(level, MENU_BAR_WIDTH)),
+                    level_icon_name(level, device.is_charging()),
+                ),
+                None => (device.format_line(), String::new()),
+            };
+            items.push(
+                SubMenu {
+                    label,
+                    icon_name,
+                    submenu: device_menu(device),
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+
+        if !self.paired.is_empty() {
+            let submenu = self
+                .paired
+                .iter()
+                .map(|(address, name)| {
+                    let address = address.clone();
+                    StandardItem {
+                        label: name.clone(),
+                        activate: Box::new(move |this: &mut Self| {
+                            this.send_action(TrayAction::ConnectDevice(address.clone()))
+                        }),
+                        ..Default::default()
+                    }
+                    .into()
+                })
+                .collect();
+            items.push(MenuItem::Separator);
+            items.push(
+                SubMenu {
+                    label: tr("menu-connect"),
+                    icon_name: "bluetooth-active-symbolic".into(),
+                    submenu,
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+
+        items.push(MenuItem::Separator);
+        items.push(
+            StandardItem {
+                label: tr("menu-details"),
+                icon_name: "dialog-information".into(),
+                activate: Box::new(|this: &mut Self| this.send_action(TrayAction::ShowDetails)),
+                ..Default::default()
+            }
+            .into(),
+        );
+        items.push(
+            StandardItem {
+                label: tr("menu-quit"),
+                icon_name: "application-exit".into(),
+                activate: Box::new(|this: &mut Self| this.send_action(TrayAction::Quit)),
+                ..Default::default()
+            }
+            .into(),
+        );
+
+        items
+    }
+}
+
+// The icon theme's battery gauge for a level, in its 10% steps
+fn level_icon_name(level: u8, charging: bool) -> String {
+    let rounded = (u16::from(level.min(100)) + 5) / 10 * 10;
+    match (rounded, charging) {
+        (100, true) => "battery-level-100-charged-symbolic".into(),
+        (_, true) => format!("battery-level-{}-charging-symbolic", rounded),
+        (_, false) => format!("battery-level-{}-symbolic", rounded),
+    }
+}
+
+type DeviceActionFn = fn(String) -> TrayAction;
+
+fn device_menu(device: &DeviceStatus) -> Vec<MenuItem<BatteryTray>> {
+    let mut entries: Vec<(&str, DeviceActionFn)> = vec![
+        ("menu-snooze", TrayAction::SnoozeDevice),
+        ("menu-mute", TrayAction::MuteDevice),
+        ("menu-unmute", TrayAction::UnmuteDevice),
+    ];
+    if device.transport == Transport::Bluetooth {
+        entries.push(("menu-disconnect", TrayAction::DisconnectDevice));
+        entries.push(("menu-forget", TrayAction::ForgetDevice));
+    }
+
+    entries
+        .into_iter()
+        .map(|(label, action)| {
+            let device_id = device.id.clone();
+            StandardItem {
+                label: tr(label),
+                activate: Box::new(move |this: &mut BatteryTray| this.send_action(action(device_id.clone()))),
+                ..Default::default()
+            }
+            .into()
+        })
+        .collect()
+}
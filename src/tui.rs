@@ -0,0 +1,276 @@
+use crate::history::{self, BatteryHistory};
+use crate::logging;
+use crate::output::battery_class;
+use crate::status::{level_bar, DeviceStatus};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+// Width of the battery bar in cells
+const BAR_WIDTH: usize = 10;
+// Readings shown in each device's sparkline, newest on the right
+const SPARKLINE_POINTS: usize = 30;
+// How far back the sparklines start from the history file
+const HISTORY_WINDOW_SECS: u64 = 24 * 60 * 60;
+const SPARK_CHARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SortKey {
+    Battery,
+    Name,
+    Type,
+}
+
+impl SortKey {
+    fn next(self) -> Self {
+        match self {
+            SortKey::Battery => SortKey::Name,
+            SortKey::Name => SortKey::Type,
+            SortKey::Type => SortKey::Battery,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SortKey::Battery => "battery",
+            SortKey::Name => "name",
+            SortKey::Type => "type",
+        }
+    }
+}
+
+struct Watch {
+    devices: Vec<DeviceStatus>,
+    // Recent levels per device id, oldest first
+    levels: HashMap<String, Vec<u8>>,
+    sort: SortKey,
+    reversed: bool,
+    // Why the last refresh failed, until one succeeds
+    error: Option<String>,
+    low_threshold: u8,
+    // Only devices whose name or type fuzzily matches this are listed
+    filter: String,
+    // Keys go to the filter while it's being typed
+    editing_filter: bool,
+}
+
+impl Watch {
+    fn new(low_threshold: u8) -> Self {
+        // Start the sparklines from the recorded history, when there is one
+        let since = history::now().saturating_sub(HISTORY_WINDOW_SECS);
+        let mut levels: HashMap<String, Vec<u8>> = HashMap::new();
+        for sample in BatteryHistory::read(since, None).unwrap_or_default() {
+            push_level(levels.entry(sample.device_id).or_default(), sample.level);
+        }
+
+        Self {
+            devices: Vec::new(),
+            levels,
+            sort: SortKey::Battery,
+            reversed: false,
+            error: None,
+            low_threshold,
+            filter: String::new(),
+            editing_filter: false,
+        }
+    }
+
+    fn update(&mut self, devices: Vec<DeviceStatus>) {
+        for device in &devices {
+            if let Some(battery) = device.battery_percentage {
+                push_level(self.levels.entry(device.id.clone()).or_default(), battery);
+            }
+        }
+        self.devices = devices;
+        self.error = None;
+    }
+
+    fn sorted(&self) -> Vec<&DeviceStatus> {
+        let mut devices: Vec<&DeviceStatus> = self
+            .devices
+            .iter()
+            .filter(|d| fuzzy_match(&self.filter, &d.name) || fuzzy_match(&self.filter, &d.device_type))
+            .collect();
+        match self.sort {
+            // Lowest first, with devices that don't report a level last
+            SortKey::Battery => devices.sort_by_key(|d| d.battery_percentage.unwrap_or(u8::MAX)),
+            SortKey::Name => devices.sort_by_key(|d| d.name.to_lowercase()),
+            SortKey::Type => devices.sort_by(|a, b| a.device_type.cmp(&b.device_type).then(a.name.cmp(&b.name))),
+        }
+        if self.reversed {
+            devices.reverse();
+        }
+        devices
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [table_area, footer_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+
+        let rows = self.sorted().into_iter().map(|device| {
+            let battery = match device.battery_percentage {
+                Some(battery) => {
+                    let charging = if device.is_charging() { " ⚡" } else { "" };
+                    Cell::from(format!("{} {:>3}%{}", level_bar(battery, BAR_WIDTH), battery, charging))
+                        .style(level_style(battery, self.low_threshold))
+                }
+                None => Cell::from("?"),
+            };
+            let levels = self.levels.get(&device.id).map(Vec::as_slice).unwrap_or_default();
+            Row::new(vec![
+                Cell::from(format!("{} {}", device.icon, device.name)),
+                Cell::from(device.device_type.clone()),
+                battery,
+                Cell::from(sparkline(levels)).dim(),
+            ])
+        });
+        let widths = [
+            Constraint::Fill(1),
+            Constraint::Length(12),
+            Constraint::Length(BAR_WIDTH as u16 + 8),
+            Constraint::Length(SPARKLINE_POINTS as u16),
+        ];
+        let table = Table::new(rows, widths)
+            .header(Row::new(["Device", "Type", "Battery", "History"]).bold())
+            .block(Block::bordered().title(" Battery Monitor "));
+        frame.render_widget(table, table_area);
+
+        let footer = match &self.error {
+            Some(e) => Line::from(e.as_str()).red(),
+            None if self.editing_filter => Line::from(format!("/{}█  enter keep  esc clear", self.filter)),
+            None if !self.filter.is_empty() => Line::from(format!(
+                "filter: {}  / edit  esc clear  s sort by {}  r reverse  ^R refresh",
+                self.filter,
+                self.sort.label()
+            ))
+            .dim(),
+            None => Line::from(format!("q quit  / filter  s sort by {}  r reverse  ^R refresh", self.sort.label())).dim(),
+        };
+        frame.render_widget(Paragraph::new(footer), footer_area);
+    }
+}
+
+// Full-screen live device table, refreshed every `interval` with `fetch`
+pub async fn run<F>(low_threshold: u8, interval: Duration, fetch: F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: AsyncFnMut() -> Result<Vec<DeviceStatus>, Box<dyn std::error::Error>>,
+{
+    // crossterm's reads block, so they get a thread of their own
+    let (sender, mut events) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if sender.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    logging::mute_stderr(true);
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, Watch::new(low_threshold), interval, fetch, &mut events).await;
+    ratatui::restore();
+    logging::mute_stderr(false);
+    result
+}
+
+async fn event_loop<F>(
+    terminal: &mut DefaultTerminal,
+    mut watch: Watch,
+    interval: Duration,
+    mut fetch: F,
+    events: &mut mpsc::UnboundedReceiver<Event>,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: AsyncFnMut() -> Result<Vec<DeviceStatus>, Box<dyn std::error::Error>>,
+{
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        terminal.draw(|frame| watch.draw(frame))?;
+
+        tokio::select! {
+            _ = ticker.tick() => match fetch().await {
+                Ok(devices) => watch.update(devices),
+                Err(e) => watch.error = Some(format!("Failed to read device status: {}", e)),
+            },
+            Some(event) = events.recv() => {
+                let Event::Key(key) = event else { continue };
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                let control = key.modifiers.contains(KeyModifiers::CONTROL);
+                if watch.editing_filter {
+                    match key.code {
+                        KeyCode::Enter => watch.editing_filter = false,
+                        KeyCode::Esc => {
+                            watch.filter.clear();
+                            watch.editing_filter = false;
+                        }
+                        KeyCode::Backspace => {
+                            watch.filter.pop();
+                        }
+                        KeyCode::Char('c') if control => return Ok(()),
+                        KeyCode::Char(c) if !control => watch.filter.push(c),
+                        _ => {}
+                    }
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('/') => watch.editing_filter = true,
+                    KeyCode::Char('f') if control => watch.editing_filter = true,
+                    KeyCode::Esc if !watch.filter.is_empty() => watch.filter.clear(),
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    // Raw mode turns Ctrl+C into a key press
+                    KeyCode::Char('c') if control => return Ok(()),
+                    // Fetch now instead of waiting for the next tick
+                    KeyCode::Char('r') if control => ticker.reset_immediately(),
+                    KeyCode::F(5) => ticker.reset_immediately(),
+                    KeyCode::Char('s') => watch.sort = watch.sort.next(),
+                    KeyCode::Char('r') => watch.reversed = !watch.reversed,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+// Keep a level if it differs from the last one, dropping the oldest past the sparkline width
+fn push_level(levels: &mut Vec<u8>, level: u8) {
+    if levels.last() == Some(&level) {
+        return;
+    }
+    levels.push(level);
+    if levels.len() > SPARKLINE_POINTS {
+        levels.remove(0);
+    }
+}
+
+// Whether every character of `pattern` appears in `text` in order, ignoring
+// case, so "wh1000" finds "WH-1000XM4"
+fn fuzzy_match(pattern: &str, text: &str) -> bool {
+    let mut text = text.chars().flat_map(char::to_lowercase);
+    pattern
+        .chars()
+        .flat_map(char::to_lowercase)
+        .filter(|c| !c.is_whitespace())
+        .all(|p| text.any(|t| t == p))
+}
+
+fn sparkline(levels: &[u8]) -> String {
+    levels
+        .iter()
+        .map(|&level| SPARK_CHARS[(level.min(100) as usize * (SPARK_CHARS.len() - 1)) / 100])
+        .collect()
+}
+
+fn level_style(battery: u8, low_threshold: u8) -> Style {
+    match battery_class(battery, low_threshold) {
+        "low" => Style::new().fg(Color::Red),
+        "medium" => Style::new().fg(Color::Yellow),
+        _ => Style::new().fg(Color::Green),
+    }
+}
@@ -0,0 +1,159 @@
+// Runs the Bluetooth scanning code against src/bin/fake_bluez.rs on a private
+// bus, the same setup scripts/fake-bluez.sh uses. Needs dbus-daemon, so it's
+// ignored by default: run it with `cargo test -- --ignored`.
+//
+// The bus address only reaches bluer through DBUS_SYSTEM_BUS_ADDRESS, so the
+// test starts the bus, then runs itself again in a child process with the
+// address in that process's environment.
+
+use battery_percentage::bluetooth::{BluetoothDevice, BluetoothManager};
+use bluer::{Adapter, Address};
+use std::io::Write;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::time::Duration;
+
+const TEST_NAME: &str = "scans_fake_bluez_devices_and_follows_changes";
+// Set in the child process that talks to fake BlueZ
+const CHILD_ENV: &str = "FAKE_BLUEZ_TEST_CHILD";
+const DEVICES_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/scripts/fake-bluez-devices.toml");
+const HEADPHONES: &str = "AC:80:0A:12:34:56";
+const MOUSE: &str = "F0:11:22:33:44:55";
+const KEYBOARD: &str = "F0:11:22:33:44:66";
+// How long fake BlueZ gets to show up on the bus, and a change to arrive
+const WAIT: Duration = Duration::from_secs(5);
+const POLL: Duration = Duration::from_millis(100);
+
+// A throwaway bus, killed when the test ends however it ends
+struct PrivateBus {
+    address: String,
+    pid: String,
+}
+
+impl PrivateBus {
+    fn start() -> Self {
+        let output = Command::new("dbus-daemon")
+            .args(["--session", "--fork", "--print-address=1", "--print-pid=1"])
+            .output()
+            .unwrap_or_else(|e| panic!("this test needs dbus-daemon, which didn't start: {}", e));
+        assert!(output.status.success(), "dbus-daemon failed: {}", String::from_utf8_lossy(&output.stderr));
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        let mut lines = stdout.lines().map(str::to_string);
+        let (Some(address), Some(pid)) = (lines.next(), lines.next()) else {
+            panic!("dbus-daemon didn't print its address and pid: {:?}", stdout);
+        };
+        Self { address, pid }
+    }
+}
+
+impl Drop for PrivateBus {
+    fn drop(&mut self) {
+        let _ = Command::new("kill").arg(&self.pid).status();
+    }
+}
+
+// Fake BlueZ on the bus the child process was given, killed with it
+struct FakeBluez {
+    server: Child,
+    commands: ChildStdin,
+}
+
+impl FakeBluez {
+    fn start() -> Self {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_fake_bluez"))
+            .arg(DEVICES_FILE)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("fake_bluez starts");
+        let commands = server.stdin.take().expect("fake_bluez stdin is piped");
+        Self { server, commands }
+    }
+
+    fn send(&mut self, command: &str) {
+        writeln!(self.commands, "{}", command).expect("fake_bluez reads commands");
+    }
+}
+
+impl Drop for FakeBluez {
+    fn drop(&mut self) {
+        let _ = self.server.kill();
+        let _ = self.server.wait();
+    }
+}
+
+async fn wait_for_adapter() -> Adapter {
+    let deadline = tokio::time::Instant::now() + WAIT;
+    loop {
+        if let Ok(session) = bluer::Session::new().await
+            && let Ok(adapter) = session.default_adapter().await
+            && adapter.device_addresses().await.is_ok_and(|addresses| !addresses.is_empty())
+        {
+            return adapter;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "fake BlueZ didn't show up on the bus");
+        tokio::time::sleep(POLL).await;
+    }
+}
+
+// What the monitor does at startup: every connected device BlueZ knows
+async fn scan(adapter: &Adapter) -> BluetoothManager {
+    let mut bt_manager = BluetoothManager::new();
+    for addr in adapter.device_addresses().await.expect("devices are listed") {
+        let device = adapter.device(addr).expect("device is reachable");
+        if let Some(bt_device) = BluetoothDevice::from_device(device, addr).await.expect("device reads") {
+            bt_manager.add_device(bt_device);
+        }
+    }
+    bt_manager
+}
+
+fn battery(bt_manager: &BluetoothManager, address: &str) -> Option<u8> {
+    let addr: Address = address.parse().expect("valid address");
+    bt_manager.connected_devices.get(&addr).and_then(|device| device.battery_percentage)
+}
+
+#[test]
+#[ignore = "needs dbus-daemon"]
+fn scans_fake_bluez_devices_and_follows_changes() {
+    if std::env::var_os(CHILD_ENV).is_some() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().expect("runtime starts");
+        runtime.block_on(scan_and_follow_changes());
+        return;
+    }
+
+    // Both fake BlueZ and the scanning code treat the private bus as the system bus
+    let bus = PrivateBus::start();
+    let status = Command::new(std::env::current_exe().expect("test binary path"))
+        .args([TEST_NAME, "--exact", "--ignored", "--nocapture", "--test-threads=1"])
+        .env("DBUS_SYSTEM_BUS_ADDRESS", &bus.address)
+        .env(CHILD_ENV, "1")
+        .status()
+        .expect("test binary runs again");
+    assert!(status.success(), "the scan against fake BlueZ failed, see the output above");
+}
+
+async fn scan_and_follow_changes() {
+    let mut fake = FakeBluez::start();
+    let adapter = wait_for_adapter().await;
+
+    let mut bt_manager = scan(&adapter).await;
+    assert_eq!(bt_manager.connected_devices.len(), 2, "the disconnected keyboard isn't listed");
+    assert_eq!(battery(&bt_manager, HEADPHONES), Some(75));
+    assert_eq!(battery(&bt_manager, MOUSE), Some(18));
+    let keyboard: Address = KEYBOARD.parse().expect("valid address");
+    assert!(!bt_manager.connected_devices.contains_key(&keyboard));
+
+    // A level change and a disconnect, re-read the way a refresh does
+    fake.send(&format!("battery {} 9", MOUSE));
+    fake.send(&format!("disconnect {}", HEADPHONES));
+    let deadline = tokio::time::Instant::now() + WAIT;
+    loop {
+        bt_manager = scan(&adapter).await;
+        if battery(&bt_manager, MOUSE) == Some(9) && battery(&bt_manager, HEADPHONES).is_none() {
+            break;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "changes didn't arrive: {:?}", bt_manager.connected_devices);
+        tokio::time::sleep(POLL).await;
+    }
+    assert_eq!(bt_manager.connected_devices.len(), 1);
+}